@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+
+use crate::output::CommandOutput;
+use crate::utils::{self, Quoting};
+
+#[derive(Default)]
+pub struct Aliases {
+    definitions: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.definitions.get(name).map(String::as_str)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.definitions.keys().map(String::as_str)
+    }
+
+    // Expands the first word of a simple command, following bash's recursion rules. `active`
+    // holds the aliases whose replacement is being run, which are not expanded again, and gains
+    // the ones expanded here for running the result
+    pub fn expand(&self, command: &str, active: &mut Vec<String>) -> String {
+        let mut expanded = Vec::new();
+        let text = self.expand_first_word(command, active, &mut expanded);
+        active.extend(expanded);
+        text
+    }
+
+    fn expand_first_word(
+        &self,
+        text: &str,
+        active: &mut Vec<String>,
+        expanded: &mut Vec<String>,
+    ) -> String {
+        let word_start = text.len() - text.trim_start().len();
+        // The word ends at a blank or at an operator such as `;` in `e1;`
+        let word_end = text[word_start..]
+            .find(|c: char| c.is_whitespace() || ";&|()<>".contains(c))
+            .map_or(text.len(), |end| word_start + end);
+        let word = &text[word_start..word_end];
+
+        let value = match self.get(word) {
+            Some(value) if !active.iter().any(|name| name == word) => value,
+            _ => return text.to_string(),
+        };
+
+        // An alias is not expanded again while its own replacement is being expanded
+        active.push(word.to_string());
+        let replacement = self.expand_first_word(value, active, &mut Vec::new());
+        active.pop();
+        expanded.push(word.to_string());
+
+        let rest = &text[word_end..];
+        let rest = if replacement.ends_with([' ', '\t']) {
+            self.expand_first_word(rest, active, expanded)
+        } else {
+            rest.to_string()
+        };
+        format!("{}{}{}", &text[..word_start], replacement, rest)
+    }
+
+    pub fn alias(&mut self, args: &[String]) -> CommandOutput {
+        let args: Vec<&String> = args.iter().filter(|arg| *arg != "-p").collect();
+        if args.is_empty() {
+            return CommandOutput::stdout(self.listing());
+        }
+
+        let mut output = CommandOutput::default();
+        for arg in args {
+            match arg.split_once('=') {
+                Some((name, value)) if is_valid_alias_name(name) => {
+                    self.definitions.insert(name.to_string(), value.to_string());
+                }
+                Some((name, _)) => {
+                    output.stderr += &format!("alias: `{}': invalid alias name\n", name);
+                    output.status = 1;
+                }
+                None => match self.get(arg) {
+                    Some(value) => output.push_stdout(&format_definition(arg, value)),
+                    None => {
+                        output.stderr += &format!("alias: {}: not found\n", arg);
+                        output.status = 1;
+                    }
+                },
+            }
+        }
+        output
+    }
+
+    pub fn unalias(&mut self, args: &[String]) -> CommandOutput {
+        if args.is_empty() {
+            return CommandOutput::stderr("unalias: usage: unalias [-a] name [name ...]\n")
+                .with_status(2);
+        }
+        if args.iter().any(|arg| arg == "-a") {
+            self.definitions.clear();
+            return CommandOutput::default();
+        }
+
+        let mut output = CommandOutput::default();
+        for name in args {
+            if self.definitions.remove(name.as_str()).is_none() {
+                output.stderr += &format!("unalias: {}: not found\n", name);
+                output.status = 1;
+            }
+        }
+        output
+    }
+
+    fn listing(&self) -> String {
+        self.definitions
+            .iter()
+            .map(|(name, value)| format_definition(name, value))
+            .collect()
+    }
+}
+
+pub fn format_definition(name: &str, value: &str) -> String {
+    let value = utils::shell_quote(value, Quoting::Always);
+    format!("alias {}={}\n", name, value)
+}
+
+fn is_valid_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || "/$`=|&;()<>'\"\\".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn define(definitions: &[(&str, &str)]) -> Aliases {
+        let mut aliases = Aliases::new();
+        for (name, value) in definitions {
+            aliases.alias(&[format!("{}={}", name, value)]);
+        }
+        aliases
+    }
+
+    fn expand(aliases: &Aliases, command: &str) -> (String, Vec<String>) {
+        let mut active = Vec::new();
+        let expanded = aliases.expand(command, &mut active);
+        (expanded, active)
+    }
+
+    #[test]
+    fn expands_the_first_word() {
+        let aliases = define(&[("ll", "ls -l"), ("e1", "echo one")]);
+        assert_eq!(expand(&aliases, "ll /tmp").0, "ls -l /tmp");
+        assert_eq!(expand(&aliases, "  ll").0, "  ls -l");
+        assert_eq!(expand(&aliases, "echo ll").0, "echo ll");
+        assert_eq!(expand(&aliases, "e1;").0, "echo one;");
+        assert_eq!(expand(&aliases, "e1>out").0, "echo one>out");
+        assert_eq!(expand(&aliases, "e1x").0, "e1x");
+    }
+
+    #[test]
+    fn follows_replacements_without_recursing() {
+        let aliases = define(&[("ls", "ls --color"), ("a", "b"), ("b", "a -x"), ("l", "ls")]);
+        assert_eq!(
+            expand(&aliases, "ls /"),
+            ("ls --color /".to_string(), vec!["ls".to_string()])
+        );
+        assert_eq!(expand(&aliases, "a").0, "a -x");
+        assert_eq!(expand(&aliases, "l").0, "ls --color");
+
+        // A replacement ending in a blank expands the next word too
+        let aliases = define(&[("sudo", "sudo "), ("ll", "ls -l")]);
+        let (expanded, active) = expand(&aliases, "sudo ll");
+        assert_eq!(expanded, "sudo  ls -l");
+        assert_eq!(active, ["sudo", "ll"]);
+    }
+
+    #[test]
+    fn leaves_later_commands_of_a_replacement_for_the_next_pass() {
+        let aliases = define(&[
+            ("e1", "echo one"),
+            ("both", "e1; e1"),
+            ("chain", "e1 && e1"),
+        ]);
+        let (expanded, active) = expand(&aliases, "both");
+        assert_eq!(expanded, "echo one; e1");
+        assert_eq!(active, ["both"]);
+        // The second `e1` is expanded when the list is run, as `both` is the only active alias
+        let mut active = active;
+        assert_eq!(aliases.expand(" e1", &mut active), " echo one");
+        assert_eq!(expand(&aliases, "chain").0, "echo one && e1");
+        let mut active = vec!["e1".to_string()];
+        assert_eq!(aliases.expand("e1", &mut active), "e1");
+    }
+}
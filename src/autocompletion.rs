@@ -1,161 +1,305 @@
-use std::env;
-use std::io::{self, Write};
-use std::os::unix::fs::PermissionsExt;
-use std::sync::Mutex;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
 
 use rustyline::completion::{Completer, Pair};
-use rustyline::highlight::Highlighter;
+use rustyline::highlight::{CmdKind, Highlighter};
 use rustyline::hint::Hinter;
-use rustyline::validate::Validator;
-use rustyline::{Context, Helper, Result as RustylineResult};
+use rustyline::history::SearchDirection;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{
+    Cmd, ConditionalEventHandler, Context, Event, EventContext, Helper, RepeatCount,
+    Result as RustylineResult,
+};
 
-pub struct BuiltInCompleter {
-    paths: Vec<String>,
-    completion_state: Mutex<CompletionState>,
+use crate::alias::Aliases;
+use crate::builtins;
+use crate::command_index::CommandIndex;
+use crate::fuzzy;
+use crate::highlight::{self, CommandKind, Palette};
+use crate::path_completion::{self, PathFilter, WordContext, WordPosition};
+use crate::programmable_completion::{self, CompletionSources, CompletionSpec, CompletionSpecs};
+use crate::tool_completion;
+use crate::utils;
+use crate::validation;
+
+const DIRECTORY_COMMANDS: &[&str] = &["cd", "pushd", "rmdir"];
+
+// Completes the arguments of one command; no candidates falls back to filenames
+pub trait ArgumentCompleter {
+    fn command(&self) -> &'static str;
+    fn candidates(&self, word: &WordContext) -> Vec<String>;
 }
 
-struct CompletionState {
-    last_prefix: String,
-    last_matches: Vec<String>,
+pub struct BuiltInCompleter {
+    commands: Rc<RefCell<CommandIndex>>,
+    aliases: Rc<RefCell<Aliases>>,
+    specs: Rc<RefCell<CompletionSpecs>>,
+    argument_completers: Vec<Box<dyn ArgumentCompleter>>,
+    palette: Option<Palette>,
+    fuzzy: bool,
+    ignore_case: bool,
+    variables: HashMap<String, String>,
 }
 
 impl BuiltInCompleter {
-    pub fn new() -> Self {
-        let paths = env::var("PATH")
-            .unwrap_or_default()
-            .split(':')
-            .map(String::from)
-            .collect();
-            
+    pub fn new(
+        aliases: Rc<RefCell<Aliases>>,
+        commands: Rc<RefCell<CommandIndex>>,
+        specs: Rc<RefCell<CompletionSpecs>>,
+    ) -> Self {
         Self {
-            paths,
-            completion_state: Mutex::new(CompletionState {
-                last_prefix: String::new(),
-                last_matches: Vec::new(),
-            }),
-        }
-    }
-
-    fn get_builtin_completions(&self, prefix: &str) -> Vec<Pair> {
-        let mut completions = Vec::new();
-        for cmd in ["cd", "echo", "exit", "pwd", "type"] {
-            if cmd.starts_with(prefix) {
-                completions.push(Pair {
-                    display: cmd.to_string(),
-                    replacement: format!("{} ", cmd),  // Built-in commands always get a space
-                });
-            }
+            commands,
+            aliases,
+            specs,
+            argument_completers: tool_completion::default_completers(),
+            palette: None,
+            fuzzy: false,
+            ignore_case: false,
+            variables: HashMap::new(),
         }
-        completions
     }
 
-    fn find_executables(&self, prefix: &str) -> Vec<String> {
-        let normalized_prefix = prefix.replace('*', "");
-        let mut matches = self.paths.iter()
-            .filter_map(|path_dir| self.get_dir_entries(path_dir))
-            .flat_map(|entries| entries.filter_map(Result::ok))
-            .filter_map(|entry| self.process_entry(entry, &normalized_prefix))
-            .collect::<Vec<_>>();
-            
-        matches.sort();
-        matches
+    // None turns highlighting off
+    pub fn set_palette(&mut self, palette: Option<Palette>) {
+        self.palette = palette;
     }
 
-    fn get_dir_entries(&self, path_dir: &str) -> Option<std::fs::ReadDir> {
-        std::fs::read_dir(path_dir).ok()
+    // `set -o fuzzycomplete` also matches command names case-insensitively, by substring and
+    // by subsequence
+    pub fn set_fuzzy(&mut self, fuzzy: bool) {
+        self.fuzzy = fuzzy;
     }
 
-    fn process_entry(&self, entry: std::fs::DirEntry, prefix: &str) -> Option<String> {
-        let file_name = entry.file_name().into_string().ok()?;
-        if !file_name.starts_with(prefix) {
-            return None;
-        }
+    // readline's `completion-ignore-case`
+    pub fn set_ignore_case(&mut self, ignore_case: bool) {
+        self.ignore_case = ignore_case;
+    }
+
+    // Shell variables as of the current prompt, for expanding `$VAR` prefixes
+    pub fn set_variables(&mut self, variables: HashMap<String, String>) {
+        self.variables = variables;
+    }
 
-        let metadata = entry.metadata().ok()?;
-        let is_executable = metadata.permissions().mode() & 0o111 != 0;
+    // Only the index is consulted, so typing doesn't search PATH on every key
+    fn is_known_command(&self, command: &str) -> bool {
+        if command.contains('/') {
+            return utils::find_command(command, &mut self.commands.borrow_mut()).is_some();
+        }
+        self.commands.borrow_mut().knows(command)
+    }
 
-        if is_executable {
-            Some(file_name)
+    fn classify_command(&self, command: &str) -> CommandKind {
+        if builtins::is_keyword(command) {
+            CommandKind::Keyword
+        } else if builtins::is_builtin(command) {
+            CommandKind::Builtin
+        } else if self.aliases.borrow().get(command).is_some()
+            || self.is_known_command(command)
+        {
+            CommandKind::Command
         } else {
-            None
-        }
-    }
-
-    fn find_longest_common_prefix(&self, matches: &[String]) -> String {
-        if matches.is_empty() {
-            return String::new();
-        }
-        
-        let first = &matches[0];
-        let mut common_prefix_len = first.len();
-        
-        for str in matches.iter().skip(1) {
-            common_prefix_len = first
-                .chars()
-                .zip(str.chars())
-                .take(common_prefix_len)
-                .take_while(|(a, b)| a == b)
-                .count();
-                
-            if common_prefix_len == 0 {
-                break;
-            }
+            CommandKind::Unknown
         }
-        
-        first[..common_prefix_len].to_string()
-    }
-
-    fn handle_completion(&self, line: &str, pos: usize) -> RustylineResult<(usize, Vec<Pair>)> {
-        let prefix = &line[..pos];
-        
-        // First check built-in commands
-        let builtin_completions = self.get_builtin_completions(prefix);
-        if !builtin_completions.is_empty() {
-            return Ok((0, builtin_completions));
-        }
-        
-        // Get matches for the current prefix
-        let matches = self.find_executables(prefix);
-        
-        // No matches
-        if matches.is_empty() {
-            print!("\x07");  // Bell sound
-            io::stdout().flush().unwrap();
-            return Ok((0, vec![]));
-        }
-        
-        // Find the longest common prefix
-        let common_prefix = self.find_longest_common_prefix(&matches);
-        
-        // Single exact match
-        if matches.len() == 1 {
-            return Ok((0, vec![Pair {
-                display: matches[0].clone(),
-                replacement: format!("{} ", matches[0]),  // Add space for exact match
-            }]));
-        }
-        
-        // Multiple matches with common prefix
-        if common_prefix.len() > prefix.len() {
-            // Update completion state
-            if let Ok(mut state) = self.completion_state.lock() {
-                state.last_matches = matches.clone();
-                state.last_prefix = common_prefix.clone();
+    }
+
+    // The rest of the newest history entry starting with `line`, else of the first command completion
+    fn suggest(&self, line: &str, ctx: &Context<'_>) -> Option<String> {
+        let history = ctx.history();
+        if !history.is_empty() {
+            let start = ctx.history_index().min(history.len() - 1);
+            if let Ok(Some(found)) = history.starts_with(line, start, SearchDirection::Reverse) {
+                return Some(found.entry[line.len()..].to_string()).filter(|rest| !rest.is_empty());
             }
-            
-            // For partial matches, don't add a space
-            return Ok((0, vec![Pair {
-                display: common_prefix.clone(),
-                replacement: common_prefix,  // No space for partial completion
-            }]));
-        }
-        
-        // Show all matches if no further completion possible
-        println!("\n{}", matches.join("  "));
-        print!("$ {}", prefix);
-        io::stdout().flush().unwrap();
-        
-        Ok((0, vec![]))
+        }
+
+        if line.contains(char::is_whitespace) {
+            return None;
+        }
+        let (candidate, _) = self
+            .command_candidates(line, false)
+            .into_iter()
+            .find(|(name, _)| name.starts_with(line))?;
+        Some(candidate[line.len()..].to_string()).filter(|rest| !rest.is_empty())
+    }
+
+    // `$NAME` and `${NAME` complete variable names, `~name` user names and `user@name` host names,
+    // wherever the word is; None when the word is none of these
+    fn complete_context(&self, line: &str, word: &WordContext) -> Option<(usize, Vec<Pair>)> {
+        let name_length = line
+            .chars()
+            .rev()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .count();
+        let name_start = line.len() - name_length;
+        let name = &line[name_start..];
+        let braced = line[..name_start].ends_with("${");
+        let dollar = if braced { name_start - 2 } else { name_start.saturating_sub(1) };
+        let is_variable = (braced || line[..name_start].ends_with('$'))
+            && dollar >= word.start
+            && !line[..dollar].ends_with('\\')
+            && word.quote != Some('\'')
+            && !name.starts_with(|c: char| c.is_ascii_digit());
+        if is_variable {
+            let mut names: Vec<&String> = self.variables.keys().filter(|variable| variable.starts_with(name)).collect();
+            names.sort();
+            let candidates = names
+                .into_iter()
+                .map(|variable| {
+                    let is_directory = self.variables.get(variable).is_some_and(|value| Path::new(value).is_dir());
+                    let suffix = match (braced, word.quote) {
+                        (true, _) => "}",
+                        (false, None) if is_directory => "/",
+                        (false, None) => " ",
+                        (false, Some(_)) => "",
+                    };
+                    Pair {
+                        display: format!("${}", variable),
+                        replacement: format!("{}{}", variable, suffix),
+                    }
+                })
+                .collect();
+            return Some((name_start, candidates));
+        }
+
+        // Anything quoted or escaped is an ordinary word
+        if word.quote.is_some() || line[word.start..] != word.text {
+            return None;
+        }
+        if let Some(user) = word.text.strip_prefix('~').filter(|user| !user.contains('/')) {
+            let mut users: Vec<String> = programmable_completion::user_names()
+                .into_iter()
+                .filter(|name| name.starts_with(user))
+                .collect();
+            users.sort();
+            users.dedup();
+            let candidates = users
+                .into_iter()
+                .map(|name| Pair {
+                    display: format!("~{}", name),
+                    replacement: format!("~{}/", name),
+                })
+                .collect();
+            return Some((word.start, candidates));
+        }
+        if let (WordPosition::Argument(_), Some(at)) = (&word.position, word.text.rfind('@')) {
+            let host = &word.text[at + 1..];
+            let mut hosts: Vec<String> = programmable_completion::host_names(&self.variables)
+                .into_iter()
+                .filter(|name| name.starts_with(host))
+                .collect();
+            hosts.sort();
+            hosts.dedup();
+            let candidates = hosts
+                .into_iter()
+                .map(|name| Pair {
+                    replacement: format!("{} ", name),
+                    display: name,
+                })
+                .collect();
+            return Some((word.start + at + 1, candidates));
+        }
+        None
+    }
+
+    // Candidates from the built-in completer for `command`, if it has one
+    fn complete_arguments(&self, command: &str, word: &WordContext) -> Vec<Pair> {
+        let name = command.rsplit('/').next().unwrap_or(command);
+        let Some(completer) = self.argument_completers.iter().find(|completer| completer.command() == name) else {
+            return Vec::new();
+        };
+        let mut candidates: Vec<String> = completer
+            .candidates(word)
+            .into_iter()
+            .filter(|candidate| path_completion::has_prefix(candidate, &word.text, self.ignore_case))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+            .into_iter()
+            .map(|candidate| word.pair(&candidate, candidate.clone(), 0, false, true))
+            .collect()
+    }
+
+    // Completes an argument from a `complete` spec; `-o default` falls back to filenames
+    fn complete_from_spec(&self, spec: &CompletionSpec, word: &WordContext, line: &str) -> Vec<Pair> {
+        let sources = CompletionSources {
+            variables: &self.variables,
+            aliases: self.aliases.borrow().names().map(String::from).collect(),
+            commands: &self.commands,
+        };
+        let candidates = spec.generate(word, line, &sources);
+        if candidates.is_empty() && spec.has_option("default") {
+            return word.complete_path(PathFilter::All, &self.variables);
+        }
+
+        let add_space = !spec.has_option("nospace");
+        let raw_length = word.raw_prefix_length(&self.variables);
+        candidates
+            .into_iter()
+            .map(|(text, is_directory)| {
+                if !spec.completes_filenames() {
+                    let replacement = if add_space { format!("{} ", text) } else { text.clone() };
+                    return Pair { display: text, replacement };
+                }
+                let is_directory = is_directory || Path::new(&text).is_dir();
+                let name = text.rsplit('/').next().unwrap_or_default();
+                let display = if is_directory { format!("{}/", name) } else { name.to_string() };
+                let raw_length = if text.starts_with(&word.text[..raw_length]) { raw_length } else { 0 };
+                word.pair(&text, display, raw_length, is_directory, add_space)
+            })
+            .collect()
+    }
+
+    // Builtins, aliases and executables starting with `prefix`, or matching it fuzzily, each with
+    // a description of whichever of them would run
+    fn command_candidates(&self, prefix: &str, fuzzy: bool) -> Vec<(String, String)> {
+        let prefix = prefix.replace('*', "");
+        let searched = if fuzzy || self.ignore_case { "" } else { prefix.as_str() };
+        let mut descriptions = self.commands.borrow_mut().executable_paths(searched);
+        for builtin in builtins::BUILTIN_COMMANDS {
+            descriptions.insert(builtin.to_string(), "shell builtin".to_string());
+        }
+        let aliases = self.aliases.borrow();
+        for name in aliases.names() {
+            let value = aliases.get(name).unwrap_or_default();
+            descriptions.insert(name.to_string(), format!("alias for `{}'", value));
+        }
+
+        let names: Vec<&str> = if fuzzy {
+            fuzzy::rank(&prefix, descriptions.keys().map(String::as_str))
+        } else {
+            descriptions
+                .keys()
+                .map(String::as_str)
+                .filter(|name| path_completion::has_prefix(name, &prefix, self.ignore_case))
+                .collect()
+        };
+        names
+            .into_iter()
+            .map(|name| (name.to_string(), descriptions[name].clone()))
+            .collect()
+    }
+
+    // The editor inserts a lone match or the matches' common prefix, and lists them (paging, and
+    // asking first when there are many) on a second Tab, with descriptions lined up after the names
+    fn handle_completion(&self, line: &str, start: usize, pos: usize) -> RustylineResult<(usize, Vec<Pair>)> {
+        let candidates = self.command_candidates(&line[start..pos], self.fuzzy);
+        let width = candidates
+            .iter()
+            .map(|(name, _)| name.chars().count())
+            .max()
+            .unwrap_or_default();
+        let pairs = candidates
+            .into_iter()
+            .map(|(name, description)| Pair {
+                display: format!("{:<width$}  {}", name, description, width = width),
+                replacement: format!("{} ", name),
+            })
+            .collect();
+        Ok((start, pairs))
     }
 }
 
@@ -168,21 +312,108 @@ impl Completer for BuiltInCompleter {
         pos: usize,
         _ctx: &Context<'_>,
     ) -> RustylineResult<(usize, Vec<Pair>)> {
-        // Skip if not first token
-        if line[..pos].contains(' ') {
-            return Ok((0, vec![]));
+        let mut word = WordContext::parse(&line[..pos]);
+        word.ignore_case = self.ignore_case;
+        if let Some(completion) = self.complete_context(&line[..pos], &word) {
+            return Ok(completion);
         }
-
-        self.handle_completion(line, pos)
+        if let WordPosition::Argument(command) = &word.position {
+            if let Some(spec) = self.specs.borrow().get(command) {
+                return Ok((word.start, self.complete_from_spec(spec, &word, &line[..pos])));
+            }
+            let candidates = self.complete_arguments(command, &word);
+            if !candidates.is_empty() {
+                return Ok((word.start, candidates));
+            }
+        }
+        let filter = match &word.position {
+            WordPosition::Command if !word.text.contains('/') => {
+                return self.handle_completion(line, word.start, pos);
+            }
+            WordPosition::Command => PathFilter::Executables,
+            WordPosition::Argument(command) if DIRECTORY_COMMANDS.contains(&command.as_str()) => {
+                PathFilter::Directories
+            }
+            WordPosition::Argument(_) | WordPosition::Redirection => PathFilter::All,
+        };
+        Ok((word.start, word.complete_path(filter, &self.variables)))
     }
 }
 
 impl Helper for BuiltInCompleter {}
 impl Hinter for BuiltInCompleter {
     type Hint = String;
-    fn hint(&self, _line: &str, _pos: usize, _ctx: &Context<'_>) -> Option<String> {
-        None
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        if pos < line.len() || line.trim().is_empty() {
+            return None;
+        }
+        self.suggest(line, ctx)
+    }
+}
+impl Highlighter for BuiltInCompleter {
+    fn highlight<'l>(&self, line: &'l str, _pos: usize) -> Cow<'l, str> {
+        match &self.palette {
+            Some(palette) => Cow::Owned(highlight::highlight_line(line, palette, |command| {
+                self.classify_command(command)
+            })),
+            None => Cow::Borrowed(line),
+        }
+    }
+
+    // Grey, or just dim when colours are off
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        let style = if self.palette.is_some() { "90" } else { "2" };
+        Cow::Owned(format!("\x1b[{}m{}\x1b[0m", style, hint))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, kind: CmdKind) -> bool {
+        self.palette.is_some() && kind != CmdKind::MoveCursor
+    }
+}
+impl Validator for BuiltInCompleter {
+    fn validate(&self, ctx: &mut ValidationContext) -> RustylineResult<ValidationResult> {
+        if validation::is_incomplete(ctx.input()) {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+// End accepts the whole suggestion, like the right arrow does
+pub struct AcceptHint;
+
+impl ConditionalEventHandler for AcceptHint {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        (ctx.has_hint() && ctx.pos() == ctx.line().len()).then_some(Cmd::CompleteHint)
+    }
+}
+
+// Alt-F accepts the suggestion up to the end of its next word
+pub struct AcceptHintWord;
+
+impl ConditionalEventHandler for AcceptHintWord {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        if ctx.pos() < ctx.line().len() {
+            return None;
+        }
+        let hint = ctx.hint_text()?;
+        let leading = hint.len() - hint.trim_start().len();
+        let end = hint[leading..]
+            .find(char::is_whitespace)
+            .map_or(hint.len(), |offset| leading + offset);
+        Some(Cmd::Insert(1, hint[..end].to_string()))
     }
 }
-impl Highlighter for BuiltInCompleter {}
-impl Validator for BuiltInCompleter {}
\ No newline at end of file
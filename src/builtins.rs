@@ -1,58 +1,296 @@
 use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 
 use crate::output::CommandOutput;
+use crate::printf::{self, EscapeStyle};
 use crate::utils;
+use crate::variables::Variables;
 
 pub fn echo_input(args: &[String]) -> CommandOutput {
-    CommandOutput {
-        stdout: args.join(" ") + "\n",
-        stderr: String::new(),
+    let mut newline = true;
+    let mut escapes = false;
+    let mut index = 0;
+
+    while let Some(flags) = args.get(index).and_then(|arg| arg.strip_prefix('-')) {
+        if flags.is_empty() || !flags.chars().all(|flag| matches!(flag, 'n' | 'e' | 'E')) {
+            break;
+        }
+        for flag in flags.chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        index += 1;
     }
-}
 
-pub fn print_working_directory() -> CommandOutput {
-    CommandOutput {
-        stdout: format!("{}\n", env::current_dir().unwrap().display()),
-        stderr: String::new(),
+    let text = args[index..].join(" ");
+    let (mut bytes, stopped) = if escapes {
+        printf::interpret_escapes(&text, EscapeStyle::Echo)
+    } else {
+        (text.into_bytes(), false)
+    };
+    if newline && !stopped {
+        bytes.push(b'\n');
     }
+    CommandOutput::stdout(bytes)
 }
 
-pub fn change_directory(new_working_directory: &str) -> CommandOutput {
-    let path = if new_working_directory == "~" {
-        #[allow(deprecated)]
-        env::home_dir().unwrap_or_else(|| Path::new("").to_path_buf())
+pub fn print_working_directory(args: &[String]) -> CommandOutput {
+    let (physical, _) = match parse_directory_options("pwd", args) {
+        Ok(parsed) => parsed,
+        Err(output) => return output,
+    };
+
+    let directory = if physical {
+        physical_working_directory()
     } else {
-        Path::new(new_working_directory).to_path_buf()
+        Ok(logical_working_directory())
+    };
+
+    match directory {
+        Ok(directory) => CommandOutput::stdout(format!("{}\n", directory)),
+        Err(e) => CommandOutput::stderr(format!(
+            "pwd: error retrieving current directory: {}\n",
+            utils::io_error_message(&e)
+        )),
+    }
+}
+
+pub fn change_directory(args: &[String], variables: &Variables) -> CommandOutput {
+    let (physical, operands) = match parse_directory_options("cd", args) {
+        Ok(parsed) => parsed,
+        Err(output) => return output,
     };
+    if operands.len() > 1 {
+        return CommandOutput::stderr("cd: too many arguments\n");
+    }
 
-    match env::set_current_dir(&path) {
-        Ok(()) => CommandOutput {
-            stdout: String::new(),
-            stderr: String::new(),
+    let (target, mut print_directory) = match operands.first().map(String::as_str) {
+        None => match variables.get("HOME").filter(|home| !home.is_empty()) {
+            Some(home) => (home, false),
+            None => return CommandOutput::stderr("cd: HOME not set\n"),
         },
-        Err(_) => CommandOutput {
-            stdout: String::new(),
-            stderr: format!("cd: {}: No such file or directory\n", new_working_directory),
+        Some("-") => match variables.get("OLDPWD").filter(|old_pwd| !old_pwd.is_empty()) {
+            Some(old_pwd) => (old_pwd, true),
+            None => return CommandOutput::stderr("cd: OLDPWD not set\n"),
         },
+        Some(directory) => (directory.to_string(), false),
+    };
+
+    let directory = match search_cdpath(&target, variables.get("CDPATH")) {
+        Some(found) => {
+            print_directory = true;
+            found
+        }
+        None => target.clone(),
+    };
+
+    match change_working_directory(&directory, physical) {
+        Ok(new_pwd) if print_directory => CommandOutput::stdout(format!("{}\n", new_pwd)),
+        Ok(_) => CommandOutput::default(),
+        Err(e) => CommandOutput::stderr(format!(
+            "cd: {}: {}\n",
+            target,
+            utils::io_error_message(&e)
+        )),
     }
 }
 
-pub fn handle_type_command(command: &str, paths: &[String]) -> CommandOutput {
-    let output = if is_builtin(command) {
-        format!("{} is a shell builtin\n", command)
-    } else if let Some(command_path) = utils::find_command(command, paths) {  // Change this
-        format!("{} is {}\n", command, command_path)
+// Changes directory and keeps PWD/OLDPWD in sync, returning the new PWD
+pub fn change_working_directory(directory: &str, physical: bool) -> io::Result<String> {
+    let old_pwd = logical_working_directory();
+
+    let new_pwd = if physical {
+        env::set_current_dir(directory)?;
+        physical_working_directory()?
     } else {
-        format!("{}: not found\n", command)
+        let logical = utils::normalize_logical_path(&Path::new(&old_pwd).join(directory));
+        if env::set_current_dir(&logical).is_ok() {
+            logical.to_string_lossy().into_owned()
+        } else {
+            env::set_current_dir(directory)?;
+            physical_working_directory()?
+        }
     };
-    CommandOutput {
-        stdout: output,
-        stderr: String::new(),
+
+    env::set_var("OLDPWD", old_pwd);
+    env::set_var("PWD", &new_pwd);
+    Ok(new_pwd)
+}
+
+pub fn initialize_working_directory() {
+    env::set_var("PWD", logical_working_directory());
+}
+
+pub fn logical_working_directory() -> String {
+    match env::var("PWD") {
+        Ok(pwd) if is_current_directory(&pwd) => pwd,
+        _ => physical_working_directory().unwrap_or_default(),
+    }
+}
+
+fn physical_working_directory() -> io::Result<String> {
+    env::current_dir().map(|dir| dir.to_string_lossy().into_owned())
+}
+
+fn is_current_directory(path: &str) -> bool {
+    if !path.starts_with('/') || path.split('/').any(|part| part == "." || part == "..") {
+        return false;
+    }
+    match (fs::metadata(path), fs::metadata(".")) {
+        (Ok(candidate), Ok(current)) => {
+            candidate.dev() == current.dev() && candidate.ino() == current.ino()
+        }
+        _ => false,
     }
 }
 
+fn search_cdpath(target: &str, cdpath: Option<String>) -> Option<String> {
+    let relative_to_cwd = target.is_empty()
+        || target.starts_with('/')
+        || target == "."
+        || target == ".."
+        || target.starts_with("./")
+        || target.starts_with("../");
+    if relative_to_cwd {
+        return None;
+    }
+
+    cdpath?
+        .split(':')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| Path::new(entry).join(target))
+        .find(|candidate| candidate.is_dir())
+        .map(|candidate| candidate.to_string_lossy().into_owned())
+}
+
+fn parse_directory_options<'a>(
+    name: &str,
+    args: &'a [String],
+) -> Result<(bool, &'a [String]), CommandOutput> {
+    let mut physical = false;
+    let mut index = 0;
+
+    while let Some(arg) = args.get(index) {
+        if arg == "--" {
+            index += 1;
+            break;
+        }
+        if arg == "-" || !arg.starts_with('-') {
+            break;
+        }
+        for flag in arg.chars().skip(1) {
+            match flag {
+                'L' => physical = false,
+                'P' => physical = true,
+                _ => {
+                    return Err(CommandOutput::stderr(format!(
+                        "{name}: -{flag}: invalid option\n{name}: usage: {name} [-L|-P]{}\n",
+                        if name == "cd" { " [dir]" } else { "" }
+                    )))
+                }
+            }
+        }
+        index += 1;
+    }
+
+    Ok((physical, &args[index..]))
+}
+
+// Tests that change the working directory or environment variables hold this, as both belong
+// to the whole process
+#[cfg(test)]
+pub static ENVIRONMENT_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+pub const BUILTIN_COMMANDS: &[&str] = &[
+    ".", "[", "alias", "bind", "cd", "command", "compgen", "complete", "dirs", "echo", "exit", "export",
+    "hash", "history", "popd", "printf", "pushd", "pwd", "read", "set", "source", "test", "type",
+    "unalias", "unset",
+];
+
+pub const KEYWORDS: &[&str] = &["[[", "]]"];
+
 pub fn is_builtin(command: &str) -> bool {
-    let builtins = ["cd", "echo", "exit", "pwd", "type"];
-    builtins.contains(&command)
-}
\ No newline at end of file
+    BUILTIN_COMMANDS.contains(&command)
+}
+
+pub fn is_keyword(command: &str) -> bool {
+    KEYWORDS.contains(&command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cd(args: &[&str], variables: &Variables) -> CommandOutput {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        change_directory(&args, variables)
+    }
+
+    #[test]
+    fn searches_cdpath_for_plain_relative_names() {
+        let base = env::temp_dir().join(format!("shell-rs-cdpath-{}", std::process::id()));
+        fs::create_dir_all(base.join("projects/app")).unwrap();
+        let cdpath = Some(format!("/nonexistent:{}", base.join("projects").display()));
+        let app = base.join("projects/app").to_string_lossy().into_owned();
+        assert_eq!(search_cdpath("app", cdpath.clone()), Some(app));
+        assert_eq!(search_cdpath("./app", cdpath.clone()), None);
+        assert_eq!(search_cdpath("/app", cdpath.clone()), None);
+        assert_eq!(search_cdpath("missing", cdpath), None);
+        assert_eq!(search_cdpath("app", None), None);
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn changes_directory_logically_or_physically() {
+        let _lock = ENVIRONMENT_LOCK.lock().unwrap();
+        let original = logical_working_directory();
+        let base = env::temp_dir().join(format!("shell-rs-cd-{}", std::process::id()));
+        fs::create_dir_all(base.join("real/sub")).unwrap();
+        std::os::unix::fs::symlink(base.join("real"), base.join("link")).unwrap();
+        let link = base.join("link").to_string_lossy().into_owned();
+        let real = fs::canonicalize(base.join("real")).unwrap();
+        let mut variables = Variables::new();
+
+        // -L (the default) keeps the symlink in PWD; -P resolves it
+        assert_eq!(cd(&[&link], &variables).status, 0);
+        assert_eq!(cd(&["sub"], &variables).status, 0);
+        assert_eq!(env::var("PWD").unwrap(), format!("{}/sub", link));
+        assert_eq!(cd(&["-P", ".."], &variables).status, 0);
+        assert_eq!(env::var("PWD").unwrap(), real.to_string_lossy());
+
+        // `cd -` returns to OLDPWD and prints it
+        assert_eq!(
+            cd(&["-"], &variables).stdout,
+            format!("{}/sub\n", link).into_bytes()
+        );
+
+        // A directory found through CDPATH is printed too
+        variables.set("CDPATH", base.to_string_lossy());
+        let output = cd(&["real"], &variables);
+        assert_eq!(
+            output.stdout,
+            format!("{}/real\n", base.display()).into_bytes()
+        );
+
+        assert_eq!(
+            cd(&["a", "b"], &variables).stderr,
+            "cd: too many arguments\n"
+        );
+        assert!(cd(&["-x"], &variables)
+            .stderr
+            .starts_with("cd: -x: invalid option\n"));
+        assert_eq!(
+            cd(&["missing"], &variables).stderr,
+            "cd: missing: No such file or directory\n"
+        );
+
+        change_working_directory(&original, false).unwrap();
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -1,15 +1,19 @@
-use std::fs::OpenOptions;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::os::unix::process::CommandExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::process::Command;
 
+use crate::command_index::CommandIndex;
+use crate::output::CommandOutput;
 use crate::redirection::{Redirection, RedirectionMode};
 use crate::utils;
 
 struct CommandExecutor<'a> {
     command: &'a str,
-    paths: &'a [String],
+    commands: &'a RefCell<CommandIndex>,
     args: &'a [String],
+    stdin_redir: &'a Option<Redirection>,
     stdout_redir: &'a Option<Redirection>,
     stderr_redir: &'a Option<Redirection>,
 }
@@ -17,36 +21,51 @@ struct CommandExecutor<'a> {
 impl<'a> CommandExecutor<'a> {
     fn new(
         command: &'a str,
-        paths: &'a [String],
+        commands: &'a RefCell<CommandIndex>,
         args: &'a [String],
+        stdin_redir: &'a Option<Redirection>,
         stdout_redir: &'a Option<Redirection>,
         stderr_redir: &'a Option<Redirection>,
     ) -> Self {
         Self {
             command,
-            paths,
+            commands,
             args,
+            stdin_redir,
             stdout_redir,
             stderr_redir,
         }
     }
 
-    fn execute(&self) {
+    // The child writes its own output; the returned output only carries shell diagnostics
+    fn execute(&self) -> CommandOutput {
         match self.find_command() {
             Some(command_path) => self.run_command(&command_path),
-            None => eprintln!("{}: command not found", self.command),
+            None => CommandOutput::stderr(format!("{}: command not found\n", self.command))
+                .with_status(127),
         }
     }
 
-    fn run_command(&self, command_path: &str) {
+    fn run_command(&self, command_path: &str) -> CommandOutput {
         let mut cmd = self.create_base_command(command_path);
         
         if let Err(e) = self.setup_redirections(&mut cmd) {
-            eprintln!("Redirection error: {}", e);
-            return;
+            return CommandOutput::stderr(format!("Redirection error: {}\n", e));
         }
 
-        let _ = cmd.status();
+        match cmd.status() {
+            Ok(status) => CommandOutput::default().with_status(
+                status
+                    .code()
+                    .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+            ),
+            Err(e) => CommandOutput::stderr(format!(
+                "{}: {}\n",
+                self.command,
+                utils::io_error_message(&e)
+            ))
+            .with_status(126),
+        }
     }
 
     fn create_base_command(&self, command_path: &str) -> Command {
@@ -57,6 +76,11 @@ impl<'a> CommandExecutor<'a> {
     }
 
     fn setup_redirections(&self, cmd: &mut Command) -> io::Result<()> {
+        if let Some(redir) = self.stdin_redir {
+            let file = self.open_redirection_file(redir)?;
+            cmd.stdin(file);
+        }
+
         if let Some(redir) = self.stdout_redir {
             let file = self.open_redirection_file(redir)?;
             cmd.stdout(file);
@@ -70,7 +94,10 @@ impl<'a> CommandExecutor<'a> {
         Ok(())
     }
 
-    fn open_redirection_file(&self, redir: &Redirection) -> io::Result<std::fs::File> {
+    fn open_redirection_file(&self, redir: &Redirection) -> io::Result<File> {
+        if matches!(redir.mode, RedirectionMode::Read) {
+            return File::open(&redir.filename);
+        }
         OpenOptions::new()
             .create(true)
             .write(true)
@@ -80,16 +107,20 @@ impl<'a> CommandExecutor<'a> {
     }
 
     fn find_command(&self) -> Option<String> {
-        utils::find_command(self.command, self.paths)
+        if self.command.contains('/') {
+            return utils::find_command(self.command, &mut self.commands.borrow_mut());
+        }
+        self.commands.borrow_mut().hash_command(self.command)
     }
 }
 
 pub fn execute_command(
     command: &str,
-    paths: &[String],
+    commands: &RefCell<CommandIndex>,
     args: &[String],
+    stdin_redir: &Option<Redirection>,
     stdout_redir: &Option<Redirection>,
     stderr_redir: &Option<Redirection>,
-) {
-    CommandExecutor::new(command, paths, args, stdout_redir, stderr_redir).execute();
+) -> CommandOutput {
+    CommandExecutor::new(command, commands, args, stdin_redir, stdout_redir, stderr_redir).execute()
 }
\ No newline at end of file
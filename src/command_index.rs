@@ -0,0 +1,428 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::ops::Bound;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::builtins;
+use crate::output::CommandOutput;
+
+// The executables of each PATH directory, re-read only when the directory's mtime changes, and
+// bash's table of remembered command locations with their hit counts
+pub struct CommandIndex {
+    directories: Vec<IndexedDirectory>,
+    hashed: BTreeMap<String, HashedCommand>,
+    // When the directories' mtimes were last checked
+    checked: Option<Instant>,
+    // The value of PATH the directories came from, for an index that follows PATH
+    path: Option<String>,
+}
+
+// Lookups made while typing reuse the index for this long before checking the directories again
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct IndexedDirectory {
+    path: String,
+    modified: Option<SystemTime>,
+    executables: BTreeSet<String>,
+}
+
+struct HashedCommand {
+    path: String,
+    hits: usize,
+}
+
+impl CommandIndex {
+    // An index of a fixed list of directories
+    pub fn new(paths: &[String]) -> Self {
+        Self {
+            directories: index_directories(paths),
+            hashed: BTreeMap::new(),
+            checked: None,
+            path: None,
+        }
+    }
+
+    // An index of the directories in PATH that starts over whenever PATH is assigned
+    pub fn following_path() -> Self {
+        let mut index = Self::new(&[]);
+        index.path = Some(String::new());
+        index.follow_path();
+        index
+    }
+
+    // Executables on PATH starting with `prefix`, without duplicates, in name order
+    pub fn executables(&mut self, prefix: &str) -> Vec<String> {
+        self.executable_paths(prefix).into_keys().collect()
+    }
+
+    // The same names, each with the path that would run
+    pub fn executable_paths(&mut self, prefix: &str) -> BTreeMap<String, String> {
+        self.refresh();
+        let mut paths = BTreeMap::new();
+        for directory in &self.directories {
+            let names = directory
+                .executables
+                .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+                .take_while(|name| name.starts_with(prefix));
+            for name in names {
+                paths
+                    .entry(name.clone())
+                    .or_insert_with(|| join(&directory.path, name));
+            }
+        }
+        paths
+    }
+
+    // The remembered location of `name`, else the first PATH directory that has it
+    pub fn lookup(&mut self, name: &str) -> Option<String> {
+        if let Some(path) = self.hashed_path(name) {
+            return Some(path.to_string());
+        }
+        self.search(name)
+    }
+
+    // Looks `name` up for running it, remembering where it was found and counting the hit
+    pub fn hash_command(&mut self, name: &str) -> Option<String> {
+        let path = self.lookup(name)?;
+        let entry = self
+            .hashed
+            .entry(name.to_string())
+            .or_insert_with(|| HashedCommand {
+                path: path.clone(),
+                hits: 0,
+            });
+        entry.hits += 1;
+        Some(path)
+    }
+
+    // A remembered location that no longer exists is forgotten, so the command is searched again
+    pub fn hashed_path(&mut self, name: &str) -> Option<&str> {
+        self.follow_path();
+        if self
+            .hashed
+            .get(name)
+            .is_some_and(|command| !Path::new(&command.path).is_file())
+        {
+            self.hashed.remove(name);
+        }
+        self.hashed.get(name).map(|command| command.path.as_str())
+    }
+
+    // Whether `name` is remembered or in the index, without touching the filesystem more than
+    // once per CHECK_INTERVAL; for highlighting, which looks commands up on every keystroke
+    pub fn knows(&mut self, name: &str) -> bool {
+        if self.hashed.contains_key(name) {
+            return true;
+        }
+        self.refresh();
+        self.directories
+            .iter()
+            .any(|directory| directory.executables.contains(name))
+    }
+
+    // The index first; on a miss PATH is searched directly, as bash does, because `chmod +x`
+    // makes a command runnable without changing its directory's mtime
+    fn search(&mut self, name: &str) -> Option<String> {
+        self.refresh();
+        let indexed = self
+            .directories
+            .iter()
+            .find(|directory| directory.executables.contains(name));
+        if let Some(directory) = indexed {
+            return Some(join(&directory.path, name));
+        }
+        self.search_all(name).into_iter().next()
+    }
+
+    // Every PATH directory's copy of `name`, in search order, adding any the index missed
+    pub fn search_all(&mut self, name: &str) -> Vec<String> {
+        self.follow_path();
+        let mut paths = Vec::new();
+        for directory in &mut self.directories {
+            let path = join(&directory.path, name);
+            if is_executable(Path::new(&path)) {
+                directory.executables.insert(name.to_string());
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    // As in bash, a new PATH forgets every remembered location as well as the old directories
+    fn follow_path(&mut self) {
+        let Some(previous) = &self.path else {
+            return;
+        };
+        let path = env::var("PATH").unwrap_or_default();
+        if *previous == path {
+            return;
+        }
+        let paths: Vec<String> = path.split(':').map(String::from).collect();
+        self.directories = index_directories(&paths);
+        self.hashed.clear();
+        self.checked = None;
+        self.path = Some(path);
+    }
+
+    fn refresh(&mut self) {
+        self.follow_path();
+        if self
+            .checked
+            .is_some_and(|checked| checked.elapsed() < CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.checked = Some(Instant::now());
+        for directory in &mut self.directories {
+            let modified = fs::metadata(&directory.path)
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            if modified.is_some() && modified == directory.modified {
+                continue;
+            }
+            directory.modified = modified;
+            directory.executables = read_executables(&directory.path);
+        }
+    }
+
+    // Forgets every remembered location and re-reads every directory on the next lookup
+    fn clear(&mut self) {
+        self.hashed.clear();
+        self.checked = None;
+        for directory in &mut self.directories {
+            directory.modified = None;
+        }
+    }
+
+    pub fn hash(&mut self, args: &[String]) -> CommandOutput {
+        self.follow_path();
+        let mut clear = false;
+        let mut delete = false;
+        let mut list = false;
+        let mut print = false;
+        let mut path = None;
+        let mut index = 0;
+        while let Some(arg) = args
+            .get(index)
+            .filter(|arg| arg.starts_with('-') && arg.len() > 1)
+        {
+            index += 1;
+            if arg == "--" {
+                break;
+            }
+            for (position, flag) in arg.chars().skip(1).enumerate() {
+                match flag {
+                    'r' => clear = true,
+                    'd' => delete = true,
+                    'l' => list = true,
+                    't' => print = true,
+                    'p' => {
+                        // The path is the rest of the argument or the next one
+                        let rest = &arg[position + 2..];
+                        path = if rest.is_empty() {
+                            index += 1;
+                            args.get(index - 1).cloned()
+                        } else {
+                            Some(rest.to_string())
+                        };
+                        if path.is_none() {
+                            return CommandOutput::stderr(
+                                "hash: -p: option requires an argument\n",
+                            )
+                            .with_status(2);
+                        }
+                        break;
+                    }
+                    _ => {
+                        return CommandOutput::stderr(format!(
+                            "hash: -{}: invalid option\nhash: usage: hash [-lr] [-p pathname] [-dt] [name ...]\n",
+                            flag
+                        ))
+                        .with_status(2);
+                    }
+                }
+            }
+        }
+        let names = &args[index..];
+
+        if clear {
+            self.clear();
+        }
+        if names.is_empty() {
+            if clear || delete || print || path.is_some() {
+                return CommandOutput::default();
+            }
+            return self.listing(list);
+        }
+
+        let mut output = CommandOutput::default();
+        let not_found = |output: &mut CommandOutput, name: &str| {
+            output.stderr += &format!("hash: {}: not found\n", name);
+            output.status = 1;
+        };
+        for name in names {
+            if let Some(path) = &path {
+                self.hashed.insert(
+                    name.clone(),
+                    HashedCommand {
+                        path: path.clone(),
+                        hits: 0,
+                    },
+                );
+            } else if delete {
+                if self.hashed.remove(name).is_none() {
+                    not_found(&mut output, name);
+                }
+            } else if print {
+                match self.hashed_path(name) {
+                    Some(path) if names.len() > 1 => {
+                        output.push_stdout(&format!("{}\t{}\n", name, path))
+                    }
+                    Some(path) => output.push_stdout(&format!("{}\n", path)),
+                    None => not_found(&mut output, name),
+                }
+            } else if name.contains('/') || builtins::is_builtin(name) {
+                // bash only remembers commands found by searching PATH
+            } else if self.hashed_path(name).is_none() {
+                match self.search(name) {
+                    Some(path) => {
+                        self.hashed
+                            .insert(name.clone(), HashedCommand { path, hits: 0 });
+                    }
+                    None => not_found(&mut output, name),
+                }
+            }
+        }
+        output
+    }
+
+    fn listing(&self, reusable: bool) -> CommandOutput {
+        if self.hashed.is_empty() {
+            return CommandOutput::stdout("hash: hash table empty\n");
+        }
+        let lines: String = self
+            .hashed
+            .iter()
+            .map(|(name, command)| match reusable {
+                true => format!("builtin hash -p {} {}\n", command.path, name),
+                false => format!("{:4}\t{}\n", command.hits, command.path),
+            })
+            .collect();
+        match reusable {
+            true => CommandOutput::stdout(lines),
+            false => CommandOutput::stdout(format!("hits\tcommand\n{}", lines)),
+        }
+    }
+}
+
+fn read_executables(directory: &str) -> BTreeSet<String> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return BTreeSet::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            is_executable(&entry.path()).then_some(name)
+        })
+        .collect()
+}
+
+fn index_directories(paths: &[String]) -> Vec<IndexedDirectory> {
+    paths
+        .iter()
+        .map(|path| IndexedDirectory {
+            path: path.clone(),
+            modified: None,
+            executables: BTreeSet::new(),
+        })
+        .collect()
+}
+
+// Follows symlinks, which is how most PATH entries in Nix-style profiles are made
+fn is_executable(path: &Path) -> bool {
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+fn join(directory: &str, name: &str) -> String {
+    Path::new(directory).join(name).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_mode(path: &Path, mode: u32) {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    #[test]
+    fn finds_commands_made_executable_after_indexing() {
+        let directory = std::env::temp_dir().join(format!("command-index-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let command = directory.join("newcmd");
+        fs::write(&command, "#!/bin/sh\n").unwrap();
+        set_mode(&command, 0o644);
+
+        let mut index = CommandIndex::new(&[directory.to_string_lossy().into_owned()]);
+        assert_eq!(index.lookup("newcmd"), None);
+        assert!(!index.knows("newcmd"));
+
+        // chmod leaves the directory's mtime alone, so only the direct search can find it
+        set_mode(&command, 0o755);
+        let expected = command.to_string_lossy().into_owned();
+        assert_eq!(index.hash_command("newcmd"), Some(expected.clone()));
+        assert!(index.knows("newcmd"));
+        assert_eq!(index.search_all("newcmd"), vec![expected]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn follows_path_assignments() {
+        let _lock = builtins::ENVIRONMENT_LOCK.lock().unwrap();
+        let base = env::temp_dir().join(format!("command-index-path-{}", std::process::id()));
+        let (first, second) = (base.join("first"), base.join("second"));
+        fs::create_dir_all(&first).unwrap();
+        fs::create_dir_all(&second).unwrap();
+        let command = second.join("mytool");
+        fs::write(&command, "#!/bin/sh\n").unwrap();
+        set_mode(&command, 0o755);
+        let original = env::var_os("PATH");
+
+        env::set_var("PATH", &first);
+        let mut index = CommandIndex::following_path();
+        assert_eq!(index.lookup("mytool"), None);
+        let args = ["-p".to_string(), "/bin/sh".to_string(), "sh".to_string()];
+        index.hash(&args);
+
+        env::set_var("PATH", format!("{}:{}", second.display(), first.display()));
+        let expected = command.to_string_lossy().into_owned();
+        assert_eq!(index.lookup("mytool"), Some(expected));
+        assert!(index.knows("mytool"));
+        assert_eq!(index.hash(&[]).stdout, b"hash: hash table empty\n");
+
+        match original {
+            Some(path) => env::set_var("PATH", path),
+            None => env::remove_var("PATH"),
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn hash_lists_and_forgets_commands() {
+        let mut index = CommandIndex::new(&[]);
+        assert_eq!(index.hash(&[]).stdout, b"hash: hash table empty\n");
+        let args = ["-p".to_string(), "/bin/sh".to_string(), "sh".to_string()];
+        assert_eq!(index.hash(&args).status, 0);
+        assert_eq!(index.hash(&["-t".to_string(), "sh".to_string()]).stdout, b"/bin/sh\n");
+        assert_eq!(index.hash(&[]).stdout, b"hits\tcommand\n   0\t/bin/sh\n");
+        assert_eq!(index.hash(&["-d".to_string(), "nosuch".to_string()]).status, 1);
+        index.hash(&["-r".to_string()]);
+        assert_eq!(index.hash(&[]).stdout, b"hash: hash table empty\n");
+    }
+}
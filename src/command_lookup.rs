@@ -0,0 +1,286 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use crate::alias::{self, Aliases};
+use crate::builtins;
+use crate::command_index::CommandIndex;
+use crate::output::CommandOutput;
+
+// The search path for `command -p`, as `getconf PATH` reports it
+const DEFAULT_PATH: &[&str] = &["/bin", "/usr/bin"];
+
+// One way a name can be run, in the order the shell tries them
+enum Resolution {
+    Alias(String),
+    Keyword,
+    Builtin,
+    Hashed(String),
+    File(String),
+}
+
+impl Resolution {
+    fn kind(&self) -> &'static str {
+        match self {
+            Resolution::Alias(_) => "alias",
+            Resolution::Keyword => "keyword",
+            Resolution::Builtin => "builtin",
+            Resolution::Hashed(_) | Resolution::File(_) => "file",
+        }
+    }
+
+    fn path(&self) -> Option<&str> {
+        match self {
+            Resolution::Hashed(path) | Resolution::File(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    fn describe(&self, name: &str) -> String {
+        match self {
+            Resolution::Alias(value) => format!("{} is aliased to `{}'\n", name, value),
+            Resolution::Keyword => format!("{} is a shell keyword\n", name),
+            Resolution::Builtin => format!("{} is a shell builtin\n", name),
+            Resolution::Hashed(path) => format!("{} is hashed ({})\n", name, path),
+            Resolution::File(path) => format!("{} is {}\n", name, path),
+        }
+    }
+}
+
+// The first way `name` would be run, or with `all` every one of them including each PATH
+// directory's copy; `path_only` leaves out aliases, keywords and builtins
+fn resolve(
+    name: &str,
+    commands: &mut CommandIndex,
+    aliases: &Aliases,
+    all: bool,
+    path_only: bool,
+) -> Vec<Resolution> {
+    let mut found = Vec::new();
+    if !path_only {
+        if let Some(value) = aliases.get(name) {
+            found.push(Resolution::Alias(value.to_string()));
+        }
+        if builtins::is_keyword(name) {
+            found.push(Resolution::Keyword);
+        }
+        if builtins::is_builtin(name) {
+            found.push(Resolution::Builtin);
+        }
+    }
+    if !all && !found.is_empty() {
+        found.truncate(1);
+        return found;
+    }
+
+    if name.contains('/') {
+        if is_executable_file(name) {
+            found.push(Resolution::File(name.to_string()));
+        }
+    } else if all {
+        // As in bash, `-a` lists the PATH search results rather than the remembered location
+        found.extend(commands.search_all(name).into_iter().map(Resolution::File));
+    } else if let Some(path) = commands.hashed_path(name) {
+        found.push(Resolution::Hashed(path.to_string()));
+    } else if let Some(path) = commands.lookup(name) {
+        found.push(Resolution::File(path));
+    }
+    found
+}
+
+// An index of the default search path, so `command -p` finds the standard utilities whatever
+// PATH is set to
+pub fn default_path_index() -> CommandIndex {
+    let paths: Vec<String> = DEFAULT_PATH.iter().map(|path| path.to_string()).collect();
+    CommandIndex::new(&paths)
+}
+
+fn is_executable_file(path: &str) -> bool {
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+// Leading `-xyz` flag groups, stopping at `--` or the first name
+fn parse_flags<'a>(
+    name: &str,
+    usage: &str,
+    valid: &str,
+    args: &'a [String],
+) -> Result<(Vec<char>, &'a [String]), CommandOutput> {
+    let mut flags = Vec::new();
+    let mut index = 0;
+    while let Some(arg) = args.get(index) {
+        if arg == "--" {
+            index += 1;
+            break;
+        }
+        if arg == "-" || !arg.starts_with('-') {
+            break;
+        }
+        for flag in arg.chars().skip(1) {
+            if !valid.contains(flag) {
+                return Err(CommandOutput::stderr(format!(
+                    "{}: -{}: invalid option\n{}: usage: {}\n",
+                    name, flag, name, usage
+                ))
+                .with_status(2));
+            }
+            flags.push(flag);
+        }
+        index += 1;
+    }
+    Ok((flags, &args[index..]))
+}
+
+// `type [-afptP] name ...`; `-f` is accepted for bash compatibility, there being no functions
+pub fn type_command(
+    args: &[String],
+    commands: &mut CommandIndex,
+    aliases: &Aliases,
+) -> CommandOutput {
+    let (flags, names) = match parse_flags("type", "type [-afptP] name [name ...]", "afptP", args) {
+        Ok(parsed) => parsed,
+        Err(output) => return output,
+    };
+    let all = flags.contains(&'a');
+    let kind_only = flags.contains(&'t');
+    let force_path = flags.contains(&'P');
+    let path_only = force_path || flags.contains(&'p');
+
+    let mut output = CommandOutput::default();
+    for name in names {
+        let found = resolve(name, commands, aliases, all, force_path);
+        if found.is_empty() {
+            if !kind_only && !path_only {
+                output.stderr += &format!("type: {}: not found\n", name);
+            }
+            output.status = 1;
+            continue;
+        }
+        for resolution in &found {
+            if kind_only {
+                output.push_stdout(&format!("{}\n", resolution.kind()));
+            } else if path_only {
+                if let Some(path) = resolution.path() {
+                    output.push_stdout(&format!("{}\n", path));
+                }
+            } else {
+                output.push_stdout(&resolution.describe(name));
+            }
+        }
+    }
+    output
+}
+
+// `command -v` prints how each name would be run in a form that can be reused, `command -V`
+// describes it as `type` does, and with `-p` either searches the default path instead of PATH;
+// running a command through `command` is handled by the shell
+pub fn command(args: &[String], commands: &mut CommandIndex, aliases: &Aliases) -> CommandOutput {
+    let usage = "command [-pVv] command [arg ...]";
+    let (flags, names) = match parse_flags("command", usage, "pvV", args) {
+        Ok(parsed) => parsed,
+        Err(output) => return output,
+    };
+    let verbose = flags.contains(&'V');
+    let mut default_commands;
+    let commands = if flags.contains(&'p') {
+        default_commands = default_path_index();
+        &mut default_commands
+    } else {
+        commands
+    };
+
+    let mut output = CommandOutput::default();
+    for name in names {
+        let Some(resolution) = resolve(name, commands, aliases, false, false).pop() else {
+            if verbose {
+                output.stderr += &format!("command: {}: not found\n", name);
+            }
+            output.status = 1;
+            continue;
+        };
+        output.push_stdout(&match (&resolution, verbose) {
+            (_, true) => resolution.describe(name),
+            (Resolution::Alias(value), false) => alias::format_definition(name, value),
+            (Resolution::Hashed(path) | Resolution::File(path), false) => format!("{}\n", path),
+            _ => format!("{}\n", name),
+        });
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn type_of(args: &[&str], commands: &mut CommandIndex, aliases: &Aliases) -> CommandOutput {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        type_command(&args, commands, aliases)
+    }
+
+    fn stdout(output: &CommandOutput) -> String {
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    #[test]
+    fn type_reports_each_way_a_name_runs() {
+        let base = env::temp_dir().join(format!("shell-rs-type-{}", std::process::id()));
+        let (first, second) = (base.join("first"), base.join("second"));
+        for path in [first.join("tool"), second.join("tool"), second.join("echo")] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "#!/bin/sh\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let paths = [first.clone(), second.clone()].map(|path| path.display().to_string());
+        let mut commands = CommandIndex::new(&paths);
+        let mut aliases = Aliases::new();
+        aliases.alias(&["ll=ls -l".to_string()]);
+        let tool = format!("{}/tool", paths[0]);
+        let echo = format!("{}/echo", paths[1]);
+
+        let output = type_of(&["ll", "echo", "[[", "tool"], &mut commands, &aliases);
+        assert_eq!(
+            stdout(&output),
+            format!(
+                "ll is aliased to `ls -l'\necho is a shell builtin\n[[ is a shell keyword\n\
+                 tool is {}\n",
+                tool
+            )
+        );
+        commands.hash_command("tool");
+        let output = type_of(&["tool"], &mut commands, &aliases);
+        assert_eq!(stdout(&output), format!("tool is hashed ({})\n", tool));
+
+        // -a lists every PATH copy, not the hashed one
+        let output = type_of(&["-a", "tool", "echo"], &mut commands, &aliases);
+        assert_eq!(
+            stdout(&output),
+            format!(
+                "tool is {}\ntool is {}/tool\necho is a shell builtin\necho is {}\n",
+                tool, paths[1], echo
+            )
+        );
+
+        let output = type_of(
+            &["-t", "ll", "echo", "tool", "missing"],
+            &mut commands,
+            &aliases,
+        );
+        assert_eq!(stdout(&output), "alias\nbuiltin\nfile\n");
+        assert_eq!((output.stderr.as_str(), output.status), ("", 1));
+
+        // -p prints nothing for a builtin, while -P searches PATH regardless
+        let output = type_of(&["-p", "echo", "tool"], &mut commands, &aliases);
+        assert_eq!(stdout(&output), format!("{}\n", tool));
+        let output = type_of(&["-P", "echo"], &mut commands, &aliases);
+        assert_eq!(stdout(&output), format!("{}\n", echo));
+
+        let output = type_of(&["missing"], &mut commands, &aliases);
+        assert_eq!(
+            (output.stderr.as_str(), output.status),
+            ("type: missing: not found\n", 1)
+        );
+        assert_eq!(type_of(&["-x", "ll"], &mut commands, &aliases).status, 2);
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,518 @@
+use std::ffi::CString;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use regex::Regex;
+
+use crate::output::CommandOutput;
+use crate::parser::Word;
+use crate::pattern;
+use crate::variables::Variables;
+
+const UNARY_OPERATORS: &[&str] = &[
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-n", "-p", "-r", "-s", "-t", "-u",
+    "-v", "-w", "-x", "-z", "-G", "-L", "-N", "-O", "-S",
+];
+const BINARY_OPERATORS: &[&str] = &[
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef",
+];
+
+fn is_unary_operator(token: &str) -> bool {
+    UNARY_OPERATORS.contains(&token)
+}
+
+fn is_binary_operator(token: &str) -> bool {
+    BINARY_OPERATORS.contains(&token)
+}
+
+fn unary_test(operator: &str, operand: &str, variables: &Variables) -> Result<bool, String> {
+    let result = match operator {
+        "-n" => !operand.is_empty(),
+        "-z" => operand.is_empty(),
+        "-v" => variables.lookup(operand).is_some(),
+        "-t" => {
+            let fd = parse_integer(operand)?;
+            i32::try_from(fd).is_ok_and(|fd| unsafe { libc::isatty(fd) } == 1)
+        }
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|m| m.file_type().is_symlink()),
+        "-r" => is_accessible(operand, libc::R_OK),
+        "-w" => is_accessible(operand, libc::W_OK),
+        "-x" => is_accessible(operand, libc::X_OK),
+        _ => match fs::metadata(operand) {
+            Ok(metadata) => file_test(operator, &metadata),
+            Err(_) => false,
+        },
+    };
+    Ok(result)
+}
+
+fn file_test(operator: &str, metadata: &Metadata) -> bool {
+    let file_type = metadata.file_type();
+    match operator {
+        "-a" | "-e" => true,
+        "-b" => file_type.is_block_device(),
+        "-c" => file_type.is_char_device(),
+        "-d" => file_type.is_dir(),
+        "-f" => file_type.is_file(),
+        "-p" => file_type.is_fifo(),
+        "-S" => file_type.is_socket(),
+        "-s" => metadata.len() > 0,
+        "-g" => metadata.mode() & 0o2000 != 0,
+        "-u" => metadata.mode() & 0o4000 != 0,
+        "-k" => metadata.mode() & 0o1000 != 0,
+        "-O" => metadata.uid() == unsafe { libc::geteuid() },
+        "-G" => metadata.gid() == unsafe { libc::getegid() },
+        "-N" => metadata.mtime() > metadata.atime(),
+        _ => false,
+    }
+}
+
+fn is_accessible(path: &str, mode: libc::c_int) -> bool {
+    match CString::new(path) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+fn binary_test(left: &str, operator: &str, right: &str) -> Result<bool, String> {
+    let result = match operator {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-nt" | "-ot" => {
+            let left_time = fs::metadata(left).and_then(|m| m.modified()).ok();
+            let right_time = fs::metadata(right).and_then(|m| m.modified()).ok();
+            let (newer, older) = if operator == "-nt" {
+                (left_time, right_time)
+            } else {
+                (right_time, left_time)
+            };
+            match (newer, older) {
+                (Some(newer), Some(older)) => newer > older,
+                (Some(_), None) => true,
+                _ => false,
+            }
+        }
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        },
+        _ => {
+            let (left, right) = (parse_integer(left)?, parse_integer(right)?);
+            match operator {
+                "-eq" => left == right,
+                "-ne" => left != right,
+                "-lt" => left < right,
+                "-le" => left <= right,
+                "-gt" => left > right,
+                _ => left >= right,
+            }
+        }
+    };
+    Ok(result)
+}
+
+fn parse_integer(text: &str) -> Result<i64, String> {
+    text.trim()
+        .parse()
+        .map_err(|_| format!("{}: integer expression expected", text))
+}
+
+// `test` and `[`, following the POSIX rules that pick a parse by argument count
+pub fn test(name: &str, args: &[String], variables: &Variables) -> CommandOutput {
+    let args = if name == "[" {
+        match args.split_last() {
+            Some((last, rest)) if last == "]" => rest,
+            _ => return CommandOutput::stderr("[: missing `]'\n").with_status(2),
+        }
+    } else {
+        args
+    };
+
+    let evaluator = TestEvaluator {
+        args,
+        position: 0,
+        variables,
+    };
+    match evaluator.evaluate() {
+        Ok(result) => CommandOutput::default().with_status(i32::from(!result)),
+        Err(message) => CommandOutput::stderr(format!("{}: {}\n", name, message)).with_status(2),
+    }
+}
+
+struct TestEvaluator<'a> {
+    args: &'a [String],
+    position: usize,
+    variables: &'a Variables,
+}
+
+impl TestEvaluator<'_> {
+    fn evaluate(mut self) -> Result<bool, String> {
+        let args = self.args;
+        match args.len() {
+            0 => Ok(false),
+            1 => Ok(!args[0].is_empty()),
+            2 => self.two_arguments(args),
+            3 => self.three_arguments(args),
+            4 if args[0] == "!" => self.three_arguments(&args[1..]).map(|result| !result),
+            4 if args[0] == "(" && args[3] == ")" => self.two_arguments(&args[1..3]),
+            _ => {
+                let result = self.or_expression()?;
+                match self.args.get(self.position) {
+                    Some(_) => Err("too many arguments".to_string()),
+                    None => Ok(result),
+                }
+            }
+        }
+    }
+
+    fn two_arguments(&self, args: &[String]) -> Result<bool, String> {
+        if args[0] == "!" {
+            Ok(args[1].is_empty())
+        } else if is_unary_operator(&args[0]) {
+            unary_test(&args[0], &args[1], self.variables)
+        } else {
+            Err(format!("{}: unary operator expected", args[0]))
+        }
+    }
+
+    fn three_arguments(&self, args: &[String]) -> Result<bool, String> {
+        match args[1].as_str() {
+            operator if is_binary_operator(operator) => binary_test(&args[0], operator, &args[2]),
+            "-a" => Ok(!args[0].is_empty() && !args[2].is_empty()),
+            "-o" => Ok(!args[0].is_empty() || !args[2].is_empty()),
+            _ if args[0] == "!" => self.two_arguments(&args[1..]).map(|result| !result),
+            _ if args[0] == "(" && args[2] == ")" => Ok(!args[1].is_empty()),
+            operator => Err(format!("{}: binary operator expected", operator)),
+        }
+    }
+
+    fn peek(&self, offset: usize) -> Option<&str> {
+        self.args.get(self.position + offset).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Result<&'_ str, String> {
+        let token = self
+            .args
+            .get(self.position)
+            .ok_or_else(|| "argument expected".to_string())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn or_expression(&mut self) -> Result<bool, String> {
+        let mut result = self.and_expression()?;
+        while self.peek(0) == Some("-o") {
+            self.position += 1;
+            result |= self.and_expression()?;
+        }
+        Ok(result)
+    }
+
+    fn and_expression(&mut self) -> Result<bool, String> {
+        let mut result = self.not_expression()?;
+        while self.peek(0) == Some("-a") {
+            self.position += 1;
+            result &= self.not_expression()?;
+        }
+        Ok(result)
+    }
+
+    fn not_expression(&mut self) -> Result<bool, String> {
+        if self.peek(0) == Some("!") {
+            self.position += 1;
+            return self.not_expression().map(|result| !result);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        if self.peek(0) == Some("(") {
+            self.position += 1;
+            let result = self.or_expression()?;
+            if self.peek(0) != Some(")") {
+                return Err("`)' expected".to_string());
+            }
+            self.position += 1;
+            return Ok(result);
+        }
+
+        if let Some(operator) = self.peek(1).filter(|op| is_binary_operator(op)) {
+            let operator = operator.to_string();
+            let left = self.next()?.to_string();
+            self.position += 1;
+            let right = self.next()?.to_string();
+            return binary_test(&left, &operator, &right);
+        }
+
+        let token = self.next()?.to_string();
+        if is_unary_operator(&token) && self.peek(0).is_some() {
+            let operand = self.next()?.to_string();
+            return unary_test(&token, &operand, self.variables);
+        }
+        Ok(!token.is_empty())
+    }
+}
+
+// `[[ ... ]]`: no word splitting, pattern matching on `==`/`!=` and regex matching on `=~`
+pub fn conditional(words: &[Word], variables: &mut Variables) -> CommandOutput {
+    let Some((_, words)) = words.split_last().filter(|(last, _)| is_operator(last, "]]")) else {
+        return conditional_error("unexpected end of file, expected `]]'");
+    };
+
+    let mut evaluator = ConditionalEvaluator {
+        words,
+        position: 0,
+        variables,
+        skipping: false,
+    };
+    let result = evaluator.or_expression().and_then(|result| {
+        match evaluator.words.get(evaluator.position) {
+            Some(word) => Err(format!("unexpected token `{}'", word.text)),
+            None => Ok(result),
+        }
+    });
+
+    match result {
+        Ok(result) => CommandOutput::default().with_status(i32::from(!result)),
+        Err(message) => conditional_error(&message),
+    }
+}
+
+fn conditional_error(message: &str) -> CommandOutput {
+    CommandOutput::stderr(format!(
+        "syntax error in conditional expression: {}\n",
+        message
+    ))
+    .with_status(2)
+}
+
+// Operators only count when written without quotes
+fn is_operator(word: &Word, operator: &str) -> bool {
+    word.text == operator && !word.quoted.iter().any(|&quoted| quoted)
+}
+
+fn glob_pattern(word: &Word) -> String {
+    let mut pattern = String::new();
+    for (c, &quoted) in word.text.chars().zip(&word.quoted) {
+        if quoted && matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+fn regex_pattern(word: &Word) -> String {
+    let mut pattern = String::new();
+    for (c, &quoted) in word.text.chars().zip(&word.quoted) {
+        if quoted {
+            pattern.push_str(&regex::escape(&c.to_string()));
+        } else {
+            pattern.push(c);
+        }
+    }
+    pattern
+}
+
+struct ConditionalEvaluator<'a> {
+    words: &'a [Word],
+    position: usize,
+    variables: &'a mut Variables,
+    // Set while parsing the right side of an `&&` or `||` whose result is already known
+    skipping: bool,
+}
+
+impl<'a> ConditionalEvaluator<'a> {
+    fn peek_operator(&self, operator: &str) -> bool {
+        self.words
+            .get(self.position)
+            .is_some_and(|word| is_operator(word, operator))
+    }
+
+    fn next(&mut self) -> Result<&'a Word, String> {
+        let words = self.words;
+        let word = words
+            .get(self.position)
+            .ok_or_else(|| "unexpected end of expression".to_string())?;
+        self.position += 1;
+        Ok(word)
+    }
+
+    fn or_expression(&mut self) -> Result<bool, String> {
+        let mut result = self.and_expression()?;
+        while self.peek_operator("||") {
+            self.position += 1;
+            let right = self.parse_skipped(result, Self::and_expression)?;
+            result = result || right;
+        }
+        Ok(result)
+    }
+
+    fn and_expression(&mut self) -> Result<bool, String> {
+        let mut result = self.not_expression()?;
+        while self.peek_operator("&&") {
+            self.position += 1;
+            let right = self.parse_skipped(!result, Self::not_expression)?;
+            result = result && right;
+        }
+        Ok(result)
+    }
+
+    // Parses an operand without evaluating it when `skip` is set, so it has no side effects
+    fn parse_skipped(
+        &mut self,
+        skip: bool,
+        parse: fn(&mut Self) -> Result<bool, String>,
+    ) -> Result<bool, String> {
+        let skipping = self.skipping;
+        self.skipping = skipping || skip;
+        let result = parse(self);
+        self.skipping = skipping;
+        result
+    }
+
+    fn not_expression(&mut self) -> Result<bool, String> {
+        if self.peek_operator("!") {
+            self.position += 1;
+            return self.not_expression().map(|result| !result);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, String> {
+        if self.peek_operator("(") {
+            self.position += 1;
+            let result = self.or_expression()?;
+            if !self.peek_operator(")") {
+                return Err("expected `)'".to_string());
+            }
+            self.position += 1;
+            return Ok(result);
+        }
+
+        let words = self.words;
+        let first = self.next()?;
+        let binary = words
+            .get(self.position)
+            .filter(|word| !word.quoted.iter().any(|&quoted| quoted))
+            .map(|word| word.text.as_str())
+            .filter(|op| is_binary_operator(op) || *op == "=~");
+        if let Some(operator) = binary {
+            self.position += 1;
+            let right = self.next()?;
+            if self.skipping {
+                return Ok(false);
+            }
+            return self.binary(first, operator, right);
+        }
+
+        if is_unary_operator(&first.text) && !first.quoted.iter().any(|&quoted| quoted) {
+            if let Some(operand) = words.get(self.position) {
+                self.position += 1;
+                if self.skipping {
+                    return Ok(false);
+                }
+                return unary_test(&first.text, &operand.text, self.variables);
+            }
+        }
+        Ok(!first.text.is_empty())
+    }
+
+    fn binary(&mut self, left: &Word, operator: &str, right: &Word) -> Result<bool, String> {
+        match operator {
+            "=" | "==" => Ok(pattern::matches(&glob_pattern(right), &left.text)),
+            "!=" => Ok(!pattern::matches(&glob_pattern(right), &left.text)),
+            "=~" => self.regex_match(&left.text, &regex_pattern(right)),
+            _ => binary_test(&left.text, operator, &right.text),
+        }
+    }
+
+    fn regex_match(&mut self, text: &str, pattern: &str) -> Result<bool, String> {
+        let regex = Regex::new(pattern).map_err(|_| format!("invalid regular expression `{}'", pattern))?;
+        let Some(captures) = regex.captures(text) else {
+            self.variables.set_array("BASH_REMATCH", Vec::new());
+            return Ok(false);
+        };
+
+        let groups = captures
+            .iter()
+            .map(|group| group.map_or(String::new(), |m| m.as_str().to_string()))
+            .collect();
+        self.variables.set_array("BASH_REMATCH", groups);
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::parser::{parse_conditional_words, WordExpander};
+
+    struct Values(HashMap<&'static str, &'static str>);
+
+    impl WordExpander for Values {
+        fn expand_tilde(&self, _prefix: &str) -> Option<String> {
+            None
+        }
+
+        fn lookup_variable(&self, name: &str) -> Option<String> {
+            self.0.get(name).map(|value| value.to_string())
+        }
+    }
+
+    fn status(expression: &str, values: &[(&'static str, &'static str)]) -> i32 {
+        let expander = Values(values.iter().copied().collect());
+        let words = parse_conditional_words(&format!("{} ]]", expression), &expander);
+        conditional(&words, &mut Variables::new()).status
+    }
+
+    #[test]
+    fn unset_and_empty_operands_are_empty_words() {
+        assert_eq!(status("-n $x", &[]), 1);
+        assert_eq!(status("-z $x", &[]), 0);
+        assert_eq!(status("-n $x", &[("x", "")]), 1);
+        assert_eq!(status("-z $x", &[("x", "")]), 0);
+        assert_eq!(status("$x == foo*", &[]), 1);
+        assert_eq!(status("$x == ''", &[]), 0);
+        assert_eq!(status("$x != foo", &[("x", "")]), 0);
+    }
+
+    #[test]
+    fn expansions_are_not_split() {
+        assert_eq!(status("$x == 'a b'", &[("x", "a b")]), 0);
+        assert_eq!(status("-n $x", &[("x", "a b")]), 0);
+    }
+
+    #[test]
+    fn patterns_match_unless_quoted() {
+        assert_eq!(status("foobar == foo*", &[]), 0);
+        assert_eq!(status("foobar == 'foo*'", &[]), 1);
+        assert_eq!(status("abc =~ ^a.c$", &[]), 0);
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        // The right sides would be errors if they were evaluated
+        assert_eq!(status("a == a || 1 -eq x", &[]), 0);
+        assert_eq!(status("a == b && 1 -eq x", &[]), 1);
+        assert_eq!(status("a == a && 1 -eq x", &[]), 2);
+        assert_eq!(status("! a == b && ( a == c || b == b )", &[]), 0);
+    }
+
+    #[test]
+    fn skipped_regex_does_not_set_rematch() {
+        let expander = Values(HashMap::new());
+        let words = parse_conditional_words("a == a || abc =~ (b) ]]", &expander);
+        let mut variables = Variables::new();
+        assert_eq!(conditional(&words, &mut variables).status, 0);
+        assert!(variables.lookup("BASH_REMATCH").is_none());
+
+        let words = parse_conditional_words("abc =~ (b) ]]", &expander);
+        assert_eq!(conditional(&words, &mut variables).status, 0);
+        assert_eq!(variables.lookup("BASH_REMATCH[1]").as_deref(), Some("b"));
+    }
+}
@@ -0,0 +1,374 @@
+use crate::builtins;
+use crate::output::CommandOutput;
+use crate::utils;
+
+#[derive(Default)]
+pub struct DirectoryStack {
+    entries: Vec<String>,
+}
+
+impl DirectoryStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, args: &[String]) -> CommandOutput {
+        let (no_change, operands) = split_no_change_flag(args);
+        if operands.len() > 1 {
+            return CommandOutput::stderr("pushd: too many arguments\n");
+        }
+
+        let result = match operands.first() {
+            None => self.exchange(no_change),
+            Some(operand) if parse_stack_index(operand).is_some() => {
+                self.rotate(operand, no_change)
+            }
+            Some(directory) => self.push_directory(directory, no_change),
+        };
+
+        match result {
+            Ok(()) => self.listing(false),
+            Err(message) => CommandOutput::stderr(format!("pushd: {}\n", message)),
+        }
+    }
+
+    pub fn pop(&mut self, args: &[String]) -> CommandOutput {
+        let (no_change, operands) = split_no_change_flag(args);
+        if operands.len() > 1 {
+            return CommandOutput::stderr("popd: too many arguments\n");
+        }
+        if self.entries.is_empty() {
+            return CommandOutput::stderr("popd: directory stack empty\n");
+        }
+
+        let index = match operands.first() {
+            None => 0,
+            Some(operand) => match parse_stack_index(operand) {
+                Some(index) => match self.resolve_index(index) {
+                    Some(index) => index,
+                    None => return index_out_of_range("popd", operand),
+                },
+                None => {
+                    return CommandOutput::stderr(format!(
+                        "popd: {}: invalid argument\npopd: usage: popd [-n] [+N | -N]\n",
+                        operand
+                    ))
+                }
+            },
+        };
+
+        if index == 0 && !no_change {
+            let target = self.entries[0].clone();
+            if let Err(e) = builtins::change_working_directory(&target, false) {
+                return CommandOutput::stderr(format!(
+                    "popd: {}: {}\n",
+                    target,
+                    utils::io_error_message(&e)
+                ));
+            }
+        }
+        self.entries.remove(index.saturating_sub(1));
+
+        self.listing(false)
+    }
+
+    pub fn dirs(&mut self, args: &[String]) -> CommandOutput {
+        let mut clear = false;
+        let mut long = false;
+        let mut one_per_line = false;
+        let mut verbose = false;
+        let mut selected = None;
+
+        for arg in args {
+            if let Some(index) = parse_stack_index(arg) {
+                selected = Some((arg, index));
+                continue;
+            }
+            let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+                return CommandOutput::stderr(format!(
+                    "dirs: {}: invalid argument\ndirs: usage: dirs [-clpv] [+N] [-N]\n",
+                    arg
+                ));
+            };
+            for flag in flags.chars() {
+                match flag {
+                    'c' => clear = true,
+                    'l' => long = true,
+                    'p' => one_per_line = true,
+                    'v' => verbose = true,
+                    _ => {
+                        return CommandOutput::stderr(format!(
+                            "dirs: -{}: invalid option\ndirs: usage: dirs [-clpv] [+N] [-N]\n",
+                            flag
+                        ))
+                    }
+                }
+            }
+        }
+
+        if clear {
+            self.entries.clear();
+            return CommandOutput::default();
+        }
+
+        if let Some((operand, index)) = selected {
+            let directories = self.directories();
+            return match self.resolve_index(index) {
+                Some(index) => {
+                    CommandOutput::stdout(format!("{}\n", display(&directories[index], long)))
+                }
+                None => index_out_of_range("dirs", operand),
+            };
+        }
+
+        if verbose {
+            let listing: String = self
+                .directories()
+                .iter()
+                .enumerate()
+                .map(|(index, dir)| format!("{:2}  {}\n", index, display(dir, long)))
+                .collect();
+            return CommandOutput::stdout(listing);
+        }
+        if one_per_line {
+            let listing: String = self
+                .directories()
+                .iter()
+                .map(|dir| format!("{}\n", display(dir, long)))
+                .collect();
+            return CommandOutput::stdout(listing);
+        }
+        self.listing(long)
+    }
+
+    // Resolves the `N`, `+N` and `-N` forms of `~N` tilde prefixes
+    pub fn tilde_entry(&self, prefix: &str) -> Option<String> {
+        let index = if prefix.starts_with(['+', '-']) {
+            parse_stack_index(prefix)?
+        } else {
+            StackIndex::FromTop(prefix.parse().ok()?)
+        };
+        let index = self.resolve_index(index)?;
+        self.directories().into_iter().nth(index)
+    }
+
+    fn exchange(&mut self, no_change: bool) -> Result<(), String> {
+        if self.entries.is_empty() {
+            return Err("no other directory".to_string());
+        }
+        if no_change {
+            return Ok(());
+        }
+
+        let target = self.entries[0].clone();
+        let old_pwd = builtins::logical_working_directory();
+        change_to(&target)?;
+        self.entries[0] = old_pwd;
+        Ok(())
+    }
+
+    fn rotate(&mut self, operand: &str, no_change: bool) -> Result<(), String> {
+        let index = parse_stack_index(operand)
+            .and_then(|index| self.resolve_index(index))
+            .ok_or_else(|| format!("{}: directory stack index out of range", operand))?;
+        if index == 0 {
+            return Ok(());
+        }
+
+        if no_change {
+            self.entries.rotate_left(index - 1);
+            return Ok(());
+        }
+
+        let mut directories = self.directories();
+        directories.rotate_left(index);
+        change_to(&directories[0])?;
+        self.entries = directories.split_off(1);
+        Ok(())
+    }
+
+    fn push_directory(&mut self, directory: &str, no_change: bool) -> Result<(), String> {
+        if no_change {
+            self.entries.insert(0, directory.to_string());
+            return Ok(());
+        }
+
+        let old_pwd = builtins::logical_working_directory();
+        change_to(directory)?;
+        self.entries.insert(0, old_pwd);
+        Ok(())
+    }
+
+    fn directories(&self) -> Vec<String> {
+        let mut directories = vec![builtins::logical_working_directory()];
+        directories.extend(self.entries.iter().cloned());
+        directories
+    }
+
+    fn resolve_index(&self, index: StackIndex) -> Option<usize> {
+        let len = self.entries.len() + 1;
+        match index {
+            StackIndex::FromTop(n) => (n < len).then_some(n),
+            StackIndex::FromBottom(n) => (n < len).then(|| len - 1 - n),
+        }
+    }
+
+    fn listing(&self, long: bool) -> CommandOutput {
+        let listing = self
+            .directories()
+            .iter()
+            .map(|dir| display(dir, long))
+            .collect::<Vec<_>>()
+            .join(" ");
+        CommandOutput::stdout(format!("{}\n", listing))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum StackIndex {
+    FromTop(usize),
+    FromBottom(usize),
+}
+
+fn parse_stack_index(arg: &str) -> Option<StackIndex> {
+    let parse_digits = |digits: &str| {
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+
+    if let Some(digits) = arg.strip_prefix('+') {
+        parse_digits(digits).map(StackIndex::FromTop)
+    } else if let Some(digits) = arg.strip_prefix('-') {
+        parse_digits(digits).map(StackIndex::FromBottom)
+    } else {
+        None
+    }
+}
+
+fn split_no_change_flag(args: &[String]) -> (bool, Vec<&String>) {
+    let no_change = args.iter().any(|arg| arg == "-n");
+    let operands = args.iter().filter(|arg| *arg != "-n").collect();
+    (no_change, operands)
+}
+
+fn change_to(directory: &str) -> Result<(), String> {
+    builtins::change_working_directory(directory, false)
+        .map(|_| ())
+        .map_err(|e| format!("{}: {}", directory, utils::io_error_message(&e)))
+}
+
+fn display(directory: &str, long: bool) -> String {
+    if long {
+        directory.to_string()
+    } else {
+        utils::abbreviate_home(directory)
+    }
+}
+
+fn index_out_of_range(name: &str, operand: &str) -> CommandOutput {
+    CommandOutput::stderr(format!(
+        "{}: {}: directory stack index out of range\n",
+        name, operand
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    fn run(
+        command: fn(&mut DirectoryStack, &[String]) -> CommandOutput,
+        stack: &mut DirectoryStack,
+        args: &[&str],
+    ) -> CommandOutput {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        command(stack, &args)
+    }
+
+    fn listing(stack: &mut DirectoryStack) -> String {
+        String::from_utf8(run(DirectoryStack::dirs, stack, &["-l"]).stdout).unwrap()
+    }
+
+    #[test]
+    fn keeps_entries_without_changing_directory() {
+        let _lock = builtins::ENVIRONMENT_LOCK.lock().unwrap();
+        let cwd = builtins::logical_working_directory();
+        let mut stack = DirectoryStack::new();
+        run(DirectoryStack::push, &mut stack, &["-n", "/a"]);
+        run(DirectoryStack::push, &mut stack, &["-n", "/b"]);
+        assert_eq!(listing(&mut stack), format!("{} /b /a\n", cwd));
+
+        run(DirectoryStack::push, &mut stack, &["-n", "+2"]);
+        assert_eq!(listing(&mut stack), format!("{} /a /b\n", cwd));
+        assert_eq!(stack.tilde_entry("1").as_deref(), Some("/a"));
+        assert_eq!(stack.tilde_entry("-0").as_deref(), Some("/b"));
+        assert_eq!(stack.tilde_entry("3"), None);
+        assert_eq!(
+            run(DirectoryStack::dirs, &mut stack, &["+1"]).stdout,
+            b"/a\n"
+        );
+        let verbose = run(DirectoryStack::dirs, &mut stack, &["-lv"]).stdout;
+        assert_eq!(
+            verbose,
+            format!(" 0  {}\n 1  /a\n 2  /b\n", cwd).into_bytes()
+        );
+
+        run(DirectoryStack::pop, &mut stack, &["-n"]);
+        assert_eq!(listing(&mut stack), format!("{} /b\n", cwd));
+        assert_eq!(
+            run(DirectoryStack::pop, &mut stack, &["+5"]).stderr,
+            "popd: +5: directory stack index out of range\n"
+        );
+        run(DirectoryStack::dirs, &mut stack, &["-c"]);
+        assert_eq!(
+            run(DirectoryStack::pop, &mut stack, &[]).stderr,
+            "popd: directory stack empty\n"
+        );
+        assert_eq!(builtins::logical_working_directory(), cwd);
+    }
+
+    #[test]
+    fn pushd_and_popd_change_directory() {
+        let _lock = builtins::ENVIRONMENT_LOCK.lock().unwrap();
+        let original = builtins::logical_working_directory();
+        let base = env::temp_dir().join(format!("shell-rs-dirstack-{}", std::process::id()));
+        fs::create_dir_all(base.join("one")).unwrap();
+        fs::create_dir_all(base.join("two")).unwrap();
+        let one = base.join("one").to_string_lossy().into_owned();
+        let two = base.join("two").to_string_lossy().into_owned();
+        let mut stack = DirectoryStack::new();
+
+        run(DirectoryStack::push, &mut stack, &[&one]);
+        run(DirectoryStack::push, &mut stack, &[&two]);
+        assert_eq!(
+            listing(&mut stack),
+            format!("{} {} {}\n", two, one, original)
+        );
+
+        // With no operand the top two entries swap
+        run(DirectoryStack::push, &mut stack, &[]);
+        assert_eq!(builtins::logical_working_directory(), one);
+        assert_eq!(
+            listing(&mut stack),
+            format!("{} {} {}\n", one, two, original)
+        );
+
+        // `+N` rotates that entry to the top
+        run(DirectoryStack::push, &mut stack, &["+2"]);
+        assert_eq!(builtins::logical_working_directory(), original);
+        assert_eq!(
+            listing(&mut stack),
+            format!("{} {} {}\n", original, one, two)
+        );
+
+        run(DirectoryStack::pop, &mut stack, &[]);
+        assert_eq!(builtins::logical_working_directory(), one);
+        assert_eq!(listing(&mut stack), format!("{} {}\n", one, two));
+
+        builtins::change_working_directory(&original, false).unwrap();
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use std::cmp::Reverse;
+
+// How a candidate matched, best first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchKind {
+    Prefix,
+    CaseInsensitivePrefix,
+    Substring,
+    Subsequence,
+}
+
+const MATCH_SCORE: i32 = 16;
+const BOUNDARY_BONUS: i32 = 8;
+const CONSECUTIVE_BONUS: i32 = 4;
+
+// Matches `pattern` against `candidate` ignoring case, as a prefix, then a substring, then
+// characters in order with anything between; higher scores are better within a kind
+pub fn fuzzy_match(pattern: &str, candidate: &str) -> Option<(MatchKind, i32)> {
+    if candidate.starts_with(pattern) {
+        return Some((MatchKind::Prefix, 0));
+    }
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    let text: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+    if text.starts_with(&pattern) {
+        return Some((MatchKind::CaseInsensitivePrefix, 0));
+    }
+    if let Some(index) = text.windows(pattern.len().max(1)).position(|window| window == pattern) {
+        let boundary = if is_boundary(&text, index) { BOUNDARY_BONUS } else { 0 };
+        return Some((MatchKind::Substring, boundary - index as i32));
+    }
+
+    // Each pattern character takes the next occurrence, scoring word starts and runs higher
+    let mut score = 0;
+    let mut run = 0;
+    let mut previous = None;
+    let mut position = 0;
+    for c in &pattern {
+        let index = position + text[position..].iter().position(|t| t == c)?;
+        score += MATCH_SCORE;
+        if is_boundary(&text, index) {
+            score += BOUNDARY_BONUS;
+        }
+        if previous.is_some_and(|previous| previous + 1 == index) {
+            run += 1;
+            score += CONSECUTIVE_BONUS * run;
+        } else {
+            run = 0;
+            score -= previous.map_or(index, |previous| index - previous - 1) as i32;
+        }
+        previous = Some(index);
+        position = index + 1;
+    }
+    Some((MatchKind::Subsequence, score))
+}
+
+// The candidates that match, best first: by kind, then score, then shorter names
+pub fn rank<'a>(pattern: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut matches: Vec<_> = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let (kind, score) = fuzzy_match(pattern, candidate)?;
+            Some(((kind, Reverse(score), candidate.len(), candidate), candidate))
+        })
+        .collect();
+    matches.sort();
+    matches.dedup_by(|a, b| a.1 == b.1);
+    matches.into_iter().map(|(_, candidate)| candidate).collect()
+}
+
+fn is_boundary(text: &[char], index: usize) -> bool {
+    index == 0 || !text[index - 1].is_alphanumeric()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_prefixes_then_substrings_then_subsequences() {
+        assert_eq!(fuzzy_match("git", "git-log"), Some((MatchKind::Prefix, 0)));
+        assert_eq!(
+            fuzzy_match("GIT", "git-log"),
+            Some((MatchKind::CaseInsensitivePrefix, 0))
+        );
+        assert_eq!(
+            fuzzy_match("log", "git-log"),
+            Some((MatchKind::Substring, 4))
+        );
+        assert_eq!(
+            fuzzy_match("og", "git-log"),
+            Some((MatchKind::Substring, -5))
+        );
+        assert_eq!(
+            fuzzy_match("gl", "git-log"),
+            Some((MatchKind::Subsequence, 45))
+        );
+        assert_eq!(
+            fuzzy_match("lg", "git-log"),
+            Some((MatchKind::Subsequence, 35))
+        );
+        assert_eq!(fuzzy_match("xyz", "git-log"), None);
+    }
+
+    #[test]
+    fn scores_word_starts_and_runs_higher() {
+        let at_boundary = fuzzy_match("gc", "git-commit").unwrap().1;
+        assert!(at_boundary > fuzzy_match("gm", "git-commit").unwrap().1);
+        let in_a_run = fuzzy_match("gim", "gitmo").unwrap().1;
+        assert!(in_a_run > fuzzy_match("gim", "gxixm").unwrap().1);
+    }
+
+    #[test]
+    fn ranks_by_kind_score_and_length() {
+        let candidates = ["git-log", "glob", "gl", "xgl"];
+        assert_eq!(rank("gl", candidates), ["gl", "glob", "xgl", "git-log"]);
+        assert_eq!(rank("zz", candidates), Vec::<&str>::new());
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::git_objects::{self, ObjectId};
+
+pub struct Repository {
+    pub work_tree: PathBuf,
+    // Per-worktree files (HEAD, index) live in `git_dir`; refs, objects and config in `common_dir`
+    pub git_dir: PathBuf,
+    pub common_dir: PathBuf,
+}
+
+pub enum Head {
+    Branch(String),
+    Detached(ObjectId),
+}
+
+const MAX_SYMREF_DEPTH: usize = 5;
+
+impl Repository {
+    // Walks up from `start`; a `.git` file (worktrees, submodules) points at the real git dir
+    pub fn discover(start: &Path) -> Option<Self> {
+        if start
+            .components()
+            .any(|component| component.as_os_str() == ".git")
+        {
+            return None;
+        }
+
+        for directory in start.ancestors() {
+            let dot_git = directory.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else if dot_git.is_file() {
+                let contents = fs::read_to_string(&dot_git).ok()?;
+                let target = contents.trim().strip_prefix("gitdir:")?.trim();
+                directory.join(target)
+            } else {
+                continue;
+            };
+            if !git_dir.join("HEAD").is_file() {
+                return None;
+            }
+
+            let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+                Ok(common) => git_dir.join(common.trim()),
+                Err(_) => git_dir.clone(),
+            };
+            return Some(Self {
+                work_tree: directory.to_path_buf(),
+                git_dir,
+                common_dir,
+            });
+        }
+        None
+    }
+
+    pub fn head(&self) -> Option<Head> {
+        let contents = fs::read_to_string(self.git_dir.join("HEAD")).ok()?;
+        match contents.trim().strip_prefix("ref:") {
+            Some(reference) => {
+                let reference = reference.trim();
+                Some(Head::Branch(
+                    reference
+                        .strip_prefix("refs/heads/")
+                        .unwrap_or(reference)
+                        .to_string(),
+                ))
+            }
+            None => git_objects::parse_hex(&contents).map(Head::Detached),
+        }
+    }
+
+    // Resolves a full ref name such as `HEAD` or `refs/heads/main`, following symbolic refs
+    pub fn resolve(&self, name: &str) -> Option<ObjectId> {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            let contents = self
+                .read_loose_ref(&name)
+                .or_else(|| self.packed_ref(&name))?;
+            match contents.trim().strip_prefix("ref:") {
+                Some(target) => name = target.trim().to_string(),
+                None => return git_objects::parse_hex(&contents),
+            }
+        }
+        None
+    }
+
+    fn read_loose_ref(&self, name: &str) -> Option<String> {
+        let base = if name.starts_with("refs/") {
+            &self.common_dir
+        } else {
+            &self.git_dir
+        };
+        fs::read_to_string(base.join(name)).ok()
+    }
+
+    fn packed_refs(&self) -> Vec<(String, String)> {
+        let Ok(contents) = fs::read_to_string(self.common_dir.join("packed-refs")) else {
+            return Vec::new();
+        };
+        contents
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .filter_map(|line| line.split_once(' '))
+            .map(|(id, name)| (name.to_string(), id.to_string()))
+            .collect()
+    }
+
+    fn packed_ref(&self, name: &str) -> Option<String> {
+        self.packed_refs()
+            .into_iter()
+            .find(|(packed, _)| packed == name)
+            .map(|(_, id)| id)
+    }
+
+    // Names under `refs/<kind>/` (e.g. `heads`, `tags`, `remotes`), loose and packed, without the prefix
+    pub fn references(&self, kind: &str) -> Vec<String> {
+        let prefix = format!("refs/{}/", kind);
+        let mut names = Vec::new();
+        collect_loose_refs(&self.common_dir.join(&prefix), "", &mut names);
+        names.extend(
+            self.packed_refs()
+                .into_iter()
+                .filter_map(|(name, _)| name.strip_prefix(&prefix).map(String::from)),
+        );
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Names of the `[remote "name"]` sections in the repository config
+    pub fn remotes(&self) -> Vec<String> {
+        let Ok(contents) = fs::read_to_string(self.common_dir.join("config")) else {
+            return Vec::new();
+        };
+        contents
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("[remote \"")?.strip_suffix("\"]"))
+            .map(String::from)
+            .collect()
+    }
+
+    // The value of `section.subsection.key` from the repository config; the last one wins
+    pub fn config_value(
+        &self,
+        section: &str,
+        subsection: Option<&str>,
+        key: &str,
+    ) -> Option<String> {
+        let contents = fs::read_to_string(self.common_dir.join("config")).ok()?;
+        let mut in_section = false;
+        let mut value = None;
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if let Some(header) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                let (name, sub) = match header.split_once(' ') {
+                    Some((name, sub)) => (name, Some(sub.trim().trim_matches('"'))),
+                    None => (header, None),
+                };
+                in_section = name.eq_ignore_ascii_case(section) && sub == subsection;
+                continue;
+            }
+            if !in_section || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let (name, found) = match line.split_once('=') {
+                Some((name, found)) => (name.trim(), found.trim().trim_matches('"')),
+                None => (line, "true"),
+            };
+            if name.eq_ignore_ascii_case(key) {
+                value = Some(found.to_string());
+            }
+        }
+        value
+    }
+
+    // The ref a branch tracks, from `branch.<name>.remote` and `branch.<name>.merge`
+    pub fn upstream(&self, branch: &str) -> Option<String> {
+        let remote = self.config_value("branch", Some(branch), "remote")?;
+        let merge = self.config_value("branch", Some(branch), "merge")?;
+        if remote == "." {
+            return Some(merge);
+        }
+        let merged_branch = merge.strip_prefix("refs/heads/").unwrap_or(&merge);
+        Some(format!("refs/remotes/{}/{}", remote, merged_branch))
+    }
+
+    pub fn objects_dir(&self) -> PathBuf {
+        self.common_dir.join("objects")
+    }
+}
+
+fn collect_loose_refs(directory: &Path, prefix: &str, names: &mut Vec<String>) {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let name = format!("{}{}", prefix, name);
+        if entry.path().is_dir() {
+            collect_loose_refs(&entry.path(), &format!("{}/", name), names);
+        } else {
+            names.push(name);
+        }
+    }
+}
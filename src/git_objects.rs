@@ -0,0 +1,416 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use flate2::read::ZlibDecoder;
+use sha1::{Digest, Sha1};
+
+pub type ObjectId = [u8; 20];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ObjectKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+pub struct Object {
+    pub kind: ObjectKind,
+    pub data: Vec<u8>,
+}
+
+// Reads loose and packed objects straight from `.git/objects`, without running `git`
+pub struct ObjectStore {
+    objects_dir: PathBuf,
+    packs: Vec<Pack>,
+}
+
+struct Pack {
+    file: File,
+    index: Vec<u8>,
+}
+
+const MAX_DELTA_DEPTH: usize = 50;
+
+impl ObjectStore {
+    pub fn open(objects_dir: &Path) -> Self {
+        let mut packs = Vec::new();
+        if let Ok(entries) = fs::read_dir(objects_dir.join("pack")) {
+            for entry in entries.filter_map(Result::ok) {
+                let path = entry.path();
+                if path.extension().is_some_and(|extension| extension == "idx") {
+                    packs.extend(Pack::open(&path));
+                }
+            }
+        }
+
+        Self {
+            objects_dir: objects_dir.to_path_buf(),
+            packs,
+        }
+    }
+
+    pub fn read(&self, id: &ObjectId) -> Option<Object> {
+        self.read_at_depth(id, 0)
+    }
+
+    fn read_at_depth(&self, id: &ObjectId, depth: usize) -> Option<Object> {
+        if let Some(object) = self.read_loose(id) {
+            return Some(object);
+        }
+        self.packs.iter().find_map(|pack| {
+            let offset = pack.find(id)?;
+            self.read_packed(pack, offset, depth)
+        })
+    }
+
+    fn read_loose(&self, id: &ObjectId) -> Option<Object> {
+        let hex = to_hex(id);
+        let compressed = fs::read(self.objects_dir.join(&hex[..2]).join(&hex[2..])).ok()?;
+        let data = inflate_zlib(&compressed, 0)?;
+
+        let header_end = data.iter().position(|&b| b == 0)?;
+        let header = std::str::from_utf8(&data[..header_end]).ok()?;
+        let kind = match header.split(' ').next()? {
+            "commit" => ObjectKind::Commit,
+            "tree" => ObjectKind::Tree,
+            "blob" => ObjectKind::Blob,
+            "tag" => ObjectKind::Tag,
+            _ => return None,
+        };
+        Some(Object {
+            kind,
+            data: data[header_end + 1..].to_vec(),
+        })
+    }
+
+    fn read_packed(&self, pack: &Pack, offset: u64, depth: usize) -> Option<Object> {
+        if depth > MAX_DELTA_DEPTH {
+            return None;
+        }
+
+        let mut header = [0u8; 32];
+        let available = pack.file.read_at(&mut header, offset).ok()?;
+        let header = &header[..available];
+
+        let mut position = 0;
+        let mut byte = *header.get(position)?;
+        let type_code = (byte >> 4) & 0x07;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            position += 1;
+            byte = *header.get(position)?;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+        position += 1;
+
+        let base = match type_code {
+            6 => {
+                let mut byte = *header.get(position)?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    position += 1;
+                    byte = *header.get(position)?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+                }
+                position += 1;
+                Some(self.read_packed(pack, offset.checked_sub(distance)?, depth + 1)?)
+            }
+            7 => {
+                let base_id: ObjectId = header.get(position..position + 20)?.try_into().ok()?;
+                position += 20;
+                Some(self.read_at_depth(&base_id, depth + 1)?)
+            }
+            _ => None,
+        };
+
+        // Deflate output is never much larger than its input, so this window holds the whole stream
+        let mut compressed = vec![0u8; size + size / 4 + 1024];
+        let read = pack
+            .file
+            .read_at(&mut compressed, offset + position as u64)
+            .ok()?;
+        let data = inflate_zlib(&compressed[..read], size)?;
+
+        match base {
+            Some(base) => Some(Object {
+                kind: base.kind,
+                data: apply_delta(&base.data, &data)?,
+            }),
+            None => {
+                let kind = match type_code {
+                    1 => ObjectKind::Commit,
+                    2 => ObjectKind::Tree,
+                    3 => ObjectKind::Blob,
+                    4 => ObjectKind::Tag,
+                    _ => return None,
+                };
+                Some(Object { kind, data })
+            }
+        }
+    }
+}
+
+impl Pack {
+    fn open(index_path: &Path) -> Option<Self> {
+        let index = fs::read(index_path).ok()?;
+        // Only version 2 indexes are written by any supported git
+        if index.get(..8)? != b"\xfftOc\x00\x00\x00\x02" {
+            return None;
+        }
+        let file = File::open(index_path.with_extension("pack")).ok()?;
+        Some(Self { file, index })
+    }
+
+    fn count(&self) -> usize {
+        read_u32(&self.index, 8 + 255 * 4).unwrap_or(0) as usize
+    }
+
+    fn find(&self, id: &ObjectId) -> Option<u64> {
+        let fanout = |byte: usize| read_u32(&self.index, 8 + byte * 4).map(|n| n as usize);
+        let mut low = if id[0] == 0 {
+            0
+        } else {
+            fanout(id[0] as usize - 1)?
+        };
+        let mut high = fanout(id[0] as usize)?;
+        let names = 8 + 256 * 4;
+
+        while low < high {
+            let middle = (low + high) / 2;
+            let name = self
+                .index
+                .get(names + middle * 20..names + middle * 20 + 20)?;
+            match name.cmp(id.as_slice()) {
+                std::cmp::Ordering::Equal => return self.offset(middle),
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+            }
+        }
+        None
+    }
+
+    fn offset(&self, position: usize) -> Option<u64> {
+        let count = self.count();
+        let offsets = 8 + 256 * 4 + count * 24;
+        let offset = read_u32(&self.index, offsets + position * 4)?;
+        if offset & 0x8000_0000 == 0 {
+            return Some(offset as u64);
+        }
+        let large = offsets + count * 4 + (offset & 0x7fff_ffff) as usize * 8;
+        let high = read_u32(&self.index, large)? as u64;
+        let low = read_u32(&self.index, large + 4)? as u64;
+        Some((high << 32) | low)
+    }
+}
+
+fn read_u32(data: &[u8], position: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(position..position + 4)?.try_into().ok()?,
+    ))
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 0;
+    let varint = |position: &mut usize| -> Option<usize> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(*position)?;
+            *position += 1;
+            value |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    };
+    let base_size = varint(&mut position)?;
+    let result_size = varint(&mut position)?;
+    if base_size != base.len() {
+        return None;
+    }
+
+    let mut result = Vec::with_capacity(result_size);
+    while let Some(&instruction) = delta.get(position) {
+        position += 1;
+        if instruction & 0x80 != 0 {
+            let mut fields = [0usize; 7];
+            for (bit, field) in fields.iter_mut().enumerate() {
+                if instruction & (1 << bit) != 0 {
+                    *field = *delta.get(position)? as usize;
+                    position += 1;
+                }
+            }
+            let offset = fields[0] | fields[1] << 8 | fields[2] << 16 | fields[3] << 24;
+            let size = match fields[4] | fields[5] << 8 | fields[6] << 16 {
+                0 => 0x10000,
+                size => size,
+            };
+            result.extend_from_slice(base.get(offset..offset + size)?);
+        } else if instruction != 0 {
+            let size = instruction as usize;
+            result.extend_from_slice(delta.get(position..position + size)?);
+            position += size;
+        } else {
+            return None;
+        }
+    }
+
+    (result.len() == result_size).then_some(result)
+}
+
+pub fn parse_hex(hex: &str) -> Option<ObjectId> {
+    let hex = hex.trim();
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut id = [0u8; 20];
+    for (index, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+    Some(id)
+}
+
+pub fn to_hex(id: &ObjectId) -> String {
+    id.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub struct Commit {
+    pub tree: Option<ObjectId>,
+    pub parents: Vec<ObjectId>,
+    pub time: i64,
+}
+
+pub fn parse_commit(data: &[u8]) -> Commit {
+    let mut commit = Commit {
+        tree: None,
+        parents: Vec::new(),
+        time: 0,
+    };
+    let text = String::from_utf8_lossy(data);
+    for line in text.lines() {
+        if line.is_empty() {
+            break;
+        }
+        if let Some(tree) = line.strip_prefix("tree ") {
+            commit.tree = parse_hex(tree);
+        } else if let Some(parent) = line.strip_prefix("parent ") {
+            commit.parents.extend(parse_hex(parent));
+        } else if let Some(committer) = line.strip_prefix("committer ") {
+            // "Name <email> 1700000000 +0000"
+            let mut fields = committer.rsplit(' ');
+            fields.next();
+            commit.time = fields
+                .next()
+                .and_then(|time| time.parse().ok())
+                .unwrap_or(0);
+        }
+    }
+    commit
+}
+
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub id: ObjectId,
+}
+
+pub fn parse_tree(data: &[u8]) -> Vec<TreeEntry> {
+    let mut entries = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let Some(space) = data[position..].iter().position(|&b| b == b' ') else {
+            break;
+        };
+        let Some(nul) = data[position..].iter().position(|&b| b == 0) else {
+            break;
+        };
+        let mode = std::str::from_utf8(&data[position..position + space])
+            .ok()
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .unwrap_or(0);
+        let name =
+            String::from_utf8_lossy(&data[position + space + 1..position + nul]).into_owned();
+        let Some(id) = data
+            .get(position + nul + 1..position + nul + 21)
+            .and_then(|id| id.try_into().ok())
+        else {
+            break;
+        };
+        entries.push(TreeEntry { mode, name, id });
+        position += nul + 21;
+    }
+    entries
+}
+
+pub fn hash_object(kind: &str, data: &[u8]) -> ObjectId {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("{} {}\0", kind, data.len()));
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+// Decompresses a zlib stream; trailing bytes after the stream are ignored
+pub fn inflate_zlib(data: &[u8], size_hint: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(size_hint);
+    ZlibDecoder::new(data).read_to_end(&mut output).ok()?;
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_objects_like_git() {
+        assert_eq!(
+            to_hex(&hash_object("blob", b"")),
+            "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391"
+        );
+        assert_eq!(
+            to_hex(&hash_object("blob", b"hello\n")),
+            "ce013625030ba8dba906f756967f9e9ca394464a"
+        );
+    }
+
+    #[test]
+    fn inflates_stored_block() {
+        let data = [
+            0x78, 0x01, 0x01, 0x0c, 0x00, 0xf3, 0xff, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20,
+            0x77, 0x6f, 0x72, 0x6c, 0x64, 0x1d, 0x54, 0x04, 0x89,
+        ];
+        assert_eq!(inflate_zlib(&data, 0).unwrap(), b"hello, world");
+        // Pack entries are read through a window that runs past the end of the stream
+        let mut windowed = data.to_vec();
+        windowed.extend_from_slice(b"PACK");
+        assert_eq!(inflate_zlib(&windowed, 0).unwrap(), b"hello, world");
+    }
+
+    #[test]
+    fn inflates_fixed_huffman_block() {
+        let data = [
+            0x78, 0x01, 0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00, 0x1d, 0xe0, 0x04, 0x99,
+        ];
+        assert_eq!(inflate_zlib(&data, 0).unwrap(), b"abcabcabcabc");
+    }
+
+    #[test]
+    fn inflates_dynamic_huffman_block() {
+        let data = [
+            0x78, 0xda, 0x05, 0xc1, 0x01, 0x09, 0x00, 0x00, 0x0c, 0xc3, 0x30, 0x2b, 0xb3, 0xd6,
+            0x43, 0x1d, 0xd4, 0x3f, 0x4f, 0x8e, 0x46, 0x2e, 0xd9, 0x69, 0x3b, 0x98, 0xf4, 0x67,
+            0xcd, 0x08, 0x4a,
+        ];
+        assert_eq!(inflate_zlib(&data, 0).unwrap(), b"bat ate tea beet baa eat");
+    }
+
+    #[test]
+    fn rejects_invalid_header() {
+        assert_eq!(inflate_zlib(&[0x78, 0x00, 0x01], 0), None);
+    }
+}
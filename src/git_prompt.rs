@@ -0,0 +1,446 @@
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::git::{Head, Repository};
+use crate::git_objects::{self, ObjectId, ObjectKind, ObjectStore};
+
+const MAX_WALKED_COMMITS: usize = 20_000;
+// Work trees with more files than this skip the unstaged check, as stat-ing every file on
+// each prompt gets slow
+const MAX_CHECKED_FILES: usize = 10_000;
+
+// State behind the `\g` prompt escape. Everything that only depends on object ids is cached,
+// as are the parsed index and the staged state for an unchanged index file, so a prompt in an
+// unchanged repository only re-reads HEAD and stats the indexed files.
+#[derive(Default)]
+pub struct GitPrompt {
+    cache: RefCell<Cache>,
+}
+
+#[derive(Default)]
+struct Cache {
+    repositories: HashMap<PathBuf, Rc<Repository>>,
+    divergence: HashMap<(ObjectId, ObjectId), Option<(usize, usize)>>,
+    head_trees: HashMap<ObjectId, Rc<HashMap<String, ObjectId>>>,
+    file_hashes: HashMap<PathBuf, (i64, i64, u64, ObjectId)>,
+    indexes: HashMap<PathBuf, (Option<Stamp>, Rc<Vec<IndexEntry>>)>,
+    staged: HashMap<PathBuf, (Option<ObjectId>, Option<Stamp>, bool)>,
+}
+
+// Modification time (seconds and nanoseconds) and size of a file
+type Stamp = (i64, i64, u64);
+
+struct IndexEntry {
+    path: String,
+    id: ObjectId,
+    mode: u32,
+    mtime: i64,
+    mtime_nsec: i64,
+    size: u32,
+    stage: u16,
+    skip_worktree: bool,
+}
+
+impl GitPrompt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // "main*+ ↑1↓2": branch (or detached commit), unstaged `*` and staged `+` changes,
+    // an in-progress operation and the distance from the upstream branch
+    pub fn segment(&self, directory: &Path) -> String {
+        let Some(repository) = self.repository(directory) else {
+            return String::new();
+        };
+        let Some(head) = repository.head() else {
+            return String::new();
+        };
+        let head_id = repository.resolve("HEAD");
+
+        let mut segment = match &head {
+            Head::Branch(branch) => branch.clone(),
+            Head::Detached(id) => format!("({})", &git_objects::to_hex(id)[..7]),
+        };
+
+        let (stamp, index) = self.index(&repository);
+        if self.has_unstaged_changes(&repository, &index) {
+            segment.push('*');
+        }
+        if self.has_staged_changes(&repository, head_id, stamp, &index) {
+            segment.push('+');
+        }
+        segment.push_str(operation_in_progress(&repository));
+
+        if let (Head::Branch(branch), Some(local)) = (&head, head_id) {
+            let upstream = repository
+                .upstream(branch)
+                .and_then(|upstream| repository.resolve(&upstream));
+            if let Some(upstream) = upstream {
+                match self.divergence(&repository, local, upstream) {
+                    Some((0, 0)) | None => {}
+                    Some((ahead, behind)) => {
+                        segment.push(' ');
+                        if ahead > 0 {
+                            segment.push_str(&format!("↑{}", ahead));
+                        }
+                        if behind > 0 {
+                            segment.push_str(&format!("↓{}", behind));
+                        }
+                    }
+                }
+            }
+        }
+        segment
+    }
+
+    // Only found repositories are cached, so one created with `git init` shows up at once
+    fn repository(&self, directory: &Path) -> Option<Rc<Repository>> {
+        let mut cache = self.cache.borrow_mut();
+        // A cached repository whose git dir has since been removed is no longer valid
+        let cached = cache
+            .repositories
+            .get(directory)
+            .filter(|repository| repository.git_dir.join("HEAD").is_file());
+        if let Some(repository) = cached {
+            return Some(repository.clone());
+        }
+        cache.repositories.remove(directory);
+        let repository = Rc::new(Repository::discover(directory)?);
+        cache
+            .repositories
+            .insert(directory.to_path_buf(), repository.clone());
+        Some(repository)
+    }
+
+    fn index(&self, repository: &Repository) -> (Option<Stamp>, Rc<Vec<IndexEntry>>) {
+        let path = repository.git_dir.join("index");
+        let stamp = fs::metadata(&path)
+            .ok()
+            .map(|metadata| (metadata.mtime(), metadata.mtime_nsec(), metadata.size()));
+        if let Some((cached, index)) = self.cache.borrow().indexes.get(&path) {
+            if *cached == stamp {
+                return (stamp, Rc::clone(index));
+            }
+        }
+        let index = Rc::new(read_index(&path).unwrap_or_default());
+        self.cache
+            .borrow_mut()
+            .indexes
+            .insert(path, (stamp, Rc::clone(&index)));
+        (stamp, index)
+    }
+
+    fn has_unstaged_changes(&self, repository: &Repository, index: &[IndexEntry]) -> bool {
+        if index.len() > MAX_CHECKED_FILES {
+            return false;
+        }
+        index.iter().any(|entry| {
+            if entry.stage != 0 {
+                return true;
+            }
+            // Gitlinks (submodules) and sparse entries are not checked out as files
+            if entry.skip_worktree || entry.mode == 0o160000 {
+                return false;
+            }
+            let path = repository.work_tree.join(&entry.path);
+            match fs::symlink_metadata(&path) {
+                Ok(metadata) => self.file_changed(&path, &metadata, entry),
+                Err(_) => true,
+            }
+        })
+    }
+
+    // Files whose stat data matches the index are assumed unchanged, as git does
+    fn file_changed(&self, path: &Path, metadata: &fs::Metadata, entry: &IndexEntry) -> bool {
+        if entry.mode & 0o170000 == 0o100000
+            && (entry.mode & 0o100 != 0) != (metadata.mode() & 0o100 != 0)
+        {
+            return true;
+        }
+        if metadata.mtime() == entry.mtime
+            && metadata.mtime_nsec() == entry.mtime_nsec
+            && metadata.size() as u32 == entry.size
+        {
+            return false;
+        }
+
+        let stamp = (metadata.mtime(), metadata.mtime_nsec(), metadata.size());
+        if let Some((mtime, nsec, size, id)) = self.cache.borrow().file_hashes.get(path) {
+            if (*mtime, *nsec, *size) == stamp {
+                return *id != entry.id;
+            }
+        }
+        let contents = if metadata.file_type().is_symlink() {
+            fs::read_link(path).map(|target| target.to_string_lossy().into_owned().into_bytes())
+        } else {
+            fs::read(path)
+        };
+        let Ok(contents) = contents else {
+            return true;
+        };
+        let id = git_objects::hash_object("blob", &contents);
+        self.cache
+            .borrow_mut()
+            .file_hashes
+            .insert(path.to_path_buf(), (stamp.0, stamp.1, stamp.2, id));
+        id != entry.id
+    }
+
+    // The staged state only depends on HEAD and the index, so it is kept until either changes
+    fn has_staged_changes(
+        &self,
+        repository: &Repository,
+        head: Option<ObjectId>,
+        stamp: Option<Stamp>,
+        index: &[IndexEntry],
+    ) -> bool {
+        if let Some((cached_head, cached_stamp, staged)) =
+            self.cache.borrow().staged.get(&repository.git_dir)
+        {
+            if (*cached_head, *cached_stamp) == (head, stamp) {
+                return *staged;
+            }
+        }
+        let staged = self.index_differs_from_head(repository, head, index);
+        self.cache
+            .borrow_mut()
+            .staged
+            .insert(repository.git_dir.clone(), (head, stamp, staged));
+        staged
+    }
+
+    fn index_differs_from_head(
+        &self,
+        repository: &Repository,
+        head: Option<ObjectId>,
+        index: &[IndexEntry],
+    ) -> bool {
+        let Some(head) = head else {
+            return !index.is_empty();
+        };
+        let Some(tree) = self.head_tree(repository, head) else {
+            return false;
+        };
+        let staged: Vec<&IndexEntry> = index.iter().filter(|entry| entry.stage == 0).collect();
+        staged.len() != tree.len()
+            || staged
+                .iter()
+                .any(|entry| tree.get(&entry.path) != Some(&entry.id))
+    }
+
+    // Pack indexes can be large, so the object store is only opened on a cache miss
+    fn head_tree(
+        &self,
+        repository: &Repository,
+        head: ObjectId,
+    ) -> Option<Rc<HashMap<String, ObjectId>>> {
+        if let Some(tree) = self.cache.borrow().head_trees.get(&head) {
+            return Some(Rc::clone(tree));
+        }
+        let objects = ObjectStore::open(&repository.objects_dir());
+        let commit = objects
+            .read(&head)
+            .filter(|object| object.kind == ObjectKind::Commit)?;
+        let root = git_objects::parse_commit(&commit.data).tree?;
+        let mut files = HashMap::new();
+        flatten_tree(&objects, &root, "", &mut files)?;
+
+        let files = Rc::new(files);
+        self.cache
+            .borrow_mut()
+            .head_trees
+            .insert(head, Rc::clone(&files));
+        Some(files)
+    }
+
+    fn divergence(
+        &self,
+        repository: &Repository,
+        local: ObjectId,
+        upstream: ObjectId,
+    ) -> Option<(usize, usize)> {
+        if local == upstream {
+            return Some((0, 0));
+        }
+        if let Some(counts) = self.cache.borrow().divergence.get(&(local, upstream)) {
+            return *counts;
+        }
+        let objects = ObjectStore::open(&repository.objects_dir());
+        let counts = count_divergence(&objects, local, upstream);
+        self.cache
+            .borrow_mut()
+            .divergence
+            .insert((local, upstream), counts);
+        counts
+    }
+}
+
+fn flatten_tree(
+    objects: &ObjectStore,
+    tree: &ObjectId,
+    prefix: &str,
+    files: &mut HashMap<String, ObjectId>,
+) -> Option<()> {
+    let object = objects
+        .read(tree)
+        .filter(|object| object.kind == ObjectKind::Tree)?;
+    for entry in git_objects::parse_tree(&object.data) {
+        let path = format!("{}{}", prefix, entry.name);
+        if entry.mode == 0o40000 {
+            flatten_tree(objects, &entry.id, &format!("{}/", path), files)?;
+        } else {
+            files.insert(path, entry.id);
+        }
+    }
+    Some(())
+}
+
+// Walks both histories newest-first, like `git rev-list --left-right --count local...upstream`,
+// stopping once every queued commit is reachable from both sides
+fn count_divergence(
+    objects: &ObjectStore,
+    local: ObjectId,
+    upstream: ObjectId,
+) -> Option<(usize, usize)> {
+    const LOCAL: u8 = 1;
+    const UPSTREAM: u8 = 2;
+
+    let mut flags: HashMap<ObjectId, u8> = HashMap::new();
+    let mut commits = HashMap::new();
+    let mut queue = BinaryHeap::new();
+
+    for (id, flag) in [(local, LOCAL), (upstream, UPSTREAM)] {
+        let commit = git_objects::parse_commit(&objects.read(&id)?.data);
+        queue.push((commit.time, id));
+        commits.insert(id, commit);
+        flags.insert(id, flag);
+    }
+
+    while queue.iter().any(|(_, id)| flags[id] != LOCAL | UPSTREAM) {
+        let Some((_, id)) = queue.pop() else {
+            break;
+        };
+        if commits.len() > MAX_WALKED_COMMITS {
+            return None;
+        }
+        let flag = flags[&id];
+        let parents = commits[&id].parents.clone();
+        for parent in parents {
+            let previous = flags.get(&parent).copied().unwrap_or(0);
+            if previous | flag == previous {
+                continue;
+            }
+            flags.insert(parent, previous | flag);
+            let commit = match commits.entry(parent) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                // Shallow clones end at commits whose parents are missing
+                Entry::Vacant(entry) => match objects.read(&parent) {
+                    Some(object) => entry.insert(git_objects::parse_commit(&object.data)),
+                    None => continue,
+                },
+            };
+            queue.push((commit.time, parent));
+        }
+    }
+
+    let ahead = flags.values().filter(|&&flag| flag == LOCAL).count();
+    let behind = flags.values().filter(|&&flag| flag == UPSTREAM).count();
+    Some((ahead, behind))
+}
+
+fn operation_in_progress(repository: &Repository) -> &'static str {
+    let git_dir = &repository.git_dir;
+    if git_dir.join("rebase-merge").is_dir() || git_dir.join("rebase-apply").is_dir() {
+        "|REBASE"
+    } else if git_dir.join("MERGE_HEAD").is_file() {
+        "|MERGING"
+    } else if git_dir.join("CHERRY_PICK_HEAD").is_file() {
+        "|CHERRY-PICKING"
+    } else if git_dir.join("REVERT_HEAD").is_file() {
+        "|REVERTING"
+    } else if git_dir.join("BISECT_LOG").is_file() {
+        "|BISECTING"
+    } else {
+        ""
+    }
+}
+
+// Parses `.git/index` versions 2 to 4
+fn read_index(path: &Path) -> Option<Vec<IndexEntry>> {
+    let data = fs::read(path).ok()?;
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let field = |position: usize| -> Option<u32> {
+        Some(u32::from_be_bytes(
+            data.get(position..position + 4)?.try_into().ok()?,
+        ))
+    };
+    let version = field(4)?;
+    let count = field(8)? as usize;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+
+    let mut entries = Vec::with_capacity(count);
+    let mut position = 12;
+    let mut previous_path = String::new();
+    for _ in 0..count {
+        let start = position;
+        let id: ObjectId = data.get(start + 40..start + 60)?.try_into().ok()?;
+        let flags = u16::from_be_bytes(data.get(start + 60..start + 62)?.try_into().ok()?);
+        position = start + 62;
+
+        let mut skip_worktree = false;
+        if flags & 0x4000 != 0 && version >= 3 {
+            let extended = u16::from_be_bytes(data.get(position..position + 2)?.try_into().ok()?);
+            skip_worktree = extended & 0x4000 != 0;
+            position += 2;
+        }
+
+        let path = if version == 4 {
+            // The path is stored as a count of bytes to drop from the previous path plus a suffix
+            let mut strip = 0usize;
+            loop {
+                let byte = *data.get(position)?;
+                position += 1;
+                strip = (strip << 7) | (byte & 0x7f) as usize;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+                strip += 1;
+            }
+            let end = position + data.get(position..)?.iter().position(|&b| b == 0)?;
+            let mut path = previous_path
+                .get(..previous_path.len().checked_sub(strip)?)?
+                .to_string();
+            path.push_str(&String::from_utf8_lossy(&data[position..end]));
+            position = end + 1;
+            path
+        } else {
+            let end = position + data.get(position..)?.iter().position(|&b| b == 0)?;
+            let path = String::from_utf8_lossy(&data[position..end]).into_owned();
+            // Entries are NUL-padded to a multiple of eight bytes
+            position = start + (end - start + 8) / 8 * 8;
+            path
+        };
+
+        entries.push(IndexEntry {
+            path: path.clone(),
+            id,
+            mode: field(start + 24)?,
+            mtime: field(start + 8)? as i64,
+            mtime_nsec: field(start + 12)? as i64,
+            size: field(start + 36)?,
+            stage: (flags >> 12) & 0x3,
+            skip_worktree,
+        });
+        previous_path = path;
+    }
+    Some(entries)
+}
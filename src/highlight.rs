@@ -0,0 +1,287 @@
+// Colours are SGR parameters, e.g. `32` or `1;31`, configurable through `HIGHLIGHT_COLORS`
+// as `command=32:unknown=31:string=33`.
+pub struct Palette {
+    command: String,
+    unknown: String,
+    builtin: String,
+    keyword: String,
+    string: String,
+    variable: String,
+    redirection: String,
+    unmatched: String,
+}
+
+pub enum CommandKind {
+    Command,
+    Unknown,
+    Builtin,
+    Keyword,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            command: "32".to_string(),
+            unknown: "31".to_string(),
+            builtin: "36".to_string(),
+            keyword: "35".to_string(),
+            string: "33".to_string(),
+            variable: "34".to_string(),
+            redirection: "1".to_string(),
+            unmatched: "1;41".to_string(),
+        }
+    }
+}
+
+impl Palette {
+    pub fn from_spec(spec: &str) -> Self {
+        let mut palette = Self::default();
+        for (name, code) in spec.split(':').filter_map(|entry| entry.split_once('=')) {
+            let valid = !code.is_empty() && code.chars().all(|c| c.is_ascii_digit() || c == ';');
+            let slot = match name.trim() {
+                "command" => &mut palette.command,
+                "unknown" => &mut palette.unknown,
+                "builtin" => &mut palette.builtin,
+                "keyword" => &mut palette.keyword,
+                "string" => &mut palette.string,
+                "variable" => &mut palette.variable,
+                "redirection" => &mut palette.redirection,
+                "unmatched" => &mut palette.unmatched,
+                _ => continue,
+            };
+            if valid {
+                *slot = code.to_string();
+            }
+        }
+        palette
+    }
+
+    fn color(&self, kind: &CommandKind) -> &str {
+        match kind {
+            CommandKind::Command => &self.command,
+            CommandKind::Unknown => &self.unknown,
+            CommandKind::Builtin => &self.builtin,
+            CommandKind::Keyword => &self.keyword,
+        }
+    }
+}
+
+fn paint(output: &mut String, color: &str, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    output.push_str(&format!("\x1b[{}m{}\x1b[0m", color, text));
+}
+
+// Colours a command line without changing its visible text
+pub fn highlight_line(
+    line: &str,
+    palette: &Palette,
+    classify: impl Fn(&str) -> CommandKind,
+) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut output = String::new();
+    let mut command_position = true;
+    let mut after_redirection = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            output.push(c);
+            i += 1;
+            continue;
+        }
+
+        if c == '#' {
+            output.extend(&chars[i..]);
+            break;
+        }
+
+        if let Some(length) = redirection_length(&chars[i..]) {
+            let operator: String = chars[i..i + length].iter().collect();
+            paint(&mut output, &palette.redirection, &operator);
+            i += length;
+            after_redirection = true;
+            continue;
+        }
+
+        if ";|&".contains(c) {
+            let length = chars[i..]
+                .iter()
+                .take_while(|c| ";|&".contains(**c))
+                .count();
+            output.extend(&chars[i..i + length]);
+            i += length;
+            command_position = true;
+            continue;
+        }
+
+        let end = word_end(&chars, i);
+        let word: String = chars[i..end].iter().collect();
+        let plain = !word.contains(['\'', '"', '$', '\\']);
+
+        if command_position && !after_redirection && is_assignment(&word) {
+            paint_word(&mut output, &chars[i..end], palette, None);
+        } else if command_position && !after_redirection && plain {
+            paint(&mut output, palette.color(&classify(&word)), &word);
+            command_position = word == "!";
+        } else {
+            paint_word(&mut output, &chars[i..end], palette, None);
+            if !after_redirection {
+                command_position = false;
+            }
+        }
+        after_redirection = false;
+        i = end;
+    }
+
+    output
+}
+
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+// Recognises `<`, `>`, `>>`, `2>`, `2>>`, `&>` and `>&` style operators
+fn redirection_length(chars: &[char]) -> Option<usize> {
+    let mut length = chars.iter().take_while(|c| c.is_ascii_digit()).count();
+    if chars.get(length) == Some(&'&') && chars.get(length + 1) == Some(&'>') {
+        length += 1;
+    }
+    match chars.get(length) {
+        Some('<') | Some('>') => {}
+        _ => return None,
+    }
+    length += chars[length..]
+        .iter()
+        .take_while(|c| **c == '<' || **c == '>')
+        .count();
+    if chars.get(length) == Some(&'&') {
+        length += 1;
+    }
+    Some(length)
+}
+
+// A word runs to the next unquoted blank or operator; an open quote runs to the end of the line
+fn word_end(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    let mut quote = None;
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == '\\' => i += 1,
+            Some(_) => {}
+            None if c.is_whitespace() || ";|&<>".contains(c) => break,
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '\\' => i += 1,
+            None => {}
+        }
+        i += 1;
+    }
+    i.min(chars.len())
+}
+
+fn paint_word(output: &mut String, chars: &[char], palette: &Palette, enclosing: Option<&str>) {
+    let restore = |output: &mut String| {
+        if let Some(color) = enclosing {
+            output.push_str(&format!("\x1b[{}m", color));
+        }
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\\' => {
+                let end = (i + 2).min(chars.len());
+                output.extend(&chars[i..end]);
+                i = end;
+            }
+            '\'' | '"' if enclosing.is_none() => {
+                let close = closing_quote(chars, i);
+                match close {
+                    Some(close) if c == '"' => {
+                        output.push_str(&format!("\x1b[{}m\"", palette.string));
+                        paint_word(output, &chars[i + 1..close], palette, Some(&palette.string));
+                        output.push_str("\"\x1b[0m");
+                        i = close + 1;
+                    }
+                    Some(close) => {
+                        paint(
+                            output,
+                            &palette.string,
+                            &chars[i..=close].iter().collect::<String>(),
+                        );
+                        i = close + 1;
+                    }
+                    None => {
+                        paint(
+                            output,
+                            &palette.unmatched,
+                            &chars[i..].iter().collect::<String>(),
+                        );
+                        i = chars.len();
+                    }
+                }
+            }
+            '$' => {
+                let length = variable_length(&chars[i..]);
+                if length > 1 {
+                    paint(
+                        output,
+                        &palette.variable,
+                        &chars[i..i + length].iter().collect::<String>(),
+                    );
+                    restore(output);
+                } else {
+                    output.push(c);
+                }
+                i += length;
+            }
+            _ => {
+                output.push(c);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn closing_quote(chars: &[char], open: usize) -> Option<usize> {
+    let quote = chars[open];
+    let mut i = open + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if quote == '"' => i += 1,
+            c if c == quote => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn variable_length(chars: &[char]) -> usize {
+    match chars.get(1) {
+        Some('{') => chars
+            .iter()
+            .position(|&c| c == '}')
+            .map_or(chars.len(), |close| close + 1),
+        Some(c) if "?$#!@*-0123456789".contains(*c) => 2,
+        Some(c) if c.is_alphabetic() || *c == '_' => {
+            1 + chars[1..]
+                .iter()
+                .take_while(|c| c.is_alphanumeric() || **c == '_')
+                .count()
+        }
+        _ => 1,
+    }
+}
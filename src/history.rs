@@ -0,0 +1,374 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use crate::pattern;
+use crate::variables::Variables;
+
+pub const DEFAULT_HISTORY_SIZE: usize = 500;
+
+// HISTCONTROL and HISTIGNORE rules, re-read before every entry so changes apply at once
+pub struct HistoryPolicy {
+    ignore_space: bool,
+    ignore_dups: bool,
+    pub erase_dups: bool,
+    ignore_patterns: Vec<String>,
+}
+
+impl HistoryPolicy {
+    pub fn from_variables(variables: &Variables) -> Self {
+        let control = variables.get("HISTCONTROL").unwrap_or_default();
+        let control: Vec<&str> = control.split(':').collect();
+        let ignore_both = control.contains(&"ignoreboth");
+
+        Self {
+            ignore_space: ignore_both || control.contains(&"ignorespace"),
+            ignore_dups: ignore_both || control.contains(&"ignoredups"),
+            erase_dups: control.contains(&"erasedups"),
+            ignore_patterns: split_patterns(&variables.get("HISTIGNORE").unwrap_or_default()),
+        }
+    }
+
+    pub fn accepts(&self, line: &str, previous: Option<&str>) -> bool {
+        if line.trim().is_empty() {
+            return false;
+        }
+        if self.ignore_space && line.starts_with(char::is_whitespace) {
+            return false;
+        }
+        if self.ignore_dups && previous == Some(line) {
+            return false;
+        }
+        !self.ignore_patterns.iter().any(|ignored| {
+            // `&` stands for the previous history entry
+            if ignored == "&" {
+                previous == Some(line)
+            } else {
+                pattern::matches(ignored, line)
+            }
+        })
+    }
+}
+
+// HISTIGNORE is colon-separated; `\:` keeps a literal colon in a pattern
+fn split_patterns(value: &str) -> Vec<String> {
+    let mut patterns = Vec::new();
+    let mut current = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(':') => current.push(':'),
+                Some(next) => {
+                    current.push('\\');
+                    current.push(next);
+                }
+                None => current.push('\\'),
+            },
+            ':' => patterns.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    patterns.push(current);
+    patterns.retain(|pattern| !pattern.is_empty());
+    patterns
+}
+
+// Parses HISTSIZE/HISTFILESIZE; negative or non-numeric values mean no limit
+pub fn parse_limit(value: Option<String>) -> Option<usize> {
+    match value?.trim().parse::<i64>() {
+        Ok(limit) if limit >= 0 => Some(limit as usize),
+        _ => None,
+    }
+}
+
+pub fn load(path: &str, limit: Option<usize>) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    lock(&file, libc::LOCK_SH)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+
+    Ok(keep_last(parse_entries(&contents), limit))
+}
+
+// Multi-line entries are stored with a backslash ending each line but the last
+fn parse_entries(contents: &str) -> Vec<String> {
+    let mut entries = Vec::new();
+    let mut pending: Option<String> = None;
+    for line in contents.lines() {
+        let continued = line.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1;
+        let text = if continued { &line[..line.len() - 1] } else { line };
+        let entry = match pending.take() {
+            Some(mut entry) => {
+                entry.push('\n');
+                entry.push_str(text);
+                entry
+            }
+            None => text.to_string(),
+        };
+        if continued {
+            pending = Some(entry);
+        } else if !entry.is_empty() {
+            entries.push(entry);
+        }
+    }
+    entries.extend(pending);
+    entries
+}
+
+fn format_entries<'a>(entries: impl IntoIterator<Item = &'a String>) -> String {
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&entry.replace('\n', "\\\n"));
+        contents.push('\n');
+    }
+    contents
+}
+
+// Appends this session's entries under an exclusive lock so concurrent shells don't lose lines
+pub fn append(path: &str, entries: &[String], file_limit: Option<usize>) -> io::Result<()> {
+    append_and_trim(path, &format_entries(entries), |contents| {
+        let stored = parse_entries(contents);
+        let limit = file_limit.filter(|&limit| stored.len() > limit)?;
+        Some(format_entries(&stored[stored.len() - limit..]))
+    })
+}
+
+// `trim` sees the whole file after the append and returns new contents if it is too long
+fn append_and_trim(
+    path: &str,
+    text: &str,
+    trim: impl FnOnce(&str) -> Option<String>,
+) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    lock(&file, libc::LOCK_EX)?;
+
+    file.write_all(text.as_bytes())?;
+
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut contents)?;
+    let Some(trimmed) = trim(&contents) else {
+        return Ok(());
+    };
+
+    file.set_len(0)?;
+    file.write_all(trimmed.as_bytes())
+}
+
+pub fn write(path: &str, entries: &[String]) -> io::Result<()> {
+    // Truncated only once the lock is held
+    let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(path)?;
+    lock(&file, libc::LOCK_EX)?;
+    file.set_len(0)?;
+
+    file.write_all(format_entries(entries).as_bytes())
+}
+
+// How each history line ran, kept next to HISTFILE in `HISTFILE.records` as one tab-separated
+// line per entry: start time, duration in milliseconds, exit status, session, directory and line
+#[derive(Clone)]
+pub struct HistoryRecord {
+    pub line: String,
+    pub time: i64,
+    pub duration: Duration,
+    // None until the line has finished
+    pub status: Option<i32>,
+    pub cwd: String,
+    pub session: String,
+}
+
+impl HistoryRecord {
+    fn parse(text: &str) -> Option<Self> {
+        let fields: Vec<&str> = text.split('\t').collect();
+        match fields[..] {
+            [time, duration, status, session, cwd, line] => Some(Self {
+                line: unescape_field(line),
+                time: time.parse().ok()?,
+                duration: Duration::from_millis(duration.parse().ok()?),
+                status: status.parse().ok(),
+                cwd: unescape_field(cwd),
+                session: session.to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn format(&self) -> String {
+        let status = self
+            .status
+            .map_or_else(|| "-".to_string(), |status| status.to_string());
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            self.time,
+            self.duration.as_millis(),
+            status,
+            self.session,
+            escape_field(&self.cwd),
+            escape_field(&self.line)
+        )
+    }
+}
+
+// Pairs history entries with their records, newest first so repeated lines get their own times;
+// entries loaded from a history file without records get None
+pub fn match_records<'a>(
+    entries: &[String],
+    records: &'a [HistoryRecord],
+) -> Vec<Option<&'a HistoryRecord>> {
+    let mut remaining = records.len();
+    let mut matched: Vec<Option<&HistoryRecord>> = entries
+        .iter()
+        .rev()
+        .map(|entry| {
+            let index = records[..remaining]
+                .iter()
+                .rposition(|record| record.line == *entry)?;
+            remaining = index;
+            Some(&records[index])
+        })
+        .collect();
+    matched.reverse();
+    matched
+}
+
+// `30s`, `15m`, `2h`, `1d` or `1w`; a bare number is seconds
+pub fn parse_age(text: &str) -> Option<i64> {
+    let split = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: i64 = number.parse().ok()?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    number.checked_mul(seconds)
+}
+
+pub fn records_file(history_file: &str) -> String {
+    format!("{}.records", history_file)
+}
+
+pub fn load_records(path: &str, limit: Option<usize>) -> io::Result<Vec<HistoryRecord>> {
+    let mut file = File::open(path)?;
+    lock(&file, libc::LOCK_SH)?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+
+    let records = contents.lines().filter_map(HistoryRecord::parse).collect();
+    Ok(keep_last(records, limit))
+}
+
+pub fn append_records(
+    path: &str,
+    records: &[HistoryRecord],
+    file_limit: Option<usize>,
+) -> io::Result<()> {
+    let text: String = records.iter().map(HistoryRecord::format).collect();
+    append_and_trim(path, &text, |contents| {
+        let stored: Vec<&str> = contents.lines().collect();
+        let limit = file_limit.filter(|&limit| stored.len() > limit)?;
+        Some(stored[stored.len() - limit..].iter().map(|line| format!("{}\n", line)).collect())
+    })
+}
+
+pub fn write_records(path: &str, records: &[HistoryRecord]) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).write(true).truncate(false).open(path)?;
+    lock(&file, libc::LOCK_EX)?;
+    file.set_len(0)?;
+
+    let text: String = records.iter().map(HistoryRecord::format).collect();
+    file.write_all(text.as_bytes())
+}
+
+fn escape_field(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape_field(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+fn keep_last<T>(mut entries: Vec<T>, limit: Option<usize>) -> Vec<T> {
+    if let Some(limit) = limit.filter(|&limit| entries.len() > limit) {
+        entries.drain(..entries.len() - limit);
+    }
+    entries
+}
+
+// The lock is released when the file is closed
+fn lock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(line: &str, time: i64) -> HistoryRecord {
+        HistoryRecord {
+            line: line.to_string(),
+            time,
+            duration: Duration::from_millis(1500),
+            status: Some(1),
+            cwd: "/tmp/a\tb".to_string(),
+            session: "s1".to_string(),
+        }
+    }
+
+    #[test]
+    fn records_round_trip_through_the_file() {
+        let path = std::env::temp_dir().join(format!("history-records-{}", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        write_records(&path, &[record("echo one", 1), record("multi\nline", 2)]).unwrap();
+        append_records(&path, &[record("echo three", 3)], Some(2)).unwrap();
+
+        let loaded = load_records(&path, None).unwrap();
+        let lines: Vec<&str> = loaded.iter().map(|record| record.line.as_str()).collect();
+        assert_eq!(lines, ["multi\nline", "echo three"]);
+        assert_eq!(loaded[0].cwd, "/tmp/a\tb");
+        assert_eq!(loaded[0].duration, Duration::from_millis(1500));
+        assert_eq!(loaded[0].status, Some(1));
+        assert_eq!(load_records(&path, Some(1)).unwrap().len(), 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn matches_repeated_lines_newest_first() {
+        let records = [record("ls", 1), record("pwd", 2), record("ls", 3)];
+        let entries = ["old".to_string(), "ls".to_string(), "ls".to_string()];
+        let times: Vec<Option<i64>> = match_records(&entries, &records)
+            .iter()
+            .map(|record| record.map(|record| record.time))
+            .collect();
+        assert_eq!(times, [None, Some(1), Some(3)]);
+    }
+}
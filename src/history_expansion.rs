@@ -0,0 +1,609 @@
+use crate::utils::{self, Quoting};
+
+pub struct Expansion {
+    pub line: String,
+    pub changed: bool,
+    pub print_only: bool,
+}
+
+// Remembers the last `:s` substitution and `!?string?` search for `:&` and empty patterns
+#[derive(Default)]
+pub struct HistoryExpander {
+    last_substitution: Option<(String, String)>,
+    last_search: Option<String>,
+}
+
+impl HistoryExpander {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expand(&mut self, line: &str, entries: &[String]) -> Result<Expansion, String> {
+        // `^old^new^` is shorthand for `!!:s^old^new^`
+        let line = if line.starts_with('^') {
+            format!("!!:s{}", line)
+        } else {
+            line.to_string()
+        };
+
+        let chars: Vec<char> = line.chars().collect();
+        let mut result = String::new();
+        let mut changed = false;
+        let mut print_only = false;
+        let mut in_single = false;
+        let mut in_double = false;
+        let mut i = 0;
+
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\'' if !in_double => in_single = !in_single,
+                '"' if !in_single => in_double = !in_double,
+                '\\' if !in_single => {
+                    result.push(c);
+                    if let Some(&next) = chars.get(i + 1) {
+                        result.push(next);
+                    }
+                    i += 2;
+                    continue;
+                }
+                '!' if !in_single && starts_designator(chars.get(i + 1)) => {
+                    let mut cursor = Cursor {
+                        chars: &chars,
+                        position: i + 1,
+                    };
+                    let (text, print) = self.expand_event(&mut cursor, entries)?;
+                    result.push_str(&text);
+                    changed = true;
+                    print_only |= print;
+                    i = cursor.position;
+                    continue;
+                }
+                _ => {}
+            }
+            result.push(c);
+            i += 1;
+        }
+
+        Ok(Expansion {
+            line: result,
+            changed,
+            print_only,
+        })
+    }
+
+    fn expand_event(
+        &mut self,
+        cursor: &mut Cursor,
+        entries: &[String],
+    ) -> Result<(String, bool), String> {
+        let start = cursor.position - 1;
+        let event = self.select_event(cursor, entries)?;
+        let Some(event) = event else {
+            return Err(format!("{}: event not found", cursor.text_from(start)));
+        };
+
+        let words = split_words(&event);
+        let mut text = if let Some(range) = parse_word_range(cursor, words.len()) {
+            let (first, last) =
+                range.map_err(|_| format!("{}: bad word specifier", cursor.text_from(start)))?;
+            if first > last {
+                String::new()
+            } else {
+                words[first..=last].join(" ")
+            }
+        } else {
+            event
+        };
+
+        let mut print_only = false;
+        while cursor.peek() == Some(':') {
+            let modifier_start = cursor.position;
+            cursor.position += 1;
+            match cursor.next() {
+                Some('h') => {
+                    if let Some((head, _)) = text.rsplit_once('/') {
+                        text = head.to_string();
+                    }
+                }
+                Some('t') => {
+                    if let Some((_, tail)) = text.rsplit_once('/') {
+                        text = tail.to_string();
+                    }
+                }
+                Some('r') => {
+                    if let Some(dot) = suffix_start(&text) {
+                        text.truncate(dot);
+                    }
+                }
+                Some('e') => {
+                    if let Some(dot) = suffix_start(&text) {
+                        text = text[dot..].to_string();
+                    }
+                }
+                Some('p') => print_only = true,
+                Some('q') => text = utils::shell_quote(&text, Quoting::Always),
+                Some('x') => {
+                    text = split_words(&text)
+                        .iter()
+                        .map(|word| utils::shell_quote(word, Quoting::Always))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                }
+                Some('s') => text = self.substitute(cursor, &text, false, modifier_start)?,
+                Some('&') => {
+                    text = self.repeat_substitution(cursor, &text, false, modifier_start)?
+                }
+                Some('g') | Some('a') => match cursor.next() {
+                    Some('s') => text = self.substitute(cursor, &text, true, modifier_start)?,
+                    Some('&') => {
+                        text = self.repeat_substitution(cursor, &text, true, modifier_start)?
+                    }
+                    _ => return Err(unrecognized_modifier(cursor, modifier_start)),
+                },
+                _ => return Err(unrecognized_modifier(cursor, modifier_start)),
+            }
+        }
+
+        Ok((text, print_only))
+    }
+
+    fn select_event(
+        &mut self,
+        cursor: &mut Cursor,
+        entries: &[String],
+    ) -> Result<Option<String>, String> {
+        let previous = || entries.last().cloned();
+        let event = match cursor.peek() {
+            Some('!') => {
+                cursor.position += 1;
+                previous()
+            }
+            // `!$`, `!^`, `!*` and `!:n` refer to the previous command
+            Some(':' | '$' | '^' | '*') => previous(),
+            Some('-') | Some('0'..='9') => {
+                let negative = cursor.peek() == Some('-');
+                if negative {
+                    cursor.position += 1;
+                }
+                let digits = cursor.take_while(|c| c.is_ascii_digit());
+                let number: usize = digits.parse().map_err(|_| {
+                    format!(
+                        "!{}{}: event not found",
+                        if negative { "-" } else { "" },
+                        digits
+                    )
+                })?;
+                let index = if negative {
+                    entries.len().checked_sub(number)
+                } else {
+                    number.checked_sub(1)
+                };
+                index.and_then(|index| entries.get(index)).cloned()
+            }
+            Some('?') => {
+                cursor.position += 1;
+                let needle = cursor.take_while(|c| c != '?');
+                if cursor.peek() == Some('?') {
+                    cursor.position += 1;
+                }
+                let needle = if needle.is_empty() {
+                    self.last_search.clone().unwrap_or_default()
+                } else {
+                    needle
+                };
+                let found = entries
+                    .iter()
+                    .rev()
+                    .find(|entry| entry.contains(&needle))
+                    .cloned();
+                self.last_search = Some(needle);
+                found
+            }
+            _ => {
+                let prefix = cursor.take_while(|c| !ends_event_string(c));
+                entries
+                    .iter()
+                    .rev()
+                    .find(|entry| entry.starts_with(&prefix))
+                    .cloned()
+            }
+        };
+        Ok(event)
+    }
+
+    fn substitute(
+        &mut self,
+        cursor: &mut Cursor,
+        text: &str,
+        global: bool,
+        modifier_start: usize,
+    ) -> Result<String, String> {
+        let Some(delimiter) = cursor.next() else {
+            return Err(substitution_failed(cursor, modifier_start));
+        };
+        let old = cursor.take_delimited(delimiter);
+        let new = cursor.take_delimited(delimiter);
+
+        let old = if old.is_empty() {
+            match self
+                .last_substitution
+                .as_ref()
+                .map(|(old, _)| old.clone())
+                .or_else(|| self.last_search.clone())
+            {
+                Some(old) => old,
+                None => return Err(substitution_failed(cursor, modifier_start)),
+            }
+        } else {
+            old
+        };
+        // An unescaped `&` in the replacement stands for the matched text
+        let new = replace_ampersand(&new, &old);
+        self.last_substitution = Some((old, new));
+        self.repeat_substitution(cursor, text, global, modifier_start)
+    }
+
+    fn repeat_substitution(
+        &self,
+        cursor: &Cursor,
+        text: &str,
+        global: bool,
+        modifier_start: usize,
+    ) -> Result<String, String> {
+        match &self.last_substitution {
+            Some((old, new)) if !old.is_empty() && text.contains(old.as_str()) => Ok(if global {
+                text.replace(old.as_str(), new)
+            } else {
+                text.replacen(old.as_str(), new, 1)
+            }),
+            _ => Err(substitution_failed(cursor, modifier_start)),
+        }
+    }
+}
+
+struct Cursor<'a> {
+    chars: &'a [char],
+    position: usize,
+}
+
+impl Cursor<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.position + offset).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position += 1;
+        Some(c)
+    }
+
+    fn take_while(&mut self, keep: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.peek().filter(|&c| keep(c)) {
+            taken.push(c);
+            self.position += 1;
+        }
+        taken
+    }
+
+    fn take_number(&mut self) -> Option<usize> {
+        let digits = self.take_while(|c| c.is_ascii_digit());
+        digits.parse().ok()
+    }
+
+    // Reads up to the delimiter (consuming it); `\` escapes the delimiter
+    fn take_delimited(&mut self, delimiter: char) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.next() {
+            if c == delimiter {
+                break;
+            }
+            if c == '\\' && self.peek() == Some(delimiter) {
+                self.position += 1;
+                taken.push(delimiter);
+                continue;
+            }
+            taken.push(c);
+        }
+        taken
+    }
+
+    fn text_from(&self, start: usize) -> String {
+        self.chars[start..self.position.min(self.chars.len())]
+            .iter()
+            .collect()
+    }
+}
+
+fn starts_designator(next: Option<&char>) -> bool {
+    match next {
+        None => false,
+        Some(c) if c.is_whitespace() => false,
+        Some('=' | '(' | '"') => false,
+        Some(_) => true,
+    }
+}
+
+fn ends_event_string(c: char) -> bool {
+    c.is_whitespace() || ":;&|<>()\"'".contains(c)
+}
+
+// Parses a word designator such as `:2`, `$`, `^`, `*`, `:1-3`, `:2*` or `:-2`.
+// Returns None when there is none, and Err when it doesn't fit the event's words.
+fn parse_word_range(cursor: &mut Cursor, word_count: usize) -> Option<Result<(usize, usize), ()>> {
+    let last = word_count.checked_sub(1);
+    let saved = cursor.position;
+
+    match cursor.peek() {
+        Some('$' | '^' | '*') => {}
+        Some(':')
+            if cursor
+                .peek_at(1)
+                .is_some_and(|c| c.is_ascii_digit() || "$^*-".contains(c)) =>
+        {
+            cursor.position += 1;
+        }
+        _ => return None,
+    }
+
+    let Some(last) = last else {
+        return Some(Err(()));
+    };
+    let bound = |cursor: &mut Cursor| -> Option<usize> {
+        match cursor.peek() {
+            Some('$') => {
+                cursor.position += 1;
+                Some(last)
+            }
+            Some('^') => {
+                cursor.position += 1;
+                Some(1)
+            }
+            _ => cursor.take_number(),
+        }
+    };
+
+    let range = if cursor.peek() == Some('*') {
+        cursor.position += 1;
+        Some((1, last))
+    } else if cursor.peek() == Some('-') {
+        cursor.position += 1;
+        bound(cursor).map(|end| (0, end))
+    } else {
+        match bound(cursor) {
+            None => None,
+            Some(first) => match cursor.peek() {
+                Some('*') => {
+                    cursor.position += 1;
+                    Some((first, last))
+                }
+                Some('-') => {
+                    cursor.position += 1;
+                    match bound(cursor) {
+                        Some(end) => Some((first, end)),
+                        // `x-` abbreviates `x-$` without the last word
+                        None => Some((first, last.saturating_sub(1))),
+                    }
+                }
+                _ => Some((first, first)),
+            },
+        }
+    };
+
+    match range {
+        Some((first, end)) if end <= last && (first <= end || first == end + 1) => {
+            Some(Ok((first, end)))
+        }
+        Some(_) => Some(Err(())),
+        None => {
+            cursor.position = saved;
+            Some(Err(()))
+        }
+    }
+}
+
+// Splits a history line into words, keeping quoted text and operators together
+pub fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut word = String::new();
+        if is_operator(c) {
+            while let Some(&c) = chars.peek().filter(|&&c| is_operator(c)) {
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+            continue;
+        }
+
+        let mut quote = None;
+        while let Some(&c) = chars.peek() {
+            match quote {
+                Some(q) if c == q => quote = None,
+                Some(_) => {}
+                None if c.is_whitespace() || is_operator(c) => break,
+                None if c == '\'' || c == '"' => quote = Some(c),
+                None if c == '\\' => {
+                    word.push(c);
+                    chars.next();
+                    if let Some(escaped) = chars.next() {
+                        word.push(escaped);
+                    }
+                    continue;
+                }
+                None => {}
+            }
+            word.push(c);
+            chars.next();
+        }
+        words.push(word);
+    }
+
+    words
+}
+
+fn is_operator(c: char) -> bool {
+    ";&|<>".contains(c)
+}
+
+fn suffix_start(text: &str) -> Option<usize> {
+    let name_start = text.rfind('/').map_or(0, |slash| slash + 1);
+    text[name_start..].rfind('.').map(|dot| name_start + dot)
+}
+
+fn replace_ampersand(replacement: &str, matched: &str) -> String {
+    let mut result = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('&') => result.push('&'),
+                Some(next) => {
+                    result.push('\\');
+                    result.push(next);
+                }
+                None => result.push('\\'),
+            },
+            '&' => result.push_str(matched),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn substitution_failed(cursor: &Cursor, modifier_start: usize) -> String {
+    format!("{}: substitution failed", cursor.text_from(modifier_start))
+}
+
+fn unrecognized_modifier(cursor: &Cursor, modifier_start: usize) -> String {
+    format!(
+        "{}: unrecognized history modifier",
+        cursor.text_from(modifier_start)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> Vec<String> {
+        [
+            "ls -l /tmp",
+            "git commit -m 'first change'",
+            "cat src/main.rs docs/notes.txt",
+        ]
+        .iter()
+        .map(|entry| entry.to_string())
+        .collect()
+    }
+
+    fn expand(line: &str) -> Result<String, String> {
+        HistoryExpander::new()
+            .expand(line, &history())
+            .map(|expansion| expansion.line)
+    }
+
+    #[test]
+    fn selects_events() {
+        assert_eq!(
+            expand("!!"),
+            Ok("cat src/main.rs docs/notes.txt".to_string())
+        );
+        assert_eq!(expand("!1"), Ok("ls -l /tmp".to_string()));
+        assert_eq!(
+            expand("!-2"),
+            Ok("git commit -m 'first change'".to_string())
+        );
+        assert_eq!(expand("!l"), Ok("ls -l /tmp".to_string()));
+        assert_eq!(
+            expand("echo !?commit?"),
+            Ok("echo git commit -m 'first change'".to_string())
+        );
+        assert_eq!(expand("!9"), Err("!9: event not found".to_string()));
+        assert_eq!(expand("!nope"), Err("!nope: event not found".to_string()));
+    }
+
+    #[test]
+    fn selects_words() {
+        assert_eq!(expand("vi !$"), Ok("vi docs/notes.txt".to_string()));
+        assert_eq!(expand("echo !^"), Ok("echo src/main.rs".to_string()));
+        assert_eq!(
+            expand("echo !*"),
+            Ok("echo src/main.rs docs/notes.txt".to_string())
+        );
+        assert_eq!(expand("echo !git:3"), Ok("echo 'first change'".to_string()));
+        assert_eq!(expand("echo !1:0-1"), Ok("echo ls -l".to_string()));
+        assert_eq!(expand("echo !2:1-"), Ok("echo commit -m".to_string()));
+        assert_eq!(
+            expand("echo !1:5"),
+            Err("!1:5: bad word specifier".to_string())
+        );
+    }
+
+    #[test]
+    fn applies_modifiers() {
+        assert_eq!(expand("echo !$:h"), Ok("echo docs".to_string()));
+        assert_eq!(expand("echo !$:t"), Ok("echo notes.txt".to_string()));
+        assert_eq!(expand("echo !$:r"), Ok("echo docs/notes".to_string()));
+        assert_eq!(expand("echo !$:e"), Ok("echo .txt".to_string()));
+        assert_eq!(expand("echo !1:$:q"), Ok("echo '/tmp'".to_string()));
+        assert_eq!(expand("!1:x"), Ok("'ls' '-l' '/tmp'".to_string()));
+        assert_eq!(expand("!1:s/l/L/"), Ok("Ls -l /tmp".to_string()));
+        assert_eq!(expand("!1:gs/l/L/"), Ok("Ls -L /tmp".to_string()));
+        assert_eq!(expand("!1:s/tmp/[&]/"), Ok("ls -l /[tmp]".to_string()));
+        assert_eq!(
+            expand("!1:s/x/y/"),
+            Err(":s/x/y/: substitution failed".to_string())
+        );
+        assert_eq!(
+            expand("!1:z"),
+            Err(":z: unrecognized history modifier".to_string())
+        );
+
+        let expansion = HistoryExpander::new().expand("!1:p", &history()).unwrap();
+        assert!(expansion.print_only);
+    }
+
+    #[test]
+    fn substitutes_with_carets_and_repeats() {
+        assert_eq!(
+            expand("^main^lib"),
+            Ok("cat src/lib.rs docs/notes.txt".to_string())
+        );
+
+        let mut expander = HistoryExpander::new();
+        let entries = history();
+        expander.expand("!1:s/l/L/", &entries).unwrap();
+        let repeated = expander.expand("!1:g&", &entries).unwrap();
+        assert_eq!(repeated.line, "Ls -L /tmp");
+    }
+
+    #[test]
+    fn leaves_quoted_and_plain_text_alone() {
+        let expansion = HistoryExpander::new()
+            .expand("echo '!!' \\!! ! != !(x)", &history())
+            .unwrap();
+        assert_eq!(expansion.line, "echo '!!' \\!! ! != !(x)");
+        assert!(!expansion.changed);
+    }
+
+    #[test]
+    fn splits_words_keeping_quotes_and_operators() {
+        assert_eq!(
+            split_words("echo 'a b' c\\ d|wc >>out"),
+            ["echo", "'a b'", "c\\ d", "|", "wc", ">>", "out"]
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::cmp::Reverse;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, RepeatCount};
+use unicode_width::UnicodeWidthChar;
+
+use crate::fuzzy::{self, MatchKind};
+use crate::utils;
+
+const MAX_ROWS: usize = 10;
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+const CWD_WIDTH: usize = 20;
+
+// A history line with where and when it was entered, when that was recorded
+pub struct Candidate {
+    pub line: String,
+    pub time: Option<i64>,
+    pub cwd: Option<String>,
+}
+
+pub enum Selection {
+    // Run the line at once
+    Accept(String),
+    // Put the line back in the editor
+    Edit(String),
+    Cancel,
+}
+
+// Ctrl-R accepts the line being edited so the shell can open the picker outside the editor
+pub struct SearchKey {
+    pub pending: Arc<Mutex<Option<String>>>,
+}
+
+impl ConditionalEventHandler for SearchKey {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        *self.pending.lock().ok()? = Some(ctx.line().to_string());
+        Some(Cmd::AcceptLine)
+    }
+}
+
+enum Key {
+    Text(char),
+    Backspace,
+    ClearQuery,
+    DeleteWord,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Accept,
+    Edit,
+    Cancel,
+    Ignored,
+}
+
+// Lets the user filter `candidates` (newest first) and choose one; `query` is the text already
+// typed at the prompt. The picker is drawn below the cursor and erased again afterwards
+pub fn pick(candidates: &[Candidate], query: &str) -> Selection {
+    let Some(_raw_mode) = RawMode::enable() else {
+        return Selection::Cancel;
+    };
+    let (columns, lines) = terminal_size();
+    let rows = MAX_ROWS.min(lines.saturating_sub(2)).max(1);
+    let mut query = query.to_string();
+    let mut matches = filter(candidates, &query);
+    let mut selected = 0;
+    let mut offset = 0;
+
+    // Makes room first so the terminal scrolls before anything is drawn
+    print!("{}\x1b[{}A", "\r\n".repeat(rows), rows);
+    let selection = loop {
+        selected = selected.min(matches.len().saturating_sub(1));
+        offset = offset
+            .min(selected)
+            .max((selected + 1).saturating_sub(rows));
+        draw(
+            candidates, &matches, &query, selected, offset, rows, columns,
+        );
+
+        let chosen = || candidates[matches[selected]].line.clone();
+        let previous = query.clone();
+        match read_key() {
+            Key::Text(c) => query.push(c),
+            Key::Backspace => {
+                query.pop();
+            }
+            Key::ClearQuery => query.clear(),
+            Key::DeleteWord => {
+                let kept = query.trim_end().rfind(' ').map_or(0, |index| index + 1);
+                query.truncate(kept);
+            }
+            Key::Up => selected = selected.saturating_sub(1),
+            Key::Down => selected += 1,
+            Key::PageUp => selected = selected.saturating_sub(rows),
+            Key::PageDown => selected += rows,
+            Key::Accept if !matches.is_empty() => break Selection::Accept(chosen()),
+            Key::Edit if !matches.is_empty() => break Selection::Edit(chosen()),
+            Key::Accept | Key::Edit | Key::Cancel => break Selection::Cancel,
+            Key::Ignored => continue,
+        }
+        if query != previous {
+            matches = filter(candidates, &query);
+            selected = 0;
+        }
+    };
+    print!("\r\x1b[J");
+    let _ = io::stdout().flush();
+    selection
+}
+
+// Indexes of the matching candidates, best first: by match kind and score, then the newest
+fn filter(candidates: &[Candidate], query: &str) -> Vec<usize> {
+    if query.is_empty() {
+        return (0..candidates.len()).collect();
+    }
+    let mut matches: Vec<((MatchKind, Reverse<i32>), usize)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, candidate)| {
+            let (kind, score) = fuzzy::fuzzy_match(query, &candidate.line)?;
+            Some(((kind, Reverse(score)), index))
+        })
+        .collect();
+    matches.sort();
+    matches.into_iter().map(|(_, index)| index).collect()
+}
+
+fn draw(
+    candidates: &[Candidate],
+    matches: &[usize],
+    query: &str,
+    selected: usize,
+    offset: usize,
+    rows: usize,
+    columns: usize,
+) {
+    let header = format!("history [{}/{}]: ", matches.len(), candidates.len());
+    let mut screen = format!("\r\x1b[J{}{}", header, query);
+    let shown = matches.iter().skip(offset).take(rows);
+    for (row, &index) in shown.enumerate() {
+        let text = truncate(&describe(&candidates[index]), columns.saturating_sub(3));
+        if offset + row == selected {
+            screen += &format!("\r\n\x1b[7m> {}\x1b[0m", text);
+        } else {
+            screen += &format!("\r\n  {}", text);
+        }
+    }
+    let drawn = matches.len().saturating_sub(offset).min(rows);
+    if drawn > 0 {
+        screen += &format!("\x1b[{}A", drawn);
+    }
+    let column = width(&header) + width(query);
+    screen += &format!("\r\x1b[{}C", column.min(columns.saturating_sub(1)));
+    print!("{}", screen);
+    let _ = io::stdout().flush();
+}
+
+fn describe(candidate: &Candidate) -> String {
+    let time = candidate
+        .time
+        .map(|time| utils::format_time(TIME_FORMAT, time))
+        .unwrap_or_default();
+    let cwd = candidate
+        .cwd
+        .as_deref()
+        .map(utils::abbreviate_home)
+        .unwrap_or_default();
+    // Long directories keep their last components
+    let cwd = if width(&cwd) > CWD_WIDTH {
+        let mut used = 1;
+        let mut kept: Vec<char> = cwd
+            .chars()
+            .rev()
+            .take_while(|&c| {
+                used += c.width().unwrap_or(0);
+                used <= CWD_WIDTH
+            })
+            .collect();
+        kept.reverse();
+        format!("…{}", kept.into_iter().collect::<String>())
+    } else {
+        cwd
+    };
+    let padding = " ".repeat(CWD_WIDTH.saturating_sub(width(&cwd)));
+    format!(
+        "{:<16}  {}{}  {}",
+        time,
+        cwd,
+        padding,
+        candidate.line.replace('\n', " ↵ ")
+    )
+}
+
+// Display columns, so wide characters count twice
+fn width(text: &str) -> usize {
+    text.chars().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+fn truncate(text: &str, columns: usize) -> String {
+    let mut used = 0;
+    text.chars()
+        .take_while(|&c| {
+            used += c.width().unwrap_or(0);
+            used <= columns
+        })
+        .collect()
+}
+
+// Terminal rows `text` takes when written from the first column, wrapping as the line editor
+// does: a line that exactly fills the width leaves the cursor on the next row. Escape sequences,
+// such as a prompt's colours, take no room
+pub fn rows(text: &str, columns: usize) -> usize {
+    let mut rows = 1;
+    let mut column = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => {
+                rows += 1;
+                column = 0;
+            }
+            '\x1b' => skip_escape_sequence(&mut chars),
+            c => {
+                let width = c.width().unwrap_or(0);
+                column += width;
+                if column > columns {
+                    rows += 1;
+                    column = width;
+                }
+            }
+        }
+    }
+    if column >= columns {
+        rows += 1;
+    }
+    rows
+}
+
+// CSI sequences end with a byte from `@` to `~`, OSC sequences (window titles) with BEL or ST
+fn skip_escape_sequence(chars: &mut std::str::Chars) {
+    match chars.next() {
+        Some('[') => {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+        Some(']') => {
+            while let Some(c) = chars.next() {
+                if c == '\x07' {
+                    break;
+                }
+                if c == '\x1b' {
+                    chars.next();
+                    break;
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn read_key() -> Key {
+    let Some(byte) = read_byte(None) else {
+        return Key::Cancel;
+    };
+    match byte {
+        b'\r' | b'\n' => Key::Accept,
+        b'\t' | 0x05 | 0x06 => Key::Edit,
+        0x03 | 0x07 => Key::Cancel,
+        0x7f | 0x08 => Key::Backspace,
+        0x15 => Key::ClearQuery,
+        0x17 => Key::DeleteWord,
+        0x10 => Key::Up,
+        0x0e | 0x12 => Key::Down,
+        0x1b => read_escape_sequence(),
+        byte if byte >= 0x20 => read_character(byte),
+        _ => Key::Ignored,
+    }
+}
+
+// A lone Escape cancels; arrow keys come as `ESC [ A` or `ESC O A`, paging keys as `ESC [ 5 ~`
+fn read_escape_sequence() -> Key {
+    let Some(introducer) = read_byte(Some(Duration::from_millis(50))) else {
+        return Key::Cancel;
+    };
+    if introducer != b'[' && introducer != b'O' {
+        return Key::Ignored;
+    }
+    let mut parameters = String::new();
+    loop {
+        match read_byte(Some(Duration::from_millis(50))) {
+            Some(byte @ (b'0'..=b'9' | b';')) => parameters.push(byte as char),
+            Some(b'A') => return Key::Up,
+            Some(b'B') => return Key::Down,
+            Some(b'C') => return Key::Edit,
+            Some(b'~') if parameters == "5" => return Key::PageUp,
+            Some(b'~') if parameters == "6" => return Key::PageDown,
+            _ => return Key::Ignored,
+        }
+    }
+}
+
+fn read_character(first: u8) -> Key {
+    let length = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..length {
+        match read_byte(Some(Duration::from_millis(50))) {
+            Some(byte) => bytes.push(byte),
+            None => return Key::Ignored,
+        }
+    }
+    match std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|text| text.chars().next())
+    {
+        Some(c) => Key::Text(c),
+        None => Key::Ignored,
+    }
+}
+
+fn read_byte(timeout: Option<Duration>) -> Option<u8> {
+    if let Some(timeout) = timeout {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as i32) } <= 0 {
+            return None;
+        }
+    }
+    let mut byte = 0u8;
+    let read = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+    (read == 1).then_some(byte)
+}
+
+pub fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result != 0 || size.ws_col == 0 || size.ws_row == 0 {
+        return (80, 24);
+    }
+    (size.ws_col as usize, size.ws_row as usize)
+}
+
+// Reads keys one at a time with nothing echoed and Ctrl-C delivered as a key
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> Option<Self> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return None;
+        }
+        let mut mode = original;
+        mode.c_lflag &= !(libc::ECHO | libc::ICANON | libc::ISIG | libc::IEXTEN);
+        mode.c_iflag &= !(libc::ICRNL | libc::IXON);
+        mode.c_cc[libc::VMIN] = 1;
+        mode.c_cc[libc::VTIME] = 0;
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &mode) };
+        Some(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_by_display_width() {
+        assert_eq!(truncate("abcdef", 4), "abcd");
+        assert_eq!(truncate("日本語", 4), "日本");
+        assert_eq!(truncate("日本語", 5), "日本");
+        assert_eq!(width("a日b"), 4);
+    }
+
+    #[test]
+    fn counts_wrapped_rows() {
+        assert_eq!(rows("$ ls", 10), 1);
+        assert_eq!(rows("$ echo hello world", 10), 2);
+        assert_eq!(rows("0123456789", 10), 2);
+        assert_eq!(rows("> 日本語日本語", 10), 2);
+        assert_eq!(rows("one\n$ two", 10), 2);
+        assert_eq!(rows("\x1b[1;32muser\x1b[0m \x1b]0;title\x07$ ", 10), 1);
+    }
+}
@@ -0,0 +1,856 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::{Arc, Mutex};
+
+use rustyline::config::{BellStyle, Configurer};
+use rustyline::history::DefaultHistory;
+use rustyline::{
+    Anchor, At, Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler, KeyCode,
+    KeyEvent, Modifiers, Movement, RepeatCount, Word,
+};
+
+use crate::autocompletion::BuiltInCompleter;
+use crate::options::ShellOptions;
+use crate::output::CommandOutput;
+use crate::utils;
+
+type LineEditor = Editor<BuiltInCompleter, DefaultHistory>;
+
+// A `bind -x` command waiting to run, with the line and cursor position it was typed at
+pub struct KeyCommand {
+    pub command: String,
+    pub line: String,
+    pub point: usize,
+}
+
+enum Action {
+    Function(String),
+    Macro(String),
+    ShellCommand(String),
+}
+
+// Readline variables and key bindings from `~/.inputrc` and `bind`, applied to the editor
+pub struct KeyBindings {
+    variables: BTreeMap<&'static str, String>,
+    bindings: BTreeMap<String, Action>,
+    pending: Arc<Mutex<Option<KeyCommand>>>,
+}
+
+const USAGE: &str = "bind: usage: bind [-lpvPVX] [-m keymap] [-f filename] [-r keyseq] [-x keyseq:shell-command] [keyseq:readline-function or readline-command]\n";
+
+// The variables understood, with their defaults; `editing-mode` lives in the shell options
+const VARIABLES: &[(&str, &str)] = &[
+    ("bell-style", "audible"),
+    ("completion-ignore-case", "off"),
+    ("completion-query-items", "100"),
+    ("keyseq-timeout", "500"),
+    ("show-all-if-ambiguous", "off"),
+];
+
+// Readline function names and the editor command each one runs
+type Function = (&'static str, fn() -> Cmd);
+
+const FUNCTIONS: &[Function] = &[
+    ("abort", || Cmd::Abort),
+    ("accept-line", || Cmd::AcceptLine),
+    ("backward-char", || Cmd::Move(Movement::BackwardChar(1))),
+    ("backward-delete-char", || {
+        Cmd::Kill(Movement::BackwardChar(1))
+    }),
+    ("backward-kill-line", || {
+        Cmd::Kill(Movement::BeginningOfLine)
+    }),
+    ("backward-kill-word", || {
+        Cmd::Kill(Movement::BackwardWord(1, Word::Emacs))
+    }),
+    ("backward-word", || {
+        Cmd::Move(Movement::BackwardWord(1, Word::Emacs))
+    }),
+    ("beginning-of-history", || Cmd::BeginningOfHistory),
+    ("beginning-of-line", || Cmd::Move(Movement::BeginningOfLine)),
+    ("capitalize-word", || Cmd::CapitalizeWord),
+    ("clear-screen", || Cmd::ClearScreen),
+    ("complete", || Cmd::Complete),
+    ("delete-char", || Cmd::Kill(Movement::ForwardChar(1))),
+    ("downcase-word", || Cmd::DowncaseWord),
+    ("end-of-file", || Cmd::EndOfFile),
+    ("end-of-history", || Cmd::EndOfHistory),
+    ("end-of-line", || Cmd::Move(Movement::EndOfLine)),
+    ("forward-char", || Cmd::Move(Movement::ForwardChar(1))),
+    ("forward-search-history", || Cmd::ForwardSearchHistory),
+    ("forward-word", || {
+        Cmd::Move(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs))
+    }),
+    ("history-search-backward", || Cmd::HistorySearchBackward),
+    ("history-search-forward", || Cmd::HistorySearchForward),
+    ("kill-line", || Cmd::Kill(Movement::EndOfLine)),
+    ("kill-whole-line", || Cmd::Kill(Movement::WholeLine)),
+    ("kill-word", || {
+        Cmd::Kill(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs))
+    }),
+    ("next-history", || Cmd::NextHistory),
+    ("previous-history", || Cmd::PreviousHistory),
+    ("quoted-insert", || Cmd::QuotedInsert),
+    ("redraw-current-line", || Cmd::Repaint),
+    ("reverse-search-history", || Cmd::ReverseSearchHistory),
+    ("tab-insert", || Cmd::Insert(1, "\t".to_string())),
+    ("transpose-chars", || Cmd::TransposeChars),
+    ("transpose-words", || Cmd::TransposeWords(1)),
+    ("undo", || Cmd::Undo(1)),
+    ("unix-line-discard", || Cmd::Kill(Movement::BeginningOfLine)),
+    ("unix-word-rubout", || {
+        Cmd::Kill(Movement::BackwardWord(1, Word::Big))
+    }),
+    ("upcase-word", || Cmd::UpcaseWord),
+    ("yank", || Cmd::Yank(1, Anchor::Before)),
+    ("yank-pop", || Cmd::YankPop),
+];
+
+impl KeyBindings {
+    pub fn new() -> Self {
+        Self {
+            variables: VARIABLES
+                .iter()
+                .map(|(name, value)| (*name, value.to_string()))
+                .collect(),
+            bindings: BTreeMap::new(),
+            pending: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // The `bind -x` command whose key ended the last read, if any
+    pub fn take_key_command(&self) -> Option<KeyCommand> {
+        self.pending.lock().ok()?.take()
+    }
+
+    // `$INPUTRC`, else `~/.inputrc`, else `/etc/inputrc`; errors are reported with the line number
+    pub fn read_default_init_file(&mut self, editor: &mut LineEditor, options: &mut ShellOptions) {
+        let home = utils::home_directory().map(|home| format!("{}/.inputrc", home));
+        let file = env::var("INPUTRC")
+            .ok()
+            .filter(|file| !file.is_empty())
+            .into_iter()
+            .chain(home)
+            .chain(Some("/etc/inputrc".to_string()))
+            .find(|file| fs::metadata(file).is_ok_and(|metadata| metadata.is_file()));
+        if let Some(file) = file {
+            if let Err(message) = self.read_init_file(&file, editor, options) {
+                for line in message.lines() {
+                    eprintln!("shell-rs: {}", line);
+                }
+            }
+        }
+    }
+
+    fn read_init_file(
+        &mut self,
+        file: &str,
+        editor: &mut LineEditor,
+        options: &mut ShellOptions,
+    ) -> Result<(), String> {
+        let contents = fs::read_to_string(file)
+            .map_err(|e| format!("{}: {}\n", file, utils::io_error_message(&e)))?;
+        let mut errors = String::new();
+        // Whether each enclosing `$if` is being followed
+        let mut conditions: Vec<bool> = Vec::new();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let active = conditions.iter().all(|condition| *condition);
+            let result = if let Some(test) = line.strip_prefix("$if") {
+                conditions.push(active && self.test_condition(test.trim(), options));
+                Ok(())
+            } else if line.starts_with("$else") {
+                let outer =
+                    conditions.len() < 2 || conditions[..conditions.len() - 1].iter().all(|c| *c);
+                match conditions.last_mut() {
+                    Some(condition) => {
+                        *condition = outer && !*condition;
+                        Ok(())
+                    }
+                    None => Err("$else found without matching $if".to_string()),
+                }
+            } else if line.starts_with("$endif") {
+                conditions
+                    .pop()
+                    .map(|_| ())
+                    .ok_or_else(|| "$endif without matching $if".to_string())
+            } else if !active {
+                Ok(())
+            } else if let Some(included) = line.strip_prefix("$include") {
+                let included = included.trim();
+                let included = match (included.strip_prefix("~/"), utils::home_directory()) {
+                    (Some(rest), Some(home)) => format!("{}/{}", home, rest),
+                    _ => included.to_string(),
+                };
+                // Its errors already name the included file
+                if let Err(message) = self.read_init_file(&included, editor, options) {
+                    errors += &message;
+                }
+                Ok(())
+            } else {
+                self.parse_line(line, editor, options)
+            };
+            if let Err(message) = result {
+                errors += &format!("{}: line {}: {}\n", file, index + 1, message);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // `mode=emacs`/`mode=vi`, `term=NAME` (or the part of `$TERM` before a `-`), or the
+    // application name
+    fn test_condition(&self, test: &str, options: &ShellOptions) -> bool {
+        if let Some(mode) = test.strip_prefix("mode=") {
+            return (mode == "vi") == options.vi;
+        }
+        if let Some(name) = test.strip_prefix("term=") {
+            let term = env::var("TERM").unwrap_or_default();
+            return term == name || term.split('-').next() == Some(name);
+        }
+        test == "shell-rs"
+    }
+
+    // One line of inputrc syntax: `set variable value`, `"keyseq": function-name`,
+    // `"keyseq": "macro text"` or `Control-x: function-name`
+    fn parse_line(
+        &mut self,
+        line: &str,
+        editor: &mut LineEditor,
+        options: &mut ShellOptions,
+    ) -> Result<(), String> {
+        if let Some(setting) = line.strip_prefix("set ") {
+            let mut words = setting.split_whitespace();
+            let name = words.next().unwrap_or_default();
+            let value = words.next().unwrap_or_default();
+            self.set_variable(name, value, editor, options);
+            return Ok(());
+        }
+
+        let (keys, value) = split_binding(line)?;
+        let action = if value.starts_with(['"', '\'']) {
+            Action::Macro(unescape(strip_quotes(value)).into_iter().collect())
+        } else {
+            let name = value.split_whitespace().next().unwrap_or_default();
+            if function_command(name).is_none() {
+                return Err(format!("{}: unknown function name", name));
+            }
+            Action::Function(name.to_string())
+        };
+        self.add_binding(keys, action, editor)
+    }
+
+    // Unknown variables are ignored, as readline does
+    fn set_variable(
+        &mut self,
+        name: &str,
+        value: &str,
+        editor: &mut LineEditor,
+        options: &mut ShellOptions,
+    ) {
+        let enabled = value.eq_ignore_ascii_case("on") || value == "1";
+        match name {
+            "editing-mode" => match value {
+                "vi" | "emacs" => {
+                    options.vi = value == "vi";
+                    options.emacs = value == "emacs";
+                }
+                _ => return,
+            },
+            "bell-style" => {
+                let style = match value {
+                    "none" | "off" => BellStyle::None,
+                    "visible" => BellStyle::Visible,
+                    _ => BellStyle::Audible,
+                };
+                editor.set_bell_style(style);
+            }
+            "completion-ignore-case" => {
+                if let Some(helper) = editor.helper_mut() {
+                    helper.set_ignore_case(enabled);
+                }
+            }
+            "completion-query-items" => {
+                // A negative number means never ask
+                let limit = match value.parse::<i64>() {
+                    Ok(limit) if limit < 0 => usize::MAX,
+                    Ok(limit) => limit as usize,
+                    Err(_) => 100,
+                };
+                editor.set_completion_prompt_limit(limit);
+            }
+            "keyseq-timeout" => {
+                let timeout = value.parse::<u16>().unwrap_or(500);
+                editor.set_keyseq_timeout(Some(timeout));
+            }
+            // The editor always waits for a second Tab before listing, so this is only recorded
+            "show-all-if-ambiguous" => {}
+            _ => return,
+        }
+
+        if let Some((name, _)) = VARIABLES.iter().find(|(variable, _)| *variable == name) {
+            let value = match *name {
+                "bell-style" | "completion-query-items" | "keyseq-timeout" => value.to_string(),
+                _ if enabled => "on".to_string(),
+                _ => "off".to_string(),
+            };
+            self.variables.insert(name, value);
+        }
+    }
+
+    fn add_binding(
+        &mut self,
+        keys: &str,
+        action: Action,
+        editor: &mut LineEditor,
+    ) -> Result<(), String> {
+        let events = parse_key_sequence(keys)?;
+        let handler = match &action {
+            Action::Function(name) => EventHandler::Simple(
+                function_command(name).ok_or_else(|| format!("{}: unknown function name", name))?,
+            ),
+            Action::Macro(text) => EventHandler::Simple(Cmd::Insert(1, text.clone())),
+            Action::ShellCommand(command) => EventHandler::Conditional(Box::new(RunShellCommand {
+                command: command.clone(),
+                pending: Arc::clone(&self.pending),
+            })),
+        };
+        editor.bind_sequence(Event::KeySeq(events.clone()), handler);
+        self.bindings.insert(format_key_sequence(&events), action);
+        Ok(())
+    }
+
+    pub fn bind(
+        &mut self,
+        args: &[String],
+        editor: &mut LineEditor,
+        options: &mut ShellOptions,
+    ) -> CommandOutput {
+        let mut output = CommandOutput::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+                if let Err(message) = self.parse_line(arg, editor, options) {
+                    output.stderr += &format!("bind: {}\n", message);
+                    output.status = 1;
+                }
+                continue;
+            };
+            for flag in flags.chars() {
+                match flag {
+                    'l' => {
+                        for (name, _) in FUNCTIONS {
+                            output.push_stdout(&format!("{}\n", name));
+                        }
+                    }
+                    'p' => output.push_stdout(&self.listing(false)),
+                    'X' => output.push_stdout(&self.listing(true)),
+                    'P' => output.push_stdout(&self.function_keys()),
+                    'v' => output.push_stdout(&self.variable_listing(options, false)),
+                    'V' => output.push_stdout(&self.variable_listing(options, true)),
+                    'm' | 'f' | 'r' | 'x' => {
+                        let Some(value) = args.next() else {
+                            return CommandOutput::stderr(format!(
+                                "bind: -{}: option requires an argument\n{}",
+                                flag, USAGE
+                            ))
+                            .with_status(2);
+                        };
+                        let result = match flag {
+                            'f' => self.read_init_file(value, editor, options),
+                            'r' => self.remove_binding(value, editor),
+                            'x' => split_binding(value).and_then(|(keys, command)| {
+                                let command = strip_quotes(command).to_string();
+                                self.add_binding(keys, Action::ShellCommand(command), editor)
+                            }),
+                            // There is only one keymap per editing mode
+                            _ => Ok(()),
+                        };
+                        if let Err(message) = result {
+                            for line in message.lines() {
+                                output.stderr += &format!("bind: {}\n", line);
+                            }
+                            output.status = 1;
+                        }
+                    }
+                    _ => {
+                        return CommandOutput::stderr(format!(
+                            "bind: -{}: invalid option\n{}",
+                            flag, USAGE
+                        ))
+                        .with_status(2);
+                    }
+                }
+            }
+        }
+        output
+    }
+
+    fn remove_binding(&mut self, keys: &str, editor: &mut LineEditor) -> Result<(), String> {
+        // The key sequence may be given without its quotes
+        let events = match keys.starts_with('"') {
+            true => parse_key_sequence(keys)?,
+            false => parse_key_sequence(&format!("\"{}\"", keys))?,
+        };
+        editor.unbind_sequence(Event::KeySeq(events.clone()));
+        self.bindings.remove(&format_key_sequence(&events));
+        Ok(())
+    }
+
+    // `bind -p` lists functions and macros in inputrc syntax; `bind -X` the `-x` commands
+    fn listing(&self, shell_commands: bool) -> String {
+        self.bindings
+            .iter()
+            .filter_map(|(keys, action)| match action {
+                Action::Function(name) if !shell_commands => {
+                    Some(format!("\"{}\": {}\n", keys, name))
+                }
+                Action::Macro(text) if !shell_commands => {
+                    Some(format!("\"{}\": \"{}\"\n", keys, escape(text)))
+                }
+                Action::ShellCommand(command) if shell_commands => {
+                    Some(format!("\"{}\": \"{}\"\n", keys, command))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn function_keys(&self) -> String {
+        FUNCTIONS
+            .iter()
+            .map(|(name, _)| {
+                let keys: Vec<String> = self
+                    .bindings
+                    .iter()
+                    .filter(|(_, action)| matches!(action, Action::Function(function) if function == name))
+                    .map(|(keys, _)| format!("\"{}\"", keys))
+                    .collect();
+                if keys.is_empty() {
+                    format!("{} is not bound to any keys\n", name)
+                } else {
+                    format!("{} can be found on {}.\n", name, keys.join(", "))
+                }
+            })
+            .collect()
+    }
+
+    fn variable_listing(&self, options: &ShellOptions, readable: bool) -> String {
+        let mode = if options.vi { "vi" } else { "emacs" };
+        let mut variables: Vec<(&str, &str)> = self
+            .variables
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        variables.push(("editing-mode", mode));
+        variables.sort();
+        variables
+            .into_iter()
+            .map(|(name, value)| match readable {
+                true => format!("{} is set to `{}'\n", name, value),
+                false => format!("set {} {}\n", name, value),
+            })
+            .collect()
+    }
+}
+
+fn function_command(name: &str) -> Option<Cmd> {
+    FUNCTIONS
+        .iter()
+        .find(|(function, _)| *function == name)
+        .map(|(_, command)| command())
+}
+
+// Records the line and cursor position, then ends the read so the shell can run the command
+struct RunShellCommand {
+    command: String,
+    pending: Arc<Mutex<Option<KeyCommand>>>,
+}
+
+impl ConditionalEventHandler for RunShellCommand {
+    fn handle(
+        &self,
+        _evt: &Event,
+        _n: RepeatCount,
+        _positive: bool,
+        ctx: &EventContext,
+    ) -> Option<Cmd> {
+        let mut pending = self.pending.lock().ok()?;
+        *pending = Some(KeyCommand {
+            command: self.command.clone(),
+            line: ctx.line().to_string(),
+            point: ctx.line()[..ctx.pos()].chars().count(),
+        });
+        Some(Cmd::AcceptLine)
+    }
+}
+
+// Splits `"keyseq": value` or `Key-name: value` at the colon after the key part
+fn split_binding(line: &str) -> Result<(&str, &str), String> {
+    let key_end = if line.starts_with('"') {
+        let mut escaped = false;
+        line.char_indices()
+            .skip(1)
+            .find(|(_, c)| {
+                let closes = *c == '"' && !escaped;
+                escaped = *c == '\\' && !escaped;
+                closes
+            })
+            .map(|(index, _)| index + 1)
+            .ok_or_else(|| "no closing `\"' in key binding".to_string())?
+    } else {
+        line.find(':').unwrap_or(line.len())
+    };
+    let value = line[key_end..]
+        .trim_start()
+        .strip_prefix(':')
+        .ok_or_else(|| format!("{}: no key sequence terminator", line))?;
+    Ok((&line[..key_end], value.trim()))
+}
+
+fn strip_quotes(text: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = text
+            .strip_prefix(quote)
+            .and_then(|text| text.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    text
+}
+
+pub fn parse_key_sequence(keys: &str) -> Result<Vec<KeyEvent>, String> {
+    let chars = match keys.strip_prefix('"') {
+        Some(quoted) => unescape(quoted.strip_suffix('"').unwrap_or(quoted)),
+        None => key_name(keys).ok_or_else(|| format!("{}: unknown key name", keys))?,
+    };
+    if chars.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+    chars_to_keys(&chars)
+}
+
+// `Control-u`, `C-u`, `Meta-Rubout`, `M-x` and the names `DEL`, `ESC`, `LFD`, `RET`, `SPC`, `TAB`
+// with their long forms; a Meta key is an escape followed by the key
+fn key_name(name: &str) -> Option<Vec<char>> {
+    let lower = name.to_ascii_lowercase();
+    for prefix in ["control-", "c-"] {
+        if lower.starts_with(prefix) {
+            let mut keys = key_name(&name[prefix.len()..])?;
+            let last = keys.last_mut()?;
+            *last = control(last.to_ascii_lowercase());
+            return Some(keys);
+        }
+    }
+    for prefix in ["meta-", "m-"] {
+        if lower.starts_with(prefix) {
+            let mut keys = key_name(&name[prefix.len()..])?;
+            keys.insert(0, '\x1b');
+            return Some(keys);
+        }
+    }
+    let c = match lower.as_str() {
+        "del" | "rubout" => '\x7f',
+        "esc" | "escape" => '\x1b',
+        "lfd" | "newline" => '\n',
+        "ret" | "return" => '\r',
+        "spc" | "space" => ' ',
+        "tab" => '\t',
+        _ => {
+            let mut chars = name.chars();
+            let c = chars.next()?;
+            return chars.next().is_none().then(|| vec![c]);
+        }
+    };
+    Some(vec![c])
+}
+
+fn control(c: char) -> char {
+    match c {
+        '?' => '\x7f',
+        c if c.is_ascii() => ((c.to_ascii_uppercase() as u8) & 0x1f) as char,
+        c => c,
+    }
+}
+
+// Readline's backslash escapes: `\C-x`, `\M-x`, `\e`, `\a`, `\d`, `\t`, `\nnn`, `\xHH` and so on
+fn unescape(text: &str) -> Vec<char> {
+    let mut chars = text.chars().peekable();
+    let mut result = Vec::new();
+    while let Some(next) = unescape_next(&mut chars) {
+        result.extend(next);
+    }
+    result
+}
+
+// One character, or an escape and a character for `\M-`
+fn unescape_next(chars: &mut Peekable<Chars>) -> Option<Vec<char>> {
+    let c = chars.next()?;
+    if c != '\\' {
+        return Some(vec![c]);
+    }
+    let Some(escaped) = chars.next() else {
+        return Some(vec!['\\']);
+    };
+    if matches!(escaped, 'C' | 'M') && chars.peek() == Some(&'-') {
+        chars.next();
+        let mut target = unescape_next(chars).unwrap_or_default();
+        match escaped {
+            'C' => {
+                if let Some(last) = target.last_mut() {
+                    *last = control(*last);
+                }
+            }
+            _ => target.insert(0, '\x1b'),
+        }
+        return Some(target);
+    }
+
+    let mut number = |radix: u32, digits: usize, initial: u32| {
+        let mut value = initial;
+        for _ in 0..digits {
+            match chars.peek().and_then(|c| c.to_digit(radix)) {
+                Some(digit) => value = value * radix + digit,
+                None => break,
+            }
+            chars.next();
+        }
+        char::from_u32(value).unwrap_or_default()
+    };
+    let c = match escaped {
+        'a' => '\x07',
+        'b' => '\x08',
+        'd' => '\x7f',
+        'e' => '\x1b',
+        'f' => '\x0c',
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        'v' => '\x0b',
+        'x' => number(16, 2, 0),
+        '0'..='7' => number(8, 2, escaped.to_digit(8).unwrap_or_default()),
+        other => other,
+    };
+    Some(vec![c])
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            '\n' => "\\n".to_string(),
+            '\t' => "\\t".to_string(),
+            '\x1b' => "\\e".to_string(),
+            c if c.is_control() => format!("\\{:03o}", c as u32),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+// The editor decodes terminal escape sequences itself, so `\e[A` has to become the Up key
+fn chars_to_keys(chars: &[char]) -> Result<Vec<KeyEvent>, String> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        if c != '\x1b' || i == chars.len() {
+            keys.push(KeyEvent::new(c, Modifiers::NONE));
+            continue;
+        }
+        let next = chars[i];
+        i += 1;
+        if next != '[' && next != 'O' {
+            keys.push(KeyEvent::new(next, Modifiers::ALT));
+            continue;
+        }
+        let end = chars[i..]
+            .iter()
+            .position(|c| !c.is_ascii_digit() && *c != ';')
+            .map(|offset| i + offset)
+            .ok_or_else(|| "incomplete escape sequence".to_string())?;
+        let parameters: String = chars[i..end].iter().collect();
+        let final_char = chars[end];
+        i = end + 1;
+        let mut fields = parameters.split(';');
+        let number = fields.next().unwrap_or_default();
+        let modifiers = match fields.next().and_then(|field| field.parse::<u8>().ok()) {
+            Some(code) if code > 1 => {
+                let bits = code - 1;
+                let mut modifiers = Modifiers::NONE;
+                if bits & 1 != 0 {
+                    modifiers |= Modifiers::SHIFT;
+                }
+                if bits & 2 != 0 {
+                    modifiers |= Modifiers::ALT;
+                }
+                if bits & 4 != 0 {
+                    modifiers |= Modifiers::CTRL;
+                }
+                modifiers
+            }
+            _ => Modifiers::NONE,
+        };
+        let code = match (final_char, number) {
+            ('A', _) => KeyCode::Up,
+            ('B', _) => KeyCode::Down,
+            ('C', _) => KeyCode::Right,
+            ('D', _) => KeyCode::Left,
+            ('H', _) | ('~', "1" | "7") => KeyCode::Home,
+            ('F', _) | ('~', "4" | "8") => KeyCode::End,
+            ('~', "2") => KeyCode::Insert,
+            ('~', "3") => KeyCode::Delete,
+            ('~', "5") => KeyCode::PageUp,
+            ('~', "6") => KeyCode::PageDown,
+            _ => {
+                return Err(format!(
+                    "\\e{}{}{}: unsupported escape sequence",
+                    next, parameters, final_char
+                ))
+            }
+        };
+        keys.push(KeyEvent(code, modifiers));
+    }
+    Ok(keys)
+}
+
+// The inverse of `parse_key_sequence`, for listings
+fn format_key_sequence(keys: &[KeyEvent]) -> String {
+    keys.iter()
+        .map(|KeyEvent(code, modifiers)| {
+            let mut text = String::new();
+            let special = |number: &str, letter: char| {
+                let bits = u8::from(modifiers.contains(Modifiers::SHIFT))
+                    | u8::from(modifiers.contains(Modifiers::ALT)) << 1
+                    | u8::from(modifiers.contains(Modifiers::CTRL)) << 2;
+                match (bits, letter) {
+                    (0, '~') => format!("\\e[{}~", number),
+                    (0, _) => format!("\\e[{}", letter),
+                    (_, '~') => format!("\\e[{};{}~", number, bits + 1),
+                    _ => format!("\\e[1;{}{}", bits + 1, letter),
+                }
+            };
+            match code {
+                KeyCode::Up => return special("", 'A'),
+                KeyCode::Down => return special("", 'B'),
+                KeyCode::Right => return special("", 'C'),
+                KeyCode::Left => return special("", 'D'),
+                KeyCode::Home => return special("", 'H'),
+                KeyCode::End => return special("", 'F'),
+                KeyCode::Insert => return special("2", '~'),
+                KeyCode::Delete => return special("3", '~'),
+                KeyCode::PageUp => return special("5", '~'),
+                KeyCode::PageDown => return special("6", '~'),
+                _ => {}
+            }
+            if modifiers.contains(Modifiers::ALT) {
+                text.push_str("\\e");
+            }
+            match code {
+                KeyCode::Char(c) if modifiers.contains(Modifiers::CTRL) => {
+                    text.push_str(&format!("\\C-{}", c.to_ascii_lowercase()));
+                }
+                KeyCode::Char(c) => text.push_str(&escape(&c.to_string())),
+                KeyCode::Tab => text.push_str("\\t"),
+                KeyCode::Enter => text.push_str("\\C-m"),
+                KeyCode::Esc => text.push_str("\\e"),
+                KeyCode::Backspace => text.push_str("\\C-?"),
+                other => text.push_str(&format!("{:?}", other)),
+            }
+            text
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_sequences() {
+        assert_eq!(
+            parse_key_sequence("\"\\C-x\\C-r\""),
+            Ok(vec![KeyEvent::ctrl('X'), KeyEvent::ctrl('R')])
+        );
+        assert_eq!(
+            parse_key_sequence("\"\\M-f\""),
+            Ok(vec![KeyEvent::alt('f')])
+        );
+        assert_eq!(parse_key_sequence("\"\\ef\""), Ok(vec![KeyEvent::alt('f')]));
+        assert_eq!(
+            parse_key_sequence("\"ab\""),
+            Ok(vec![KeyEvent::from('a'), KeyEvent::from('b')])
+        );
+        assert_eq!(
+            parse_key_sequence("\"\\x41\""),
+            Ok(vec![KeyEvent::from('A')])
+        );
+        assert_eq!(
+            parse_key_sequence("\"\\101\""),
+            Ok(vec![KeyEvent::from('A')])
+        );
+    }
+
+    #[test]
+    fn parses_terminal_escape_sequences() {
+        assert_eq!(
+            parse_key_sequence("\"\\e[A\""),
+            Ok(vec![KeyEvent(KeyCode::Up, Modifiers::NONE)])
+        );
+        assert_eq!(
+            parse_key_sequence("\"\\e[1;5C\""),
+            Ok(vec![KeyEvent(KeyCode::Right, Modifiers::CTRL)])
+        );
+        assert_eq!(
+            parse_key_sequence("\"\\e[3~\""),
+            Ok(vec![KeyEvent(KeyCode::Delete, Modifiers::NONE)])
+        );
+        assert_eq!(
+            parse_key_sequence("\"\\e[1\""),
+            Err("incomplete escape sequence".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_key_names() {
+        assert_eq!(
+            parse_key_sequence("Control-u"),
+            Ok(vec![KeyEvent::ctrl('U')])
+        );
+        assert_eq!(parse_key_sequence("C-u"), Ok(vec![KeyEvent::ctrl('U')]));
+        assert_eq!(parse_key_sequence("M-x"), Ok(vec![KeyEvent::alt('x')]));
+        assert_eq!(
+            parse_key_sequence("TAB"),
+            Ok(vec![KeyEvent(KeyCode::Tab, Modifiers::NONE)])
+        );
+        assert_eq!(
+            parse_key_sequence("Meta-Rubout"),
+            Ok(vec![KeyEvent::new('\x7f', Modifiers::ALT)])
+        );
+    }
+
+    #[test]
+    fn rejects_empty_and_unknown_keys() {
+        assert_eq!(
+            parse_key_sequence("\"\""),
+            Err("empty key sequence".to_string())
+        );
+        assert_eq!(
+            parse_key_sequence("Hyper-x"),
+            Err("Hyper-x: unknown key name".to_string())
+        );
+    }
+}
@@ -1,15 +1,54 @@
+mod alias;
 mod autocompletion;
 mod builtins;
 mod command;
+mod command_index;
+mod command_lookup;
+mod conditional;
+mod dirstack;
+mod fuzzy;
+mod git;
+mod git_objects;
+mod git_prompt;
+mod highlight;
+mod history;
+mod history_expansion;
+mod history_search;
+mod key_bindings;
 mod output;
+mod options;
 mod parser;
+mod path_completion;
+mod pattern;
+mod printf;
+mod programmable_completion;
+mod prompt;
+mod read;
 mod redirection;
 mod shell;
+mod startup;
+mod tool_completion;
 mod utils;
+mod validation;
+mod variables;
+
+use std::env;
+use std::process::exit;
 
 use crate::shell::Shell;
+use crate::startup::StartupOptions;
 
 fn main() {
-    let mut shell = Shell::new();
+    let options = match StartupOptions::from_args(env::args()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("shell-rs: {}", message);
+            exit(2);
+        }
+    };
+
+    let mut shell = Shell::new(options.interactive);
+    shell.load_startup_files(&options);
+    shell.load_history();
     shell.run();
 }
\ No newline at end of file
@@ -0,0 +1,118 @@
+use crate::output::CommandOutput;
+use crate::variables::Variables;
+
+// Options toggled with `set -o name`/`set +o name`, listed in `set -o` order
+#[derive(Default)]
+pub struct ShellOptions {
+    pub emacs: bool,
+    pub fuzzycomplete: bool,
+    pub vi: bool,
+    pub xtrace: bool,
+}
+
+const USAGE: &str = "set: usage: set [-x] [-o option-name] [--] [arg ...]\n";
+
+impl ShellOptions {
+    pub fn new() -> Self {
+        Self {
+            emacs: true,
+            ..Self::default()
+        }
+    }
+
+    fn names(&self) -> Vec<(&'static str, bool)> {
+        vec![
+            ("emacs", self.emacs),
+            ("fuzzycomplete", self.fuzzycomplete),
+            ("vi", self.vi),
+            ("xtrace", self.xtrace),
+        ]
+    }
+
+    fn option_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "emacs" => Some(&mut self.emacs),
+            "fuzzycomplete" => Some(&mut self.fuzzycomplete),
+            "vi" => Some(&mut self.vi),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    fn flag_name(flag: char) -> Option<&'static str> {
+        match flag {
+            'x' => Some("xtrace"),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, args: &[String], variables: &Variables) -> CommandOutput {
+        if args.is_empty() {
+            return CommandOutput::stdout(variables.listing());
+        }
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" || arg == "-" {
+                break;
+            }
+            let enable = match arg.chars().next() {
+                Some('-') => true,
+                Some('+') => false,
+                _ => break,
+            };
+
+            for flag in arg.chars().skip(1) {
+                let name = if flag == 'o' {
+                    match args.next() {
+                        Some(name) => name.as_str(),
+                        None => return self.listing(enable),
+                    }
+                } else {
+                    match Self::flag_name(flag) {
+                        Some(name) => name,
+                        None => {
+                            return CommandOutput::stderr(format!(
+                                "set: {}{}: invalid option\n{}",
+                                if enable { '-' } else { '+' },
+                                flag,
+                                USAGE
+                            ))
+                            .with_status(2)
+                        }
+                    }
+                };
+                match self.option_mut(name) {
+                    Some(option) => *option = enable,
+                    None => {
+                        return CommandOutput::stderr(format!("set: {}: invalid option name\n", name))
+                            .with_status(2)
+                    }
+                }
+                // The editing modes exclude each other; turning one off selects the other
+                match name {
+                    "emacs" => self.vi = !enable,
+                    "vi" => self.emacs = !enable,
+                    _ => {}
+                }
+            }
+        }
+        CommandOutput::default()
+    }
+
+    // `set -o` prints a table; `set +o` prints commands that recreate the settings
+    fn listing(&self, table: bool) -> CommandOutput {
+        let listing: String = self
+            .names()
+            .into_iter()
+            .map(|(name, enabled)| {
+                if table {
+                    format!("{:<15}\t{}\n", name, if enabled { "on" } else { "off" })
+                } else {
+                    format!("set {}o {}\n", if enabled { '-' } else { '+' }, name)
+                }
+            })
+            .collect();
+        CommandOutput::stdout(listing)
+    }
+}
@@ -2,10 +2,39 @@ use std::fs::OpenOptions;
 use std::io::{self, Write};
 use crate::redirection::{Redirection, RedirectionMode};
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct CommandOutput {
-    pub stdout: String,
+    // Bytes rather than text, as `printf '\xff'` writes a byte that isn't valid UTF-8
+    pub stdout: Vec<u8>,
     pub stderr: String,
+    pub status: i32,
+}
+
+impl CommandOutput {
+    pub fn stdout(stdout: impl Into<Vec<u8>>) -> Self {
+        Self {
+            stdout: stdout.into(),
+            stderr: String::new(),
+            status: 0,
+        }
+    }
+
+    pub fn stderr(stderr: impl Into<String>) -> Self {
+        Self {
+            stdout: Vec::new(),
+            stderr: stderr.into(),
+            status: 1,
+        }
+    }
+
+    pub fn with_status(mut self, status: i32) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn push_stdout(&mut self, text: &str) {
+        self.stdout.extend_from_slice(text.as_bytes());
+    }
 }
 
 struct OutputHandler<'a> {
@@ -41,12 +70,12 @@ impl<'a> OutputHandler<'a> {
 
     fn handle_stderr(&self) {
         match self.stderr_redir {
-            Some(redir) => self.redirect_to_file(&self.output.stderr, redir),
+            Some(redir) => self.redirect_to_file(self.output.stderr.as_bytes(), redir),
             None => self.write_to_stderr(&self.output.stderr),
         }
     }
 
-    fn redirect_to_file(&self, content: &str, redir: &Redirection) {
+    fn redirect_to_file(&self, content: &[u8], redir: &Redirection) {
         match self.open_file(redir) {
             Ok(mut file) => self.write_to_file(&mut file, content, &redir.filename),
             Err(e) => eprintln!("Error opening {}: {}", redir.filename, e),
@@ -62,15 +91,16 @@ impl<'a> OutputHandler<'a> {
             .open(&redir.filename)
     }
 
-    fn write_to_file(&self, file: &mut std::fs::File, content: &str, filename: &str) {
-        if let Err(e) = file.write_all(content.as_bytes()) {
+    fn write_to_file(&self, file: &mut std::fs::File, content: &[u8], filename: &str) {
+        if let Err(e) = file.write_all(content) {
             eprintln!("Error writing to {}: {}", filename, e);
         }
     }
 
-    fn write_to_stdout(&self, content: &str) {
-        print!("{}", content);
-        let _ = io::stdout().flush();
+    fn write_to_stdout(&self, content: &[u8]) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(content);
+        let _ = stdout.flush();
     }
 
     fn write_to_stderr(&self, content: &str) {
@@ -1,24 +1,64 @@
+pub trait WordExpander {
+    fn expand_tilde(&self, prefix: &str) -> Option<String>;
+    fn lookup_variable(&self, name: &str) -> Option<String>;
+}
+
+// A parsed word that remembers which characters came from quotes or escapes
+pub struct Word {
+    pub text: String,
+    pub quoted: Vec<bool>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Connector {
+    Always,
+    And,
+    Or,
+}
+
 #[derive(Default)]
-struct Parser {
-    args: Vec<String>,
+struct Parser<'a> {
+    input: Vec<char>,
+    position: usize,
+    words: Vec<Word>,
     current_arg: String,
+    current_quoted: Vec<bool>,
+    word_started: bool,
     in_single: bool,
     in_double: bool,
     escape_next: bool,
+    tilde_candidate: bool,
+    split_expansions: bool,
+    expander: Option<&'a dyn WordExpander>,
 }
 
-impl Parser {
-    fn new() -> Self {
-        Self::default()
+impl<'a> Parser<'a> {
+    fn new(input: &str, expander: &'a dyn WordExpander) -> Self {
+        Self {
+            input: input.chars().collect(),
+            split_expansions: true,
+            expander: Some(expander),
+            ..Self::default()
+        }
     }
 
-    fn parse(mut self, input: &str) -> Vec<String> {
-        for c in input.chars() {
+    fn parse(mut self) -> Vec<Word> {
+        while let Some(c) = self.next_char() {
             self.process_char(c);
         }
         self.finish_parsing()
     }
 
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.input.get(self.position).copied();
+        self.position += 1;
+        c
+    }
+
+    fn peek_char(&self) -> Option<char> {
+        self.input.get(self.position).copied()
+    }
+
     fn process_char(&mut self, c: char) {
         if self.escape_next {
             self.handle_escaped_char(c);
@@ -32,14 +72,14 @@ impl Parser {
     fn handle_escaped_char(&mut self, c: char) {
         if self.in_double {
             match c {
-                '\\' | '"' | '$' | '`' | '\n' => self.current_arg.push(c),
+                '\\' | '"' | '$' | '`' | '\n' => self.push_quoted(c),
                 _ => {
-                    self.current_arg.push('\\');
-                    self.current_arg.push(c);
+                    self.push_quoted('\\');
+                    self.push_quoted(c);
                 }
             }
         } else {
-            self.current_arg.push(c);
+            self.push_quoted(c);
         }
         self.escape_next = false;
     }
@@ -48,7 +88,7 @@ impl Parser {
         if self.in_double || !self.in_single {
             self.escape_next = true;
         } else {
-            self.current_arg.push('\\');
+            self.push_quoted('\\');
         }
     }
 
@@ -56,47 +96,248 @@ impl Parser {
         match c {
             '\'' => self.handle_single_quote(),
             '"' => self.handle_double_quote(),
-            ' ' | '\t' | '\n' => self.handle_whitespace(),
-            _ => self.current_arg.push(c),
+            '$' if !self.in_single => self.handle_dollar(),
+            ' ' | '\t' | '\n' => self.handle_whitespace(c),
+            _ if self.in_single || self.in_double => self.push_quoted(c),
+            _ => self.push_unquoted(c),
         }
     }
 
     fn handle_single_quote(&mut self) {
         if !self.in_double {
             self.in_single = !self.in_single;
+            self.word_started = true;
         } else {
-            self.current_arg.push('\'');
+            self.push_quoted('\'');
         }
     }
 
     fn handle_double_quote(&mut self) {
         if !self.in_single {
             self.in_double = !self.in_double;
+            self.word_started = true;
         } else {
-            self.current_arg.push('"');
+            self.push_quoted('"');
+        }
+    }
+
+    fn handle_dollar(&mut self) {
+        let name = match self.peek_char() {
+            Some('{') => match self.read_braced_name() {
+                Some(name) => name,
+                None => return self.push_quoted('$'),
+            },
+            Some(c @ ('$' | '?')) => {
+                self.position += 1;
+                c.to_string()
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => self.read_name(),
+            _ => {
+                if self.in_double {
+                    return self.push_quoted('$');
+                }
+                return self.push_unquoted('$');
+            }
+        };
+
+        let value = self
+            .expander
+            .and_then(|expander| expander.lookup_variable(&name))
+            .unwrap_or_default();
+        self.push_expansion(&value);
+    }
+
+    fn read_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek_char() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            name.push(c);
+            self.position += 1;
+        }
+        name
+    }
+
+    fn read_braced_name(&mut self) -> Option<String> {
+        let close = self.input[self.position..].iter().position(|&c| c == '}')?;
+        let name: String = self.input[self.position + 1..self.position + close]
+            .iter()
+            .collect();
+        self.position += close + 1;
+        Some(name)
+    }
+
+    // Unquoted expansions are split into separate words on whitespace
+    fn push_expansion(&mut self, value: &str) {
+        if self.in_double {
+            self.word_started = true;
+            value.chars().for_each(|c| self.push_quoted(c));
+            return;
+        }
+        // Without word splitting (inside `[[ ]]`) an empty expansion is still an empty word
+        if !self.split_expansions {
+            self.word_started = true;
+        }
+        for c in value.chars() {
+            if c.is_whitespace() && self.split_expansions {
+                self.handle_whitespace(c);
+            } else {
+                self.push_char(c, false);
+            }
         }
     }
 
-    fn handle_whitespace(&mut self) {
+    fn handle_whitespace(&mut self, c: char) {
         if !self.in_single && !self.in_double {
-            if !self.current_arg.is_empty() {
-                self.args.push(std::mem::take(&mut self.current_arg));
+            if !self.current_arg.is_empty() || self.word_started {
+                self.push_arg();
             }
         } else {
             // If we're inside quotes, preserve the whitespace
-            self.current_arg.push(' ');
+            self.push_quoted(c);
         }
     }
 
-    fn finish_parsing(mut self) -> Vec<String> {
-        if !self.current_arg.is_empty() {
-            self.args.push(self.current_arg);
+    fn push_unquoted(&mut self, c: char) {
+        if c == '~' && self.current_arg.is_empty() {
+            self.tilde_candidate = true;
         }
-        self.args
+        self.current_arg.push(c);
+        self.current_quoted.push(false);
     }
+
+    fn push_quoted(&mut self, c: char) {
+        self.push_char(c, true);
+    }
+
+    fn push_char(&mut self, c: char, quoted: bool) {
+        // Anything but a literal character inside the tilde prefix disables tilde expansion
+        if !self.current_arg.contains('/') {
+            self.tilde_candidate = false;
+        }
+        self.current_arg.push(c);
+        self.current_quoted.push(quoted);
+    }
+
+    fn push_arg(&mut self) {
+        let mut word = Word {
+            text: std::mem::take(&mut self.current_arg),
+            quoted: std::mem::take(&mut self.current_quoted),
+        };
+        if std::mem::take(&mut self.tilde_candidate) {
+            word = self.expand_tilde(word);
+        }
+        self.word_started = false;
+        self.words.push(word);
+    }
+
+    fn expand_tilde(&self, word: Word) -> Word {
+        let Some(expander) = self.expander else {
+            return word;
+        };
+        let prefix_end = word.text.find('/').unwrap_or(word.text.len());
+        match expander.expand_tilde(&word.text[1..prefix_end]) {
+            Some(expanded) => {
+                let prefix_chars = word.text[..prefix_end].chars().count();
+                let mut quoted = vec![true; expanded.chars().count()];
+                quoted.extend_from_slice(&word.quoted[prefix_chars..]);
+                Word {
+                    text: expanded + &word.text[prefix_end..],
+                    quoted,
+                }
+            }
+            None => word,
+        }
+    }
+
+    fn finish_parsing(mut self) -> Vec<Word> {
+        if !self.current_arg.is_empty() || self.word_started {
+            self.push_arg();
+        }
+        self.words
+    }
+}
+
+pub fn parse_arguments(input: &str, expander: &dyn WordExpander) -> Vec<String> {
+    let parser = Parser::new(input, expander);
+    parser.parse().into_iter().map(|word| word.text).collect()
+}
+
+// Parses the words of a `[[ ... ]]` command, where expansions are not word-split
+pub fn parse_conditional_words(input: &str, expander: &dyn WordExpander) -> Vec<Word> {
+    let mut parser = Parser::new(input, expander);
+    parser.split_expansions = false;
+    parser.parse()
 }
 
-pub fn parse_arguments(input: &str) -> Vec<String> {
-    let parser = Parser::new();
-    parser.parse(input)
-}
\ No newline at end of file
+// Splits a line on unquoted `;`, `&&` and `||`, leaving `[[ ... ]]` expressions intact
+pub fn split_command_list(input: &str) -> Vec<(Connector, String)> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut connector = Connector::Always;
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut conditional_depth = 0usize;
+    let mut i = 0;
+
+    let word_at = |i: usize, word: &str| {
+        let len = word.chars().count();
+        let at_start = i == 0 || chars[i - 1].is_whitespace();
+        let at_end = chars.get(i + len).map_or(true, |c| c.is_whitespace() || *c == ';');
+        at_start && at_end && chars[i..].iter().take(len).copied().eq(word.chars())
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        if in_single {
+            in_single = c != '\'';
+        } else if c == '\\' {
+            current.push(c);
+            i += 1;
+            if let Some(&escaped) = chars.get(i) {
+                current.push(escaped);
+            }
+            i += 1;
+            continue;
+        } else if in_double {
+            in_double = c != '"';
+        } else if c == '\'' {
+            in_single = true;
+        } else if c == '"' {
+            in_double = true;
+        } else if c == '#' && (i == 0 || chars[i - 1].is_whitespace()) {
+            // The rest of the line is a comment
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        } else if word_at(i, "[[") {
+            conditional_depth += 1;
+        } else if word_at(i, "]]") {
+            conditional_depth = conditional_depth.saturating_sub(1);
+        } else if conditional_depth == 0 {
+            let next = chars.get(i + 1).copied();
+            let split = match (c, next) {
+                (';', _) => Some((Connector::Always, 1)),
+                // A newline ends a command, but not one still waiting for its first word
+                ('\n', _) if !current.trim().is_empty() => Some((Connector::Always, 1)),
+                ('&', Some('&')) => Some((Connector::And, 2)),
+                ('|', Some('|')) => Some((Connector::Or, 2)),
+                _ => None,
+            };
+            if let Some((next_connector, len)) = split {
+                commands.push((connector, std::mem::take(&mut current)));
+                connector = next_connector;
+                i += len;
+                continue;
+            }
+        }
+        current.push(c);
+        i += 1;
+    }
+
+    commands.push((connector, current));
+    commands
+}
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use rustyline::completion::Pair;
+
+use crate::utils;
+
+pub enum WordPosition {
+    Command,
+    Argument(String),
+    Redirection,
+}
+
+// The word under the cursor: where it starts in the line, its text with quotes and escapes
+// removed, the quote still open at the cursor, and the command's earlier words
+pub struct WordContext {
+    pub start: usize,
+    pub text: String,
+    pub quote: Option<char>,
+    pub position: WordPosition,
+    pub words: Vec<String>,
+    pub ignore_case: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PathFilter {
+    All,
+    Directories,
+    Executables,
+}
+
+const SPECIAL_CHARACTERS: &str = " \t\n'\"\\$`&|;<>()*?[]#!{}";
+
+impl WordContext {
+    pub fn parse(line: &str) -> Self {
+        let mut start = 0;
+        let mut text = String::new();
+        let mut quote = None;
+        let mut word_started = false;
+        let mut words: Vec<String> = Vec::new();
+        let mut redirection = false;
+        let mut chars = line.char_indices().peekable();
+        let mut previous = None;
+
+        while let Some((i, c)) = chars.next() {
+            let next = chars.peek().map(|(_, c)| *c);
+            match quote {
+                Some('\'') if c == '\'' => quote = None,
+                Some('"') if c == '"' => quote = None,
+                Some('"') if c == '\\' && next.is_some_and(|c| "$`\"\\".contains(c)) => {
+                    text.extend(chars.next().map(|(_, c)| c));
+                }
+                Some(_) => text.push(c),
+                None if c.is_whitespace() || ";&|()<>".contains(c) => {
+                    let is_descriptor = text.chars().all(|c| c.is_ascii_digit());
+                    if (c == '<' || c == '>') && word_started && is_descriptor {
+                        // The `2` of `2>` is part of the operator
+                    } else if word_started {
+                        if redirection {
+                            redirection = false;
+                        } else if !words.is_empty() || !is_assignment(&text) {
+                            words.push(text.clone());
+                        }
+                    }
+                    // `&>` and `>&` are redirections rather than command separators
+                    let redirects = next == Some('>') || previous == Some('>');
+                    if ";|()".contains(c) || (c == '&' && !redirects) {
+                        words.clear();
+                        redirection = false;
+                    } else if c == '<' || c == '>' {
+                        redirection = true;
+                    }
+                    text.clear();
+                    word_started = false;
+                    start = i + c.len_utf8();
+                }
+                None => {
+                    word_started = true;
+                    match c {
+                        '\'' | '"' => quote = Some(c),
+                        '\\' => text.extend(chars.next().map(|(_, c)| c)),
+                        _ => text.push(c),
+                    }
+                }
+            }
+            previous = Some(c);
+        }
+
+        let position = if redirection {
+            WordPosition::Redirection
+        } else {
+            match words.first() {
+                Some(command) => WordPosition::Argument(command.clone()),
+                None => WordPosition::Command,
+            }
+        };
+        Self {
+            start,
+            text,
+            quote,
+            position,
+            words,
+            ignore_case: false,
+        }
+    }
+
+    // Completes the word as a path, keeping a leading `~user` or `$VAR` as typed
+    pub fn complete_path(
+        &self,
+        filter: PathFilter,
+        variables: &HashMap<String, String>,
+    ) -> Vec<Pair> {
+        let prefix_length = self.raw_prefix_length(variables);
+        self.matching_paths(filter, false, variables)
+            .into_iter()
+            .map(|(path, is_directory)| {
+                let name = path.rsplit('/').next().unwrap_or_default();
+                let display = if is_directory {
+                    format!("{}/", name)
+                } else {
+                    name.to_string()
+                };
+                self.pair(&path, display, prefix_length, is_directory, true)
+            })
+            .collect()
+    }
+
+    // Paths starting with the word, as typed, each with whether it is a directory. Dot files are
+    // left out unless `hidden` is set or the word names one.
+    pub fn matching_paths(
+        &self,
+        filter: PathFilter,
+        hidden: bool,
+        variables: &HashMap<String, String>,
+    ) -> Vec<(String, bool)> {
+        let (prefix, expanded) = match self.expansion_prefix(variables) {
+            Some((prefix, expanded)) => (prefix, expanded),
+            None => ("", String::new()),
+        };
+        let rest = &self.text[prefix.len()..];
+        let (directory, name_prefix) = match rest.rfind('/') {
+            Some(slash) => (&rest[..=slash], &rest[slash + 1..]),
+            None => ("", rest),
+        };
+        let searched = match format!("{}{}", expanded, directory) {
+            path if path.is_empty() => ".".to_string(),
+            path => path,
+        };
+        let Ok(entries) = fs::read_dir(&searched) else {
+            return Vec::new();
+        };
+
+        let mut candidates: Vec<(String, bool)> = entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                if !has_prefix(&name, name_prefix, self.ignore_case)
+                    || (name.starts_with('.') && !hidden && !name_prefix.starts_with('.'))
+                {
+                    return None;
+                }
+                // Follows symlinks, so a link to a directory completes like one
+                let metadata = fs::metadata(entry.path()).ok()?;
+                let keep = match filter {
+                    PathFilter::All => true,
+                    PathFilter::Directories => metadata.is_dir(),
+                    PathFilter::Executables => {
+                        metadata.is_dir() || metadata.permissions().mode() & 0o111 != 0
+                    }
+                };
+                keep.then(|| (format!("{}{}{}", prefix, directory, name), metadata.is_dir()))
+            })
+            .collect();
+        candidates.sort();
+        candidates
+    }
+
+    // Replaces the word with `text`, quoted the way the word was; the first `raw_length` bytes
+    // are a `~` or `$VAR` prefix inserted unquoted. Directories leave the quote open.
+    pub fn pair(
+        &self,
+        text: &str,
+        display: String,
+        raw_length: usize,
+        is_directory: bool,
+        add_space: bool,
+    ) -> Pair {
+        let mut replacement = self.quote.map(String::from).unwrap_or_default();
+        replacement.push_str(&text[..raw_length]);
+        replacement.push_str(&self.quote_text(&text[raw_length..]));
+        if is_directory {
+            replacement.push('/');
+        } else if add_space {
+            replacement.extend(self.quote);
+            replacement.push(' ');
+        }
+        Pair {
+            display,
+            replacement,
+        }
+    }
+
+    // The length of the `~` or `$VAR` prefix that completions keep unquoted
+    pub fn raw_prefix_length(&self, variables: &HashMap<String, String>) -> usize {
+        self.expansion_prefix(variables)
+            .map_or(0, |(prefix, _)| prefix.len())
+    }
+
+    // `~`, `~user`, `$VAR` or `${VAR}` up to the first `/`, with what it expands to
+    fn expansion_prefix(&self, variables: &HashMap<String, String>) -> Option<(&str, String)> {
+        let end = self.text.find('/')?;
+        let prefix = &self.text[..end];
+        if let Some(user) = prefix.strip_prefix('~').filter(|_| self.quote.is_none()) {
+            let home = match user {
+                "" => utils::home_directory(),
+                user => utils::user_home_directory(user),
+            }?;
+            return Some((prefix, home));
+        }
+        let name = prefix
+            .strip_prefix('$')
+            .filter(|_| self.quote != Some('\''))?;
+        let name = name
+            .strip_prefix('{')
+            .and_then(|name| name.strip_suffix('}'))
+            .unwrap_or(name);
+        Some((prefix, variables.get(name)?.clone()))
+    }
+
+    fn quote_text(&self, text: &str) -> String {
+        match self.quote {
+            Some('\'') => text.replace('\'', "'\\''"),
+            Some(_) => text.chars().fold(String::new(), |mut quoted, c| {
+                if "$`\"\\".contains(c) {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+                quoted
+            }),
+            None => text
+                .chars()
+                .enumerate()
+                .fold(String::new(), |mut quoted, (i, c)| {
+                    if SPECIAL_CHARACTERS.contains(c) || (i == 0 && c == '~') {
+                        quoted.push('\\');
+                    }
+                    quoted.push(c);
+                    quoted
+                }),
+        }
+    }
+}
+
+pub fn has_prefix(text: &str, prefix: &str, ignore_case: bool) -> bool {
+    match ignore_case {
+        true => text.to_lowercase().starts_with(&prefix.to_lowercase()),
+        false => text.starts_with(prefix),
+    }
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argument_of(context: &WordContext) -> Option<&str> {
+        match &context.position {
+            WordPosition::Argument(command) => Some(command),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_the_word_under_the_cursor() {
+        let context = WordContext::parse("ls -l src/ma");
+        assert_eq!(context.start, 6);
+        assert_eq!(context.text, "src/ma");
+        assert_eq!(context.quote, None);
+        assert_eq!(context.words, ["ls", "-l"]);
+        assert_eq!(argument_of(&context), Some("ls"));
+
+        let context = WordContext::parse("gi");
+        assert!(matches!(context.position, WordPosition::Command));
+        assert_eq!(context.text, "gi");
+    }
+
+    #[test]
+    fn removes_quotes_and_escapes() {
+        let context = WordContext::parse("cat 'my fi");
+        assert_eq!(context.start, 4);
+        assert_eq!(context.text, "my fi");
+        assert_eq!(context.quote, Some('\''));
+
+        let context = WordContext::parse("cat \"a\\\"b");
+        assert_eq!(context.text, "a\"b");
+        assert_eq!(context.quote, Some('"'));
+
+        let context = WordContext::parse("cat my\\ fi");
+        assert_eq!(context.text, "my fi");
+        assert_eq!(context.quote, None);
+    }
+
+    #[test]
+    fn finds_the_command_after_assignments_and_separators() {
+        let context = WordContext::parse("FOO=1 BAR=2 make t");
+        assert_eq!(argument_of(&context), Some("make"));
+
+        let context = WordContext::parse("cd /tmp && gi");
+        assert!(matches!(context.position, WordPosition::Command));
+        assert!(context.words.is_empty());
+
+        let context = WordContext::parse("ls | grep x");
+        assert_eq!(argument_of(&context), Some("grep"));
+    }
+
+    #[test]
+    fn recognizes_redirections() {
+        let context = WordContext::parse("echo hi > ou");
+        assert!(matches!(context.position, WordPosition::Redirection));
+        assert_eq!(context.text, "ou");
+
+        let context = WordContext::parse("make 2>er");
+        assert!(matches!(context.position, WordPosition::Redirection));
+        assert_eq!(context.words, ["make"]);
+
+        let context = WordContext::parse("make &> lo");
+        assert!(matches!(context.position, WordPosition::Redirection));
+        assert_eq!(context.words, ["make"]);
+
+        let context = WordContext::parse("cat < in ou");
+        assert_eq!(argument_of(&context), Some("cat"));
+        assert_eq!(context.words, ["cat"]);
+    }
+
+    #[test]
+    fn quotes_completions_for_the_open_quote() {
+        let unquoted = WordContext::parse("cat ");
+        assert_eq!(unquoted.quote_text("my file(1)"), "my\\ file\\(1\\)");
+        assert_eq!(unquoted.quote_text("~x~"), "\\~x~");
+
+        let single = WordContext::parse("cat '");
+        assert_eq!(single.quote_text("it's $x"), "it'\\''s $x");
+
+        let double = WordContext::parse("cat \"");
+        assert_eq!(double.quote_text("a\"$b`\\ c"), "a\\\"\\$b\\`\\\\ c");
+    }
+}
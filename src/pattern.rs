@@ -0,0 +1,149 @@
+// Shell glob matching: `*`, `?`, bracket expressions and backslash escapes
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let mut pi = 0;
+    let mut ti = 0;
+    let mut backtrack = None;
+
+    while ti < text.len() {
+        if pattern.get(pi) == Some(&'*') {
+            backtrack = Some((pi, ti));
+            pi += 1;
+            continue;
+        }
+        if let Some(next) = (pi < pattern.len())
+            .then(|| match_element(&pattern, pi, text[ti]))
+            .flatten()
+        {
+            pi = next;
+            ti += 1;
+            continue;
+        }
+        match backtrack {
+            Some((star, consumed)) => {
+                pi = star + 1;
+                ti = consumed + 1;
+                backtrack = Some((star, consumed + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[pi..].iter().all(|&c| c == '*')
+}
+
+// Returns the pattern index after the element if it matches `c`
+fn match_element(pattern: &[char], pi: usize, c: char) -> Option<usize> {
+    match pattern[pi] {
+        '?' => Some(pi + 1),
+        '[' => match match_bracket(pattern, pi, c) {
+            Some((matched, end)) => matched.then_some(end),
+            None => (c == '[').then_some(pi + 1),
+        },
+        '\\' if pi + 1 < pattern.len() => (pattern[pi + 1] == c).then_some(pi + 2),
+        literal => (literal == c).then_some(pi + 1),
+    }
+}
+
+// Returns whether `c` is in the bracket expression and the index past its `]`
+fn match_bracket(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let current = *pattern.get(i)?;
+        if current == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+
+        if current == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                matched |= matches_class(&rest[..end], c);
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let low = if current == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            pattern[i]
+        } else {
+            current
+        };
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&high| high != ']') {
+            let high = pattern[i + 2];
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= low == c;
+            i += 1;
+        }
+    }
+}
+
+fn matches_class(class: &str, c: char) -> bool {
+    match class {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => c.is_ascii_graphic(),
+        "lower" => c.is_lowercase(),
+        "print" => !c.is_control(),
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(matches("?at", "cat"));
+        assert!(!matches("?at", "at"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(matches("*", ""));
+        assert!(!matches("", "a"));
+    }
+
+    #[test]
+    fn matches_bracket_expressions() {
+        assert!(matches("[abc]x", "bx"));
+        assert!(!matches("[!abc]x", "bx"));
+        assert!(matches("[^abc]x", "dx"));
+        assert!(matches("[a-c]", "b"));
+        assert!(!matches("[a-c]", "d"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[[:digit:]]*", "1abc"));
+        assert!(!matches("[[:upper:]]", "a"));
+        // An unclosed bracket is an ordinary character
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn matches_escaped_characters_literally() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("a\\?", "a?"));
+        assert!(matches("[\\]]", "]"));
+    }
+}
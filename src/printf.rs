@@ -0,0 +1,602 @@
+use crate::output::CommandOutput;
+use crate::utils::{self, Quoting};
+use crate::variables::{self, Variables};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EscapeStyle {
+    // `echo -e` and `%b`: octal escapes are written `\0nnn`
+    Echo,
+    // printf format strings: octal escapes are written `\nnn`
+    Format,
+}
+
+enum Escape {
+    Bytes(Vec<u8>),
+    Stop,
+}
+
+// Returns the expanded bytes and whether a `\c` cut the output short; octal and `\x` escapes
+// give single bytes, which need not make valid UTF-8
+pub fn interpret_escapes(input: &str, style: EscapeStyle) -> (Vec<u8>, bool) {
+    let chars: Vec<char> = input.chars().collect();
+    let mut output = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] != '\\' || i + 1 == chars.len() {
+            push_char(&mut output, chars[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+        match read_escape(&chars, &mut i, style) {
+            Escape::Bytes(bytes) => output.extend(bytes),
+            Escape::Stop => return (output, true),
+        }
+    }
+
+    (output, false)
+}
+
+fn push_char(output: &mut Vec<u8>, c: char) {
+    output.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+}
+
+// `i` points just past the backslash and is advanced past the sequence
+fn read_escape(chars: &[char], i: &mut usize, style: EscapeStyle) -> Escape {
+    let c = chars[*i];
+    *i += 1;
+
+    let bytes = match c {
+        'a' => vec![0x07],
+        'b' => vec![0x08],
+        'e' | 'E' => vec![0x1b],
+        'f' => vec![0x0c],
+        'n' => vec![b'\n'],
+        'r' => vec![b'\r'],
+        't' => vec![b'\t'],
+        'v' => vec![0x0b],
+        '\\' => vec![b'\\'],
+        'c' => return Escape::Stop,
+        // In both styles, as in bash, a leading 0 doesn't count towards the three digits
+        '0' => {
+            let value = read_digits(chars, i, 8, 3).unwrap_or(0);
+            vec![(value & 0xff) as u8]
+        }
+        '1'..='7' if style == EscapeStyle::Format => {
+            *i -= 1;
+            let value = read_digits(chars, i, 8, 3).unwrap_or(0);
+            vec![(value & 0xff) as u8]
+        }
+        'x' => match read_digits(chars, i, 16, 2) {
+            Some(value) => vec![value as u8],
+            None => b"\\x".to_vec(),
+        },
+        'u' | 'U' => {
+            let max_digits = if c == 'u' { 4 } else { 8 };
+            match read_digits(chars, i, 16, max_digits).and_then(char::from_u32) {
+                Some(decoded) => decoded.to_string().into_bytes(),
+                None => format!("\\{}", c).into_bytes(),
+            }
+        }
+        '"' | '\'' | '?' if style == EscapeStyle::Format => vec![c as u8],
+        _ => format!("\\{}", c).into_bytes(),
+    };
+
+    Escape::Bytes(bytes)
+}
+
+fn read_digits(chars: &[char], i: &mut usize, radix: u32, max_digits: usize) -> Option<u32> {
+    let mut value = None;
+    for _ in 0..max_digits {
+        let Some(digit) = chars.get(*i).and_then(|c| c.to_digit(radix)) else {
+            break;
+        };
+        value = Some(value.unwrap_or(0u32).saturating_mul(radix).saturating_add(digit));
+        *i += 1;
+    }
+    value
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: Option<usize>,
+    precision: Option<usize>,
+}
+
+struct Formatter<'a> {
+    args: &'a [String],
+    next_arg: usize,
+    output: Vec<u8>,
+    errors: String,
+    stop: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn new(args: &'a [String]) -> Self {
+        Self {
+            args,
+            next_arg: 0,
+            output: Vec::new(),
+            errors: String::new(),
+            stop: false,
+        }
+    }
+
+    // The format is reused for as long as it keeps consuming arguments
+    fn run(&mut self, format: &str) {
+        let format: Vec<char> = format.chars().collect();
+        loop {
+            let consumed = self.next_arg;
+            self.format_once(&format);
+            if self.stop || self.next_arg == consumed || self.next_arg >= self.args.len() {
+                break;
+            }
+        }
+    }
+
+    fn format_once(&mut self, format: &[char]) {
+        let mut i = 0;
+        while i < format.len() && !self.stop {
+            match format[i] {
+                '\\' if i + 1 < format.len() => {
+                    i += 1;
+                    match read_escape(format, &mut i, EscapeStyle::Format) {
+                        Escape::Bytes(bytes) => self.output.extend(bytes),
+                        Escape::Stop => self.stop = true,
+                    }
+                }
+                '%' => {
+                    i += 1;
+                    self.format_conversion(format, &mut i);
+                }
+                c => {
+                    push_char(&mut self.output, c);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    fn format_conversion(&mut self, format: &[char], i: &mut usize) {
+        if format.get(*i) == Some(&'%') {
+            self.output.push(b'%');
+            *i += 1;
+            return;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&flag) = format.get(*i) {
+            match flag {
+                '-' => spec.left = true,
+                '+' => spec.plus = true,
+                ' ' => spec.space = true,
+                '#' => spec.alternate = true,
+                '0' => spec.zero = true,
+                '\'' => {}
+                _ => break,
+            }
+            *i += 1;
+        }
+
+        if format.get(*i) == Some(&'*') {
+            *i += 1;
+            let width = self.next_integer();
+            spec.left |= width < 0;
+            spec.width = Some(width.unsigned_abs() as usize);
+        } else {
+            spec.width = read_digits(format, i, 10, usize::MAX).map(|w| w as usize);
+        }
+
+        if format.get(*i) == Some(&'.') {
+            *i += 1;
+            if format.get(*i) == Some(&'*') {
+                *i += 1;
+                spec.precision = usize::try_from(self.next_integer()).ok();
+            } else {
+                spec.precision = Some(read_digits(format, i, 10, usize::MAX).unwrap_or(0) as usize);
+            }
+        }
+
+        while matches!(format.get(*i), Some('h' | 'l' | 'L' | 'j' | 'z' | 't')) {
+            *i += 1;
+        }
+
+        let Some(&conversion) = format.get(*i) else {
+            self.errors.push_str("printf: `%': missing format character\n");
+            self.stop = true;
+            return;
+        };
+        *i += 1;
+
+        let formatted = match conversion {
+            's' => {
+                let arg = self.next_string();
+                let text = truncate(&arg, spec.precision);
+                Some(pad(text.into_bytes(), &spec, false))
+            }
+            'b' => {
+                let (mut bytes, stopped) =
+                    interpret_escapes(&self.next_string(), EscapeStyle::Echo);
+                self.stop = stopped;
+                // As in bash, the precision of `%b` counts bytes
+                if let Some(precision) = spec.precision {
+                    bytes.truncate(precision);
+                }
+                Some(pad(bytes, &spec, false))
+            }
+            'q' => {
+                let arg = self.next_string();
+                let quoted = utils::shell_quote(&arg, Quoting::Backslash);
+                Some(pad(quoted.into_bytes(), &spec, false))
+            }
+            'c' => {
+                let arg = self.next_string();
+                let first: String = arg.chars().take(1).collect();
+                Some(pad(first.into_bytes(), &spec, false))
+            }
+            'd' | 'i' | 'u' | 'x' | 'X' | 'o' => {
+                let value = self.next_integer();
+                Some(format_integer(value, conversion, &spec))
+            }
+            'f' | 'F' | 'e' | 'E' | 'g' | 'G' => {
+                let value = self.next_float();
+                Some(format_float(value, conversion, &spec))
+            }
+            _ => None,
+        };
+
+        match formatted {
+            Some(bytes) => self.output.extend(bytes),
+            None => {
+                self.errors
+                    .push_str(&format!("printf: `{}': invalid format character\n", conversion));
+                self.stop = true;
+            }
+        }
+    }
+
+    fn next_string(&mut self) -> String {
+        let arg = self.args.get(self.next_arg).cloned().unwrap_or_default();
+        self.next_arg += 1;
+        arg
+    }
+
+    fn next_integer(&mut self) -> i64 {
+        let arg = self.next_string();
+        parse_integer(&arg).unwrap_or_else(|| {
+            self.errors.push_str(&format!("printf: {}: invalid number\n", arg));
+            0
+        })
+    }
+
+    fn next_float(&mut self) -> f64 {
+        let arg = self.next_string();
+        parse_float(&arg).unwrap_or_else(|| {
+            self.errors.push_str(&format!("printf: {}: invalid number\n", arg));
+            0.0
+        })
+    }
+}
+
+fn parse_integer(arg: &str) -> Option<i64> {
+    let arg = arg.trim_start();
+    if arg.is_empty() {
+        return Some(0);
+    }
+    if let Some(quoted) = arg.strip_prefix(['\'', '"']) {
+        return Some(quoted.chars().next().map_or(0, |c| c as i64));
+    }
+
+    let (negative, digits) = match arg.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, arg.strip_prefix('+').unwrap_or(arg)),
+    };
+    let magnitude = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()?
+    } else {
+        digits.parse::<u64>().ok()?
+    };
+
+    let value = magnitude as i64;
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+fn parse_float(arg: &str) -> Option<f64> {
+    let trimmed = arg.trim_start();
+    if trimmed.is_empty() {
+        return Some(0.0);
+    }
+    if let Some(quoted) = trimmed.strip_prefix(['\'', '"']) {
+        return Some(quoted.chars().next().map_or(0.0, |c| c as u32 as f64));
+    }
+    trimmed
+        .parse::<f64>()
+        .ok()
+        .or_else(|| parse_integer(trimmed).map(|value| value as f64))
+}
+
+fn format_integer(value: i64, conversion: char, spec: &Spec) -> Vec<u8> {
+    let (sign, mut digits) = match conversion {
+        'd' | 'i' => {
+            let sign = if value < 0 {
+                "-"
+            } else if spec.plus {
+                "+"
+            } else if spec.space {
+                " "
+            } else {
+                ""
+            };
+            (sign, value.unsigned_abs().to_string())
+        }
+        'x' => ("", format!("{:x}", value as u64)),
+        'X' => ("", format!("{:X}", value as u64)),
+        'o' => ("", format!("{:o}", value as u64)),
+        _ => ("", (value as u64).to_string()),
+    };
+
+    if let Some(precision) = spec.precision {
+        if precision == 0 && value == 0 {
+            digits.clear();
+        } else if digits.len() < precision {
+            digits = "0".repeat(precision - digits.len()) + &digits;
+        }
+    }
+
+    let prefix = match conversion {
+        'x' if spec.alternate && value != 0 => "0x",
+        'X' if spec.alternate && value != 0 => "0X",
+        'o' if spec.alternate && !digits.starts_with('0') => "0",
+        _ => "",
+    };
+
+    let zero_fill = spec.zero && !spec.left && spec.precision.is_none();
+    pad_number(&format!("{}{}", sign, prefix), &digits, spec, zero_fill)
+}
+
+fn format_float(value: f64, conversion: char, spec: &Spec) -> Vec<u8> {
+    let precision = spec.precision.unwrap_or(6);
+    let magnitude = value.abs();
+
+    let body = if value.is_nan() {
+        "nan".to_string()
+    } else if value.is_infinite() {
+        "inf".to_string()
+    } else {
+        match conversion {
+            'e' | 'E' => exponent_notation(magnitude, precision),
+            'g' | 'G' => general_notation(magnitude, precision, spec.alternate),
+            _ if spec.alternate && precision == 0 => format!("{:.0}.", magnitude),
+            _ => format!("{:.*}", precision, magnitude),
+        }
+    };
+    let body = if conversion.is_ascii_uppercase() {
+        body.to_uppercase()
+    } else {
+        body
+    };
+
+    let sign = if value.is_sign_negative() && !value.is_nan() {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    };
+
+    let zero_fill = spec.zero && !spec.left && value.is_finite();
+    pad_number(sign, &body, spec, zero_fill)
+}
+
+fn exponent_notation(value: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    format!(
+        "{}e{}{:02}",
+        mantissa,
+        if exponent < 0 { '-' } else { '+' },
+        exponent.abs()
+    )
+}
+
+fn general_notation(value: f64, precision: usize, alternate: bool) -> String {
+    let precision = precision.max(1);
+    let exponent = if value == 0.0 {
+        0
+    } else {
+        let formatted = format!("{:.*e}", precision - 1, value);
+        formatted
+            .split_once('e')
+            .and_then(|(_, exponent)| exponent.parse::<i32>().ok())
+            .unwrap_or(0)
+    };
+
+    let formatted = if exponent < -4 || exponent >= precision as i32 {
+        exponent_notation(value, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+    };
+
+    if alternate {
+        return formatted;
+    }
+    match formatted.split_once('e') {
+        Some((mantissa, exponent)) => format!("{}e{}", strip_fraction_zeros(mantissa), exponent),
+        None => strip_fraction_zeros(&formatted).to_string(),
+    }
+}
+
+fn strip_fraction_zeros(number: &str) -> &str {
+    if !number.contains('.') {
+        return number;
+    }
+    number.trim_end_matches('0').trim_end_matches('.')
+}
+
+fn truncate(text: &str, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text.to_string(),
+    }
+}
+
+// The width counts characters, or bytes when the text isn't valid UTF-8
+fn pad(mut text: Vec<u8>, spec: &Spec, zero_fill: bool) -> Vec<u8> {
+    let width = spec.width.unwrap_or(0);
+    let len = std::str::from_utf8(&text).map_or(text.len(), |text| text.chars().count());
+    if len >= width {
+        return text;
+    }
+
+    let fill = width - len;
+    if spec.left {
+        text.resize(text.len() + fill, b' ');
+        text
+    } else {
+        let mut padded = vec![if zero_fill { b'0' } else { b' ' }; fill];
+        padded.extend(text);
+        padded
+    }
+}
+
+fn pad_number(prefix: &str, digits: &str, spec: &Spec, zero_fill: bool) -> Vec<u8> {
+    if !zero_fill {
+        return pad(format!("{}{}", prefix, digits).into_bytes(), spec, false);
+    }
+    let width = spec.width.unwrap_or(0).saturating_sub(prefix.chars().count());
+    let inner = Spec {
+        width: Some(width),
+        ..Spec::default()
+    };
+    let mut padded = prefix.as_bytes().to_vec();
+    padded.extend(pad(digits.as_bytes().to_vec(), &inner, true));
+    padded
+}
+
+pub fn printf(args: &[String], variables: &mut Variables) -> CommandOutput {
+    const USAGE: &str = "printf: usage: printf [-v var] format [arguments]\n";
+
+    let mut args = args;
+    let mut target = None;
+    while let Some(first) = args.first() {
+        match first.as_str() {
+            "-v" => {
+                let Some(name) = args.get(1) else {
+                    return CommandOutput::stderr(format!(
+                        "printf: -v: option requires an argument\n{}",
+                        USAGE
+                    ));
+                };
+                if !variables::is_valid_name(name) {
+                    return CommandOutput::stderr(format!(
+                        "printf: `{}': not a valid identifier\n",
+                        name
+                    ));
+                }
+                target = Some(name.clone());
+                args = &args[2..];
+            }
+            "--" => {
+                args = &args[1..];
+                break;
+            }
+            _ => break,
+        }
+    }
+
+    let Some((format, arguments)) = args.split_first() else {
+        return CommandOutput::stderr(USAGE);
+    };
+
+    let mut formatter = Formatter::new(arguments);
+    formatter.run(format);
+
+    let status = i32::from(!formatter.errors.is_empty());
+    match target {
+        Some(name) => {
+            let value = String::from_utf8_lossy(&formatter.output);
+            variables.set(&name, value.into_owned());
+            CommandOutput::stderr(formatter.errors).with_status(status)
+        }
+        None => CommandOutput {
+            stdout: formatter.output,
+            stderr: formatter.errors,
+            status,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(format: &str, args: &[&str]) -> (Vec<u8>, String) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut formatter = Formatter::new(&args);
+        formatter.run(format);
+        (formatter.output, formatter.errors)
+    }
+
+    #[test]
+    fn escapes_give_raw_bytes() {
+        let echo = |input| interpret_escapes(input, EscapeStyle::Echo);
+        assert_eq!(echo(r"\xff\0377\x41"), (vec![0xff, 0xff, b'A'], false));
+        assert_eq!(echo(r"a\tb\\"), (b"a\tb\\".to_vec(), false));
+        assert_eq!(echo(r"é\x"), ("é\\x".as_bytes().to_vec(), false));
+        assert_eq!(echo(r"\101"), (br"\101".to_vec(), false));
+        assert_eq!(echo(r"one\ctwo"), (b"one".to_vec(), true));
+
+        let format = |input| interpret_escapes(input, EscapeStyle::Format);
+        assert_eq!(format(r"\101\0101\377\'"), (b"AA\xff'".to_vec(), false));
+    }
+
+    #[test]
+    fn formats_conversions() {
+        assert_eq!(format("%s-%s\n", &["a", "b"]).0, b"a-b\n");
+        assert_eq!(
+            format("[%5s|%-5s|%.2s]", &["ab", "cd", "xyz"]).0,
+            b"[   ab|cd   |xy]"
+        );
+        assert_eq!(
+            format("%05d %+d %x %#o %c", &["42", "7", "255", "8", "hi"]).0,
+            b"00042 +7 ff 010 h"
+        );
+        assert_eq!(format("%*d|%-*d|", &["4", "1", "3", "2"]).0, b"   1|2  |");
+        assert_eq!(
+            format("%.3f %e %g", &["3.14159", "1500", "0.0001"]).0,
+            b"3.142 1.500000e+03 0.0001"
+        );
+        assert_eq!(format("%d", &["'A"]).0, b"65");
+        assert_eq!(
+            format("%b|%.1b", &[r"\xff\n", r"\0303\0251"]).0,
+            b"\xff\n|\xc3"
+        );
+        assert_eq!(format("%q", &["a b'c"]).0, br"a\ b\'c");
+        assert_eq!(format("100%%\n", &[]).0, b"100%\n");
+    }
+
+    #[test]
+    fn reuses_format_and_reports_errors() {
+        assert_eq!(format("<%s>", &["a", "b", "c"]).0, b"<a><b><c>");
+        assert_eq!(format("%s %s;", &["a", "b", "c"]).0, b"a b;c ;");
+        assert_eq!(format("a\\cb%s", &["x", "y"]).0, b"a");
+        assert_eq!(
+            format("%d", &["x"]),
+            (b"0".to_vec(), "printf: x: invalid number\n".to_string())
+        );
+        let (output, errors) = format("a%k", &[]);
+        assert_eq!(output, b"a");
+        assert_eq!(errors, "printf: `k': invalid format character\n");
+    }
+}
@@ -0,0 +1,434 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::ffi::CStr;
+use std::fs;
+
+use crate::builtins;
+use crate::command_index::CommandIndex;
+use crate::output::CommandOutput;
+use crate::path_completion::{PathFilter, WordContext, WordPosition};
+use crate::utils::{self, Quoting};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    Alias,
+    Builtin,
+    Command,
+    Directory,
+    Export,
+    File,
+    Hostname,
+    Keyword,
+    User,
+    Variable,
+}
+
+// `-A` names with their single-letter shorthands
+const ACTIONS: &[(&str, char, Action)] = &[
+    ("alias", 'a', Action::Alias),
+    ("builtin", 'b', Action::Builtin),
+    ("command", 'c', Action::Command),
+    ("directory", 'd', Action::Directory),
+    ("export", 'e', Action::Export),
+    ("file", 'f', Action::File),
+    ("hostname", '\0', Action::Hostname),
+    ("keyword", 'k', Action::Keyword),
+    ("user", 'u', Action::User),
+    ("variable", 'v', Action::Variable),
+];
+
+const OPTIONS: &[&str] = &["default", "dirnames", "filenames", "nospace"];
+
+#[derive(Clone, Default)]
+pub struct CompletionSpec {
+    actions: Vec<Action>,
+    words: Option<String>,
+    command: Option<String>,
+    options: Vec<String>,
+}
+
+// Per-command specs registered with `complete`
+#[derive(Default)]
+pub struct CompletionSpecs {
+    specs: BTreeMap<String, CompletionSpec>,
+}
+
+// What candidates are drawn from, as of the current prompt
+pub struct CompletionSources<'a> {
+    pub variables: &'a HashMap<String, String>,
+    pub aliases: Vec<String>,
+    pub commands: &'a RefCell<CommandIndex>,
+}
+
+// A generated candidate and whether it names a directory
+pub type Candidate = (String, bool);
+
+struct ParsedArgs {
+    spec: CompletionSpec,
+    print: bool,
+    remove: bool,
+    operands: Vec<String>,
+}
+
+impl CompletionSpec {
+    pub fn has_option(&self, option: &str) -> bool {
+        self.options.iter().any(|set| set == option)
+    }
+
+    // Candidates get filename treatment: quoting, and a `/` rather than a space after directories
+    pub fn completes_filenames(&self) -> bool {
+        self.has_option("filenames")
+            || self.actions.contains(&Action::File)
+            || self.actions.contains(&Action::Directory)
+    }
+
+    // File and directory actions include dot files, as bash's do. `-C` runs in a child shell with
+    // `COMP_LINE` and `COMP_POINT` set, as bash does, and gets the command name, the word being
+    // completed and the word before it as arguments
+    pub fn generate(
+        &self,
+        word: &WordContext,
+        line: &str,
+        sources: &CompletionSources,
+    ) -> Vec<Candidate> {
+        let prefix = word.text.as_str();
+        let mut candidates: Vec<Candidate> = Vec::new();
+
+        for action in &self.actions {
+            match action {
+                Action::File => {
+                    candidates.extend(word.matching_paths(PathFilter::All, true, sources.variables))
+                }
+                Action::Directory => candidates.extend(word.matching_paths(
+                    PathFilter::Directories,
+                    true,
+                    sources.variables,
+                )),
+                action => candidates.extend(
+                    action_names(*action, prefix, sources)
+                        .into_iter()
+                        .filter(|name| name.starts_with(prefix))
+                        .map(|name| (name, false)),
+                ),
+            }
+        }
+        if let Some(words) = &self.words {
+            candidates.extend(
+                words
+                    .split_whitespace()
+                    .filter(|candidate| candidate.starts_with(prefix))
+                    .map(|candidate| (candidate.to_string(), false)),
+            );
+        }
+
+        let command_name = word.words.first().cloned().unwrap_or_default();
+        let previous = word.words.last().cloned().unwrap_or_default();
+        let arguments = [&command_name, prefix, &previous]
+            .iter()
+            .map(|argument| utils::shell_quote(argument, Quoting::Needed))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let environment = [
+            ("COMP_LINE", line.to_string()),
+            ("COMP_POINT", line.len().to_string()),
+        ];
+        if let Some(command) = &self.command {
+            let output =
+                utils::run_in_child_shell(&format!("{} {}", command, arguments), &environment);
+            candidates.extend(
+                output
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(|line| (line.to_string(), false)),
+            );
+        }
+
+        if candidates.is_empty() && self.has_option("dirnames") {
+            candidates = word.matching_paths(PathFilter::Directories, true, sources.variables);
+        }
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
+    // The options that recreate this spec, as `complete -p` prints them
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        for option in &self.options {
+            parts.push(format!("-o {}", option));
+        }
+        for action in &self.actions {
+            match ACTIONS.iter().find(|(_, _, known)| known == action) {
+                Some((_, short, _)) if *short != '\0' => parts.push(format!("-{}", short)),
+                Some((name, _, _)) => parts.push(format!("-A {}", name)),
+                None => {}
+            }
+        }
+        if let Some(words) = &self.words {
+            parts.push(format!("-W {}", utils::shell_quote(words, Quoting::Needed)));
+        }
+        if let Some(command) = &self.command {
+            parts.push(format!("-C {}", utils::shell_quote(command, Quoting::Needed)));
+        }
+        parts.join(" ")
+    }
+}
+
+impl CompletionSpecs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Looks the command up by its full name, then by its basename
+    pub fn get(&self, command: &str) -> Option<&CompletionSpec> {
+        self.specs.get(command).or_else(|| {
+            let basename = command.rsplit('/').next()?;
+            self.specs.get(basename)
+        })
+    }
+
+    pub fn complete(&mut self, args: &[String]) -> CommandOutput {
+        let parsed = match parse_args("complete", args) {
+            Ok(parsed) => parsed,
+            Err(output) => return output,
+        };
+
+        if parsed.remove {
+            if parsed.operands.is_empty() {
+                self.specs.clear();
+                return CommandOutput::default();
+            }
+            let mut output = CommandOutput::default();
+            for name in &parsed.operands {
+                if self.specs.remove(name).is_none() {
+                    output.stderr += &format!("complete: {}: no completion specification\n", name);
+                    output.status = 1;
+                }
+            }
+            return output;
+        }
+
+        if parsed.print || parsed.operands.is_empty() {
+            let mut output = CommandOutput::default();
+            let names: Vec<&String> = if parsed.operands.is_empty() {
+                self.specs.keys().collect()
+            } else {
+                parsed.operands.iter().collect()
+            };
+            for name in names {
+                match self.specs.get(name) {
+                    Some(spec) => {
+                        output.push_stdout(&format!("complete {} {}\n", spec.describe(), name))
+                    }
+                    None => {
+                        output.stderr +=
+                            &format!("complete: {}: no completion specification\n", name);
+                        output.status = 1;
+                    }
+                }
+            }
+            return output;
+        }
+
+        for name in parsed.operands {
+            self.specs.insert(name, parsed.spec.clone());
+        }
+        CommandOutput::default()
+    }
+}
+
+// `compgen [options] [word]` prints the candidates a spec would offer for `word`
+pub fn compgen(args: &[String], sources: &CompletionSources) -> CommandOutput {
+    let parsed = match parse_args("compgen", args) {
+        Ok(parsed) => parsed,
+        Err(output) => return output,
+    };
+    let word = WordContext {
+        start: 0,
+        text: parsed.operands.first().cloned().unwrap_or_default(),
+        quote: None,
+        position: WordPosition::Command,
+        words: Vec::new(),
+        ignore_case: false,
+    };
+
+    let candidates = parsed.spec.generate(&word, "", sources);
+    if candidates.is_empty() {
+        return CommandOutput::default().with_status(1);
+    }
+    let listing: String = candidates
+        .into_iter()
+        .map(|(candidate, _)| format!("{}\n", candidate))
+        .collect();
+    CommandOutput::stdout(listing)
+}
+
+fn parse_args(builtin: &str, args: &[String]) -> Result<ParsedArgs, CommandOutput> {
+    let usage = |message: String| {
+        CommandOutput::stderr(format!(
+            "{}: {}\n{}: usage: {} [-abcdefkuv] [-pr] [-o option] [-A action] [-W wordlist] [-C command] [name ...]\n",
+            builtin, message, builtin, builtin
+        ))
+        .with_status(2)
+    };
+    let mut parsed = ParsedArgs {
+        spec: CompletionSpec::default(),
+        print: false,
+        remove: false,
+        operands: Vec::new(),
+    };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            parsed.operands.extend(args.by_ref().cloned());
+            break;
+        }
+        let Some(flags) = arg.strip_prefix('-').filter(|flags| !flags.is_empty()) else {
+            parsed.operands.push(arg.clone());
+            parsed.operands.extend(args.by_ref().cloned());
+            break;
+        };
+
+        for (index, flag) in flags.char_indices() {
+            if "oAWFC".contains(flag) {
+                let attached = &flags[index + 1..];
+                let value = if attached.is_empty() {
+                    args.next()
+                        .cloned()
+                        .ok_or_else(|| usage(format!("-{}: option requires an argument", flag)))?
+                } else {
+                    attached.to_string()
+                };
+                match flag {
+                    'o' if OPTIONS.contains(&value.as_str()) => parsed.spec.options.push(value),
+                    'o' => return Err(usage(format!("{}: invalid option name", value))),
+                    'A' => match ACTIONS.iter().find(|(name, _, _)| *name == value) {
+                        Some((_, _, action)) => parsed.spec.actions.push(*action),
+                        None => return Err(usage(format!("{}: invalid action name", value))),
+                    },
+                    'W' => parsed.spec.words = Some(value),
+                    // There are no shell functions for `-F` to call
+                    'F' => {
+                        return Err(CommandOutput::stderr(format!(
+                            "{}: -F: shell functions are not supported\n",
+                            builtin
+                        ))
+                        .with_status(2))
+                    }
+                    _ => parsed.spec.command = Some(value),
+                }
+                break;
+            }
+            match flag {
+                'p' => parsed.print = true,
+                'r' => parsed.remove = true,
+                _ => match ACTIONS.iter().find(|(_, short, _)| *short == flag) {
+                    Some((_, _, action)) => parsed.spec.actions.push(*action),
+                    None => return Err(usage(format!("-{}: invalid option", flag))),
+                },
+            }
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn action_names(action: Action, prefix: &str, sources: &CompletionSources) -> Vec<String> {
+    let strings = |names: &[&str]| {
+        names
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>()
+    };
+    match action {
+        Action::Alias => sources.aliases.clone(),
+        Action::Builtin => strings(builtins::BUILTIN_COMMANDS),
+        Action::Keyword => strings(builtins::KEYWORDS),
+        Action::Command => {
+            let mut names = sources.aliases.clone();
+            names.extend(strings(builtins::BUILTIN_COMMANDS));
+            names.extend(strings(builtins::KEYWORDS));
+            names.extend(sources.commands.borrow_mut().executables(prefix));
+            names
+        }
+        Action::Export => env::vars().map(|(name, _)| name).collect(),
+        Action::Variable => sources.variables.keys().cloned().collect(),
+        Action::User => user_names(),
+        Action::Hostname => host_names(sources.variables),
+        Action::File | Action::Directory => Vec::new(),
+    }
+}
+
+pub fn user_names() -> Vec<String> {
+    let mut names = Vec::new();
+    unsafe {
+        libc::setpwent();
+        loop {
+            let entry = libc::getpwent();
+            if entry.is_null() {
+                break;
+            }
+            names.push(
+                CStr::from_ptr((*entry).pw_name)
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+        }
+        libc::endpwent();
+    }
+    names
+}
+
+// Names from `HOSTFILE` (or `/etc/hosts` when it is unset), then the hosts of `~/.ssh/config`
+// and `~/.ssh/known_hosts`
+pub fn host_names(variables: &HashMap<String, String>) -> Vec<String> {
+    let file = variables
+        .get("HOSTFILE")
+        .filter(|file| !file.is_empty())
+        .map_or("/etc/hosts", String::as_str);
+    let mut names: Vec<String> = fs::read_to_string(file)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split_whitespace().skip(1))
+        .map(String::from)
+        .collect();
+
+    let ssh = utils::home_directory().map(|home| format!("{}/.ssh", home));
+    let ssh = ssh.as_deref().unwrap_or("/nonexistent");
+    let config = fs::read_to_string(format!("{}/config", ssh)).unwrap_or_default();
+    for line in config.lines() {
+        let mut fields = line.split_whitespace();
+        if fields.next().is_some_and(|keyword| keyword.eq_ignore_ascii_case("host")) {
+            names.extend(
+                fields
+                    .take_while(|pattern| !pattern.starts_with('#'))
+                    .filter(|pattern| !pattern.contains(['*', '?', '!']))
+                    .map(String::from),
+            );
+        }
+    }
+
+    // Hashed entries can't be read back; `[host]:port` entries are reduced to the host
+    let known_hosts = fs::read_to_string(format!("{}/known_hosts", ssh)).unwrap_or_default();
+    for line in known_hosts.lines() {
+        let mut fields = line.split_whitespace().filter(|field| !field.starts_with('@'));
+        let Some(hosts) = fields.next().filter(|hosts| !hosts.starts_with(['|', '#'])) else {
+            continue;
+        };
+        names.extend(
+            hosts
+                .split(',')
+                .filter(|host| !host.contains(['*', '?', '!']))
+                .map(|host| {
+                    host.strip_prefix('[')
+                        .and_then(|host| host.split_once(']'))
+                        .map_or(host, |(host, _)| host)
+                        .to_string()
+                }),
+        );
+    }
+    names
+}
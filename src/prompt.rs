@@ -0,0 +1,292 @@
+use std::env;
+use std::ffi::CStr;
+use std::path::Path;
+
+use crate::builtins;
+use crate::git_prompt::GitPrompt;
+use crate::parser::WordExpander;
+use crate::utils;
+
+pub const DEFAULT_PS1: &str = "$ ";
+pub const DEFAULT_PS2: &str = "> ";
+pub const DEFAULT_PS4: &str = "+ ";
+
+pub struct PromptInfo<'a> {
+    pub history_number: usize,
+    pub command_number: usize,
+    pub git: &'a GitPrompt,
+}
+
+// Decodes bash prompt escapes, then expands `$var`, `${var}`, `$(cmd)` and `` `cmd` ``
+pub fn render(template: &str, info: &PromptInfo, expander: &impl WordExpander) -> String {
+    let decoded = decode_escapes(template, info, expander);
+    expand_substitutions(&decoded, expander)
+}
+
+fn decode_escapes(template: &str, info: &PromptInfo, expander: &impl WordExpander) -> String {
+    let mut result = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let Some(escape) = chars.next() else {
+            result.push('\\');
+            break;
+        };
+
+        match escape {
+            'a' => result.push('\x07'),
+            'e' => result.push('\x1b'),
+            'n' => result.push('\n'),
+            'r' => result.push('\r'),
+            '\\' => result.push('\\'),
+            // Non-printing sequences need no markers: the editor skips ANSI escapes when measuring
+            '[' | ']' => {}
+            'u' => push_literal(&mut result, &user_name()),
+            'h' => push_literal(&mut result, host_name().split('.').next().unwrap_or_default()),
+            'H' => push_literal(&mut result, &host_name()),
+            'w' => push_literal(
+                &mut result,
+                &utils::abbreviate_home(&builtins::logical_working_directory()),
+            ),
+            'W' => push_literal(&mut result, &directory_basename()),
+            'g' => {
+                let directory = builtins::logical_working_directory();
+                push_literal(&mut result, &info.git.segment(Path::new(&directory)));
+            }
+            's' => result.push_str("shell-rs"),
+            'v' => {
+                let version = env!("CARGO_PKG_VERSION");
+                result.push_str(version.rsplit_once('.').map_or(version, |(short, _)| short));
+            }
+            'V' => result.push_str(env!("CARGO_PKG_VERSION")),
+            '$' => {
+                let symbol = if unsafe { libc::geteuid() } == 0 { "#" } else { "$" };
+                push_literal(&mut result, symbol);
+            }
+            '?' => push_literal(&mut result, &expander.lookup_variable("?").unwrap_or_default()),
+            'j' => result.push('0'),
+            'l' => push_literal(&mut result, &terminal_name()),
+            '!' => result.push_str(&info.history_number.to_string()),
+            '#' => result.push_str(&info.command_number.to_string()),
+            't' => result.push_str(&format_time("%H:%M:%S")),
+            'T' => result.push_str(&format_time("%I:%M:%S")),
+            '@' => result.push_str(&format_time("%I:%M %p")),
+            'A' => result.push_str(&format_time("%H:%M")),
+            'd' => result.push_str(&format_time("%a %b %d")),
+            'D' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let format: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                result.push_str(&format_time(if format.is_empty() { "%X" } else { &format }));
+            }
+            '0'..='7' => {
+                let mut code = escape.to_digit(8).unwrap_or_default();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                result.extend(char::from_u32(code));
+            }
+            other => {
+                result.push('\\');
+                result.push(other);
+            }
+        }
+    }
+
+    result
+}
+
+// Escapes `$`, `` ` `` and `\` in text an escape produced, so that a directory or branch name is
+// shown as it is rather than expanded, as bash does for its prompt
+fn push_literal(result: &mut String, text: &str) {
+    for c in text.chars() {
+        if "$`\\".contains(c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+}
+
+fn expand_substitutions(text: &str, expander: &impl WordExpander) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|next| "$`\\".contains(*next)) => {
+                result.extend(chars.next());
+            }
+            '`' => {
+                let command: String = chars.by_ref().take_while(|&c| c != '`').collect();
+                result.push_str(&utils::run_in_child_shell(&command, &[]));
+            }
+            '$' => match chars.peek() {
+                Some('(') => {
+                    chars.next();
+                    let mut depth = 1;
+                    let mut command = String::new();
+                    for c in chars.by_ref() {
+                        match c {
+                            '(' => depth += 1,
+                            ')' => {
+                                depth -= 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                            }
+                            _ => {}
+                        }
+                        command.push(c);
+                    }
+                    result.push_str(&utils::run_in_child_shell(&command, &[]));
+                }
+                Some('{') => {
+                    chars.next();
+                    let expression: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    result.push_str(&expander.lookup_variable(&expression).unwrap_or_default());
+                }
+                Some(&special @ ('?' | '$')) => {
+                    chars.next();
+                    result.push_str(&expander.lookup_variable(&special.to_string()).unwrap_or_default());
+                }
+                Some(next) if next.is_alphabetic() || *next == '_' => {
+                    let mut name = String::new();
+                    while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                        name.push(c);
+                        chars.next();
+                    }
+                    result.push_str(&expander.lookup_variable(&name).unwrap_or_default());
+                }
+                _ => result.push('$'),
+            },
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+fn user_name() -> String {
+    let entry = unsafe { libc::getpwuid(libc::geteuid()) };
+    if entry.is_null() {
+        return env::var("USER").unwrap_or_default();
+    }
+    unsafe { CStr::from_ptr((*entry).pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn host_name() -> String {
+    let mut buffer = [0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0 {
+        return String::new();
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+fn directory_basename() -> String {
+    let directory = builtins::logical_working_directory();
+    if utils::home_directory().as_deref() == Some(directory.as_str()) {
+        return "~".to_string();
+    }
+    match directory.rsplit_once('/') {
+        Some((_, "")) | None => directory,
+        Some((_, name)) => name.to_string(),
+    }
+}
+
+fn terminal_name() -> String {
+    let name = unsafe { libc::ttyname(libc::STDIN_FILENO) };
+    if name.is_null() {
+        return "tty".to_string();
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    name.rsplit('/').next().unwrap_or_default().to_string()
+}
+
+fn format_time(format: &str) -> String {
+    utils::format_time(format, utils::current_time())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    struct Status;
+
+    impl WordExpander for Status {
+        fn expand_tilde(&self, _prefix: &str) -> Option<String> {
+            None
+        }
+
+        fn lookup_variable(&self, name: &str) -> Option<String> {
+            (name == "?").then(|| "1".to_string())
+        }
+    }
+
+    fn decode(template: &str) -> String {
+        let git = GitPrompt::new();
+        let info = PromptInfo {
+            history_number: 12,
+            command_number: 3,
+            git: &git,
+        };
+        decode_escapes(template, &info, &Status)
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(decode("\\! \\# \\?"), "12 3 1");
+        assert_eq!(
+            decode("\\s-\\V"),
+            format!("shell-rs-{}", env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(decode("a\\nb\\\\"), "a\nb\\");
+        assert_eq!(decode("\\[\\e[1m\\]x"), "\x1b[1mx");
+        assert_eq!(decode("\\101\\07"), "A\x07");
+        assert_eq!(decode("\\q"), "\\q");
+        let symbol = if unsafe { libc::geteuid() } == 0 {
+            "#"
+        } else {
+            "\\$"
+        };
+        assert_eq!(decode("\\$"), symbol);
+    }
+
+    #[test]
+    fn shows_directory_names_literally() {
+        let _lock = builtins::ENVIRONMENT_LOCK.lock().unwrap();
+        let original = builtins::logical_working_directory();
+        let base = env::temp_dir().join(format!("shell-rs-prompt-{}", std::process::id()));
+        let directory = base.join("$(touch PWNED)");
+        fs::create_dir_all(&directory).unwrap();
+        let directory = directory.to_string_lossy().into_owned();
+        builtins::change_working_directory(&directory, false).unwrap();
+
+        let git = GitPrompt::new();
+        let info = PromptInfo {
+            history_number: 1,
+            command_number: 1,
+            git: &git,
+        };
+        let shown = utils::abbreviate_home(&directory);
+        assert_eq!(render("\\w$ ", &info, &Status), format!("{}$ ", shown));
+        assert_eq!(render("\\W", &info, &Status), "$(touch PWNED)");
+        assert!(!base.join("PWNED").exists() && !Path::new(&directory).join("PWNED").exists());
+
+        builtins::change_working_directory(&original, false).unwrap();
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
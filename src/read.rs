@@ -0,0 +1,450 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::time::{Duration, Instant};
+
+use crate::output::CommandOutput;
+use crate::redirection::Redirection;
+use crate::utils;
+use crate::variables::{self, Variables};
+
+const USAGE: &str = "read: usage: read [-rs] [-a array] [-d delim] [-n nchars] [-p prompt] \
+[-t timeout] [name ...]\n";
+const DEFAULT_IFS: &str = " \t\n";
+const TIMEOUT_STATUS: i32 = 142;
+
+#[derive(Default)]
+struct ReadOptions {
+    raw: bool,
+    silent: bool,
+    prompt: Option<String>,
+    array: Option<String>,
+    delimiter: Option<u8>,
+    max_chars: Option<usize>,
+    timeout: Option<Duration>,
+    names: Vec<String>,
+}
+
+enum Input {
+    Terminal,
+    Reader(Box<dyn BufRead>, RawFd),
+}
+
+enum ReadResult {
+    Byte(u8),
+    Eof,
+    TimedOut,
+}
+
+enum Outcome {
+    Complete,
+    Eof,
+    TimedOut,
+}
+
+impl Input {
+    // A redirected file is opened for each `read`, so reading starts at its first line every
+    // time; reading a file line by line would need a loop to keep it open across reads.
+    // With a deadline a FIFO is opened without blocking and then given until the deadline to
+    // have data written, as an empty FIFO with no writer would otherwise read as end of file
+    fn open(stdin_redir: &Option<Redirection>, deadline: Option<Instant>) -> io::Result<Self> {
+        match stdin_redir {
+            Some(redir) => {
+                let flags = if deadline.is_some() { libc::O_NONBLOCK } else { 0 };
+                let file = OpenOptions::new()
+                    .read(true)
+                    .custom_flags(flags)
+                    .open(&redir.filename)?;
+                let fd = file.as_raw_fd();
+                if deadline.is_some() && !wait_for_input(fd, deadline) {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                Ok(Input::Reader(Box::new(BufReader::new(file)), fd))
+            }
+            None if io::stdin().is_terminal() => Ok(Input::Terminal),
+            None => Ok(Input::Reader(
+                Box::new(io::stdin().lock()),
+                libc::STDIN_FILENO,
+            )),
+        }
+    }
+
+    // Files and pipes are non-blocking while a timeout applies (see `NonBlocking`), so an empty
+    // buffer waits in poll(2) until the deadline
+    fn read_byte(&mut self, deadline: Option<Instant>) -> ReadResult {
+        match self {
+            Input::Terminal => read_terminal_byte(deadline),
+            Input::Reader(reader, fd) => loop {
+                match reader.fill_buf() {
+                    Ok([byte, ..]) => {
+                        let byte = *byte;
+                        reader.consume(1);
+                        return ReadResult::Byte(byte);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        if !wait_for_input(*fd, deadline) {
+                            return ReadResult::TimedOut;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    _ => return ReadResult::Eof,
+                }
+            },
+        }
+    }
+
+    fn has_input(&mut self) -> bool {
+        match self {
+            Input::Terminal => wait_for_input(libc::STDIN_FILENO, Some(Instant::now())),
+            Input::Reader(reader, _) => reader.fill_buf().is_ok_and(|buf| !buf.is_empty()),
+        }
+    }
+}
+
+// Terminal bytes are read unbuffered so typeahead stays available to the line editor
+fn read_terminal_byte(deadline: Option<Instant>) -> ReadResult {
+    if deadline.is_some() && !wait_for_input(libc::STDIN_FILENO, deadline) {
+        return ReadResult::TimedOut;
+    }
+
+    let mut byte = 0u8;
+    loop {
+        let read = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+        match read {
+            1 => return ReadResult::Byte(byte),
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => continue,
+            _ => return ReadResult::Eof,
+        }
+    }
+}
+
+// Whether `fd` becomes readable (or reaches end of file) before the deadline, if there is one
+fn wait_for_input(fd: RawFd, deadline: Option<Instant>) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = deadline.map_or(-1, |deadline| {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        remaining.as_millis().min(i32::MAX as u128) as i32
+    });
+    unsafe { libc::poll(&mut pollfd, 1, millis) > 0 }
+}
+
+// Makes reads from a file or pipe return at once when nothing is available, restoring the
+// descriptor's flags when dropped since standard input is shared with other processes
+struct NonBlocking {
+    fd: RawFd,
+    flags: libc::c_int,
+}
+
+impl NonBlocking {
+    fn set(fd: RawFd) -> Option<Self> {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 {
+            return None;
+        }
+        unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+        Some(Self { fd, flags })
+    }
+}
+
+impl Drop for NonBlocking {
+    fn drop(&mut self) {
+        unsafe { libc::fcntl(self.fd, libc::F_SETFL, self.flags) };
+    }
+}
+
+// Puts the terminal into the mode `read` needs and restores it when dropped
+struct TerminalMode {
+    original: libc::termios,
+}
+
+impl TerminalMode {
+    fn configure(echo: bool, canonical: bool) -> Option<Self> {
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return None;
+        }
+
+        let mut mode = original;
+        if !echo {
+            mode.c_lflag &= !libc::ECHO;
+        }
+        if !canonical {
+            mode.c_lflag &= !libc::ICANON;
+            mode.c_cc[libc::VMIN] = 1;
+            mode.c_cc[libc::VTIME] = 0;
+        }
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &mode) };
+        Some(Self { original })
+    }
+}
+
+impl Drop for TerminalMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+pub fn read(
+    args: &[String],
+    variables: &mut Variables,
+    stdin_redir: &Option<Redirection>,
+) -> CommandOutput {
+    let options = match parse_options(args) {
+        Ok(options) => options,
+        Err(output) => return output,
+    };
+
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut input = match Input::open(stdin_redir, deadline) {
+        Ok(input) => input,
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+            let status = if options.timeout == Some(Duration::ZERO) { 1 } else { TIMEOUT_STATUS };
+            return CommandOutput::default().with_status(status);
+        }
+        Err(e) => {
+            let filename = stdin_redir.as_ref().map_or("", |redir| redir.filename.as_str());
+            return CommandOutput::stderr(format!(
+                "read: {}: {}\n",
+                filename,
+                utils::io_error_message(&e)
+            ));
+        }
+    };
+
+    let _non_blocking = match input {
+        Input::Reader(_, fd) if options.timeout.is_some() => NonBlocking::set(fd),
+        _ => None,
+    };
+    if options.timeout == Some(Duration::ZERO) {
+        return CommandOutput::default().with_status(i32::from(!input.has_input()));
+    }
+
+    let _terminal_mode = match input {
+        Input::Terminal => {
+            if let Some(prompt) = &options.prompt {
+                eprint!("{}", prompt);
+                let _ = io::stderr().flush();
+            }
+            let canonical = options.max_chars.is_none() && options.delimiter.is_none();
+            TerminalMode::configure(!options.silent, canonical)
+        }
+        Input::Reader(..) => None,
+    };
+
+    let (chars, outcome) = read_chars(&mut input, &options, deadline);
+    assign_fields(&chars, &options, variables);
+
+    let status = match outcome {
+        Outcome::Complete => 0,
+        Outcome::Eof => 1,
+        Outcome::TimedOut => TIMEOUT_STATUS,
+    };
+    CommandOutput::default().with_status(status)
+}
+
+fn parse_options(args: &[String]) -> Result<ReadOptions, CommandOutput> {
+    let mut options = ReadOptions::default();
+    let mut index = 0;
+
+    while let Some(arg) = args.get(index) {
+        if arg == "--" {
+            index += 1;
+            break;
+        }
+        if arg == "-" || !arg.starts_with('-') {
+            break;
+        }
+
+        let flags: Vec<char> = arg.chars().skip(1).collect();
+        for (position, &flag) in flags.iter().enumerate() {
+            match flag {
+                'r' => options.raw = true,
+                's' => options.silent = true,
+                'a' | 'd' | 'n' | 'p' | 't' => {
+                    let attached: String = flags[position + 1..].iter().collect();
+                    let value = if attached.is_empty() {
+                        index += 1;
+                        args.get(index).cloned().ok_or_else(|| {
+                            CommandOutput::stderr(format!(
+                                "read: -{}: option requires an argument\n{}",
+                                flag, USAGE
+                            ))
+                            .with_status(2)
+                        })?
+                    } else {
+                        attached
+                    };
+                    apply_option(&mut options, flag, value)?;
+                    break;
+                }
+                _ => {
+                    return Err(CommandOutput::stderr(format!(
+                        "read: -{}: invalid option\n{}",
+                        flag, USAGE
+                    ))
+                    .with_status(2))
+                }
+            }
+        }
+        index += 1;
+    }
+
+    options.names = args[index.min(args.len())..].to_vec();
+    let invalid = options
+        .names
+        .iter()
+        .chain(options.array.iter())
+        .find(|name| !variables::is_valid_name(name));
+    if let Some(name) = invalid {
+        return Err(CommandOutput::stderr(format!(
+            "read: `{}': not a valid identifier\n",
+            name
+        )));
+    }
+
+    Ok(options)
+}
+
+fn apply_option(options: &mut ReadOptions, flag: char, value: String) -> Result<(), CommandOutput> {
+    match flag {
+        'a' => options.array = Some(value),
+        'd' => options.delimiter = Some(value.bytes().next().unwrap_or(0)),
+        'n' => match value.parse() {
+            Ok(count) => options.max_chars = Some(count),
+            Err(_) => {
+                return Err(CommandOutput::stderr(format!("read: {}: invalid number\n", value)))
+            }
+        },
+        'p' => options.prompt = Some(value),
+        _ => match value.parse::<f64>() {
+            Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => {
+                options.timeout = Some(Duration::from_secs_f64(seconds))
+            }
+            _ => {
+                return Err(CommandOutput::stderr(format!(
+                    "read: {}: invalid timeout specification\n",
+                    value
+                )))
+            }
+        },
+    }
+    Ok(())
+}
+
+// Each character is paired with whether a backslash protected it from field splitting
+fn read_chars(
+    input: &mut Input,
+    options: &ReadOptions,
+    deadline: Option<Instant>,
+) -> (Vec<(char, bool)>, Outcome) {
+    let delimiter = options.delimiter.unwrap_or(b'\n');
+    let mut chars = Vec::new();
+    let mut pending = Vec::new();
+    let mut escaped = false;
+
+    loop {
+        if options.max_chars.is_some_and(|max| chars.len() >= max) {
+            return (chars, Outcome::Complete);
+        }
+
+        let byte = match input.read_byte(deadline) {
+            ReadResult::Byte(byte) => byte,
+            ReadResult::Eof => return (chars, Outcome::Eof),
+            ReadResult::TimedOut => return (chars, Outcome::TimedOut),
+        };
+
+        if pending.is_empty() {
+            if escaped && byte == b'\n' {
+                // Backslash-newline continues the line
+                escaped = false;
+                continue;
+            }
+            if byte == delimiter && !escaped {
+                return (chars, Outcome::Complete);
+            }
+            if byte == b'\\' && !options.raw && !escaped {
+                escaped = true;
+                continue;
+            }
+        }
+
+        pending.push(byte);
+        let decoded = match std::str::from_utf8(&pending) {
+            Ok(text) => text.chars().next(),
+            Err(e) if e.error_len().is_none() && pending.len() < 4 => continue,
+            Err(_) => Some(char::REPLACEMENT_CHARACTER),
+        };
+        pending.clear();
+        if let Some(c) = decoded {
+            chars.push((c, std::mem::take(&mut escaped)));
+        }
+    }
+}
+
+fn assign_fields(chars: &[(char, bool)], options: &ReadOptions, variables: &mut Variables) {
+    let ifs = variables.get("IFS").unwrap_or_else(|| DEFAULT_IFS.to_string());
+
+    if let Some(array) = &options.array {
+        variables.set_array(array, split_fields(chars, &ifs, None));
+        return;
+    }
+
+    if options.names.is_empty() {
+        variables.set("REPLY", chars.iter().map(|&(c, _)| c).collect::<String>());
+        return;
+    }
+
+    let fields = split_fields(chars, &ifs, Some(options.names.len()));
+    for (index, name) in options.names.iter().enumerate() {
+        variables.set(name, fields.get(index).cloned().unwrap_or_default());
+    }
+}
+
+// Splits per IFS; with a field limit the last field receives the rest of the line
+fn split_fields(chars: &[(char, bool)], ifs: &str, limit: Option<usize>) -> Vec<String> {
+    let is_delimiter = |&(c, escaped): &(char, bool)| !escaped && ifs.contains(c);
+    let is_ifs_whitespace = |entry: &(char, bool)| is_delimiter(entry) && " \t\n".contains(entry.0);
+
+    if ifs.is_empty() {
+        return vec![chars.iter().map(|&(c, _)| c).collect()];
+    }
+
+    let mut fields = Vec::new();
+    let mut position = 0;
+    let skip_whitespace = |position: &mut usize| {
+        while chars.get(*position).is_some_and(is_ifs_whitespace) {
+            *position += 1;
+        }
+    };
+    skip_whitespace(&mut position);
+
+    while position < chars.len() {
+        if limit.is_some_and(|limit| fields.len() + 1 == limit) {
+            let mut end = chars.len();
+            while end > position && is_ifs_whitespace(&chars[end - 1]) {
+                end -= 1;
+            }
+            fields.push(chars[position..end].iter().map(|&(c, _)| c).collect());
+            break;
+        }
+
+        let start = position;
+        while position < chars.len() && !is_delimiter(&chars[position]) {
+            position += 1;
+        }
+        fields.push(chars[start..position].iter().map(|&(c, _)| c).collect());
+
+        skip_whitespace(&mut position);
+        if chars.get(position).is_some_and(is_delimiter) {
+            position += 1;
+            skip_whitespace(&mut position);
+        }
+    }
+
+    fields
+}
@@ -1,58 +1,55 @@
-#[derive(Debug, PartialEq)]
-pub enum RedirectionType {
-    Stdout,
-    Stderr,
-}
-
 #[derive(Debug)]
 pub enum RedirectionMode {
+    Read,
     Truncate,
     Append,
 }
 
 #[derive(Debug)]
 pub struct Redirection {
-    pub ty: RedirectionType,
     pub mode: RedirectionMode,
     pub filename: String,
 }
 
 pub fn process_redirections(
     args: Vec<String>,
-) -> (Vec<String>, Option<Redirection>, Option<Redirection>) {
+) -> (Vec<String>, Option<Redirection>, Option<Redirection>, Option<Redirection>) {
     let mut processed_args = Vec::new();
+    let mut stdin_redir = None;
     let mut stdout_redir = None;
     let mut stderr_redir = None;
 
     let mut i = 0;
     while i < args.len() {
         match args[i].as_str() {
+            "<" | "0<" => parse_redirection_operator(
+                &mut stdin_redir,
+                &args,
+                &mut i,
+                RedirectionMode::Read,
+            ),
             ">" | "1>" => parse_redirection_operator(
                 &mut stdout_redir,
                 &args,
                 &mut i,
-                RedirectionType::Stdout,
                 RedirectionMode::Truncate,
             ),
             ">>" | "1>>" => parse_redirection_operator(
                 &mut stdout_redir,
                 &args,
                 &mut i,
-                RedirectionType::Stdout,
                 RedirectionMode::Append,
             ),
             "2>" => parse_redirection_operator(
                 &mut stderr_redir,
                 &args,
                 &mut i,
-                RedirectionType::Stderr,
                 RedirectionMode::Truncate,
             ),
             "2>>" => parse_redirection_operator(
                 &mut stderr_redir,
                 &args,
                 &mut i,
-                RedirectionType::Stderr,
                 RedirectionMode::Append,
             ),
             _ => {
@@ -61,25 +58,25 @@ pub fn process_redirections(
             }
         }
     }
-    (processed_args, stdout_redir, stderr_redir)
+    (processed_args, stdin_redir, stdout_redir, stderr_redir)
 }
 
 fn parse_redirection_operator(
     target: &mut Option<Redirection>,
     args: &[String],
     i: &mut usize,
-    ty: RedirectionType,
     mode: RedirectionMode,
 ) {
     if *i + 1 < args.len() {
         *target = Some(Redirection {
-            ty,
             mode,
             filename: args[*i + 1].clone(),
         });
         *i += 2;
     } else {
-        target.as_mut().map(|r| r.filename.push_str(&args[*i]));
+        if let Some(redir) = target.as_mut() {
+            redir.filename.push_str(&args[*i]);
+        }
         *i += 1;
     }
 }
\ No newline at end of file
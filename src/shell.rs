@@ -1,59 +1,500 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::env;
-use std::process::exit;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{self, exit};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use rustyline::error::ReadlineError;
-use rustyline::Editor;
-use rustyline::history::DefaultHistory;
+use rustyline::config::Configurer;
+use rustyline::{Cmd, EditMode, Editor, EventHandler, KeyCode, KeyEvent, Modifiers};
+use rustyline::history::{DefaultHistory, History};
 
-use crate::autocompletion::BuiltInCompleter;
+use crate::alias::Aliases;
+use crate::autocompletion::{AcceptHint, AcceptHintWord, BuiltInCompleter};
 use crate::builtins;  // Add this import
 use crate::command::execute_command;
-use crate::output;
-use crate::parser::parse_arguments;
-use crate::redirection::process_redirections;
+use crate::command_index::CommandIndex;
+use crate::command_lookup;
+use crate::conditional;
+use crate::dirstack::DirectoryStack;
+use crate::git_prompt::GitPrompt;
+use crate::highlight::Palette;
+use crate::history::{self, HistoryPolicy, HistoryRecord};
+use crate::history_expansion::HistoryExpander;
+use crate::history_search::{self, Candidate, SearchKey, Selection};
+use crate::key_bindings::{KeyBindings, KeyCommand};
+use crate::options::ShellOptions;
+use crate::output::{self, CommandOutput};
+use crate::parser::{
+    parse_arguments, parse_conditional_words, split_command_list, Connector, WordExpander,
+};
+use crate::printf;
+use crate::programmable_completion::{self, CompletionSources, CompletionSpecs};
+use crate::prompt::{self, PromptInfo};
+use crate::read;
+use crate::redirection::{process_redirections, Redirection};
+use crate::startup::StartupOptions;
+use crate::utils::{self, Quoting};
+use crate::validation;
+use crate::variables::{self, Variables};
+
+const HISTORY_USAGE: &str = "history: usage: history [-c] [-d offset] [n] or history -awr [filename] \
+    or history [--cwd[=dir]] [--failed] [--since age] [n]\n";
 
 pub struct Shell {
-    paths: Vec<String>,
+    commands: Rc<RefCell<CommandIndex>>,
     editor: Editor<BuiltInCompleter, DefaultHistory>, 
+    aliases: Rc<RefCell<Aliases>>,
+    directory_stack: DirectoryStack,
+    variables: Variables,
+    last_status: i32,
+    source_location: Option<(String, usize)>,
+    interactive: bool,
+    session_history: Vec<String>,
+    history_records: Vec<HistoryRecord>,
+    session_records: Vec<HistoryRecord>,
+    // The record in `history_records` of the line being run, and when it started
+    pending_record: Option<(usize, Instant)>,
+    session_id: String,
+    history_search: Arc<Mutex<Option<String>>>,
+    history_expander: HistoryExpander,
+    options: ShellOptions,
+    git_prompt: GitPrompt,
+    command_number: usize,
+    completion_specs: Rc<RefCell<CompletionSpecs>>,
+    key_bindings: KeyBindings,
 }
 
 impl Shell {
-    pub fn new() -> Self {
-        let paths: Vec<String> = env::var("PATH")
-            .unwrap_or_default()
-            .split(':')
-            .map(String::from)
-            .collect();
-
+    pub fn new(interactive: bool) -> Self {
         let mut editor = Editor::with_config(
             rustyline::Config::builder()
                 .completion_type(rustyline::CompletionType::List)
+                .history_ignore_dups(false)
+                .expect("Should configure history")
                 .build(),
         )
         .expect("Should create readline instance");
         
-        editor.set_helper(Some(BuiltInCompleter::new()));
+        let aliases = Rc::new(RefCell::new(Aliases::new()));
+        let commands = Rc::new(RefCell::new(CommandIndex::following_path()));
+        let completion_specs = Rc::new(RefCell::new(CompletionSpecs::new()));
+        editor.set_helper(Some(BuiltInCompleter::new(
+            Rc::clone(&aliases),
+            Rc::clone(&commands),
+            Rc::clone(&completion_specs),
+        )));
+        // Enter always submits so continuation lines are read at the `PS2` prompt; without a
+        // terminal the editor's validator joins them instead
+        editor.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::NONE), Cmd::AcceptLine);
+        editor.bind_sequence(
+            KeyEvent(KeyCode::End, Modifiers::NONE),
+            EventHandler::Conditional(Box::new(AcceptHint)),
+        );
+        editor.bind_sequence(
+            KeyEvent::alt('f'),
+            EventHandler::Conditional(Box::new(AcceptHintWord)),
+        );
+        let history_search = Arc::new(Mutex::new(None));
+        editor.bind_sequence(
+            KeyEvent::ctrl('r'),
+            EventHandler::Conditional(Box::new(SearchKey {
+                pending: Arc::clone(&history_search),
+            })),
+        );
+        builtins::initialize_working_directory();
+
+        let mut variables = Variables::new();
+        if variables.get("HISTFILE").is_none() {
+            if let Some(home) = utils::home_directory() {
+                variables.set("HISTFILE", format!("{}/.shellrs_history", home));
+            }
+        }
+        for name in ["HISTSIZE", "HISTFILESIZE"] {
+            if variables.get(name).is_none() {
+                variables.set(name, history::DEFAULT_HISTORY_SIZE.to_string());
+            }
+        }
+        for (name, value) in [
+            ("PS1", prompt::DEFAULT_PS1),
+            ("PS2", prompt::DEFAULT_PS2),
+            ("PS4", prompt::DEFAULT_PS4),
+        ] {
+            if variables.get(name).is_none() {
+                variables.set(name, value);
+            }
+        }
 
         Shell { 
-            paths,
+            commands,
             editor,
+            aliases,
+            directory_stack: DirectoryStack::new(),
+            variables,
+            last_status: 0,
+            source_location: None,
+            interactive,
+            session_history: Vec::new(),
+            history_records: Vec::new(),
+            session_records: Vec::new(),
+            pending_record: None,
+            session_id: format!("{}-{}", utils::current_time(), process::id()),
+            history_search,
+            history_expander: HistoryExpander::new(),
+            options: ShellOptions::new(),
+            git_prompt: GitPrompt::new(),
+            command_number: 1,
+            completion_specs,
+            key_bindings: KeyBindings::new(),
+        }
+    }
+
+    // Called after the startup files so they can change HISTFILE and HISTSIZE
+    pub fn load_history(&mut self) {
+        if !self.interactive {
+            return;
+        }
+        let limit = self.history_size();
+        let _ = self.editor.history_mut().set_max_len(limit);
+
+        let Some(path) = self.history_file() else {
+            return;
+        };
+        match history::load(&path, Some(limit)) {
+            Ok(entries) => {
+                for entry in entries {
+                    let _ = self.editor.add_history_entry(entry);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("shell-rs: {}: {}", path, utils::io_error_message(&e)),
+        }
+
+        let records_path = history::records_file(&path);
+        match history::load_records(&records_path, Some(limit)) {
+            Ok(records) => self.history_records = records,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("shell-rs: {}: {}", records_path, utils::io_error_message(&e)),
+        }
+    }
+
+    fn history_file(&self) -> Option<String> {
+        self.variables
+            .get("HISTFILE")
+            .filter(|file| !file.is_empty())
+    }
+
+    fn history_size(&self) -> usize {
+        history::parse_limit(self.variables.get("HISTSIZE")).unwrap_or(usize::MAX)
+    }
+
+    fn record_history(&mut self, line: &str) {
+        let policy = HistoryPolicy::from_variables(&self.variables);
+        let limit = self.history_size();
+        let history = self.editor.history_mut();
+        let previous = history.iter().next_back().cloned();
+        if !policy.accepts(line, previous.as_deref()) {
+            return;
+        }
+
+        let _ = history.set_max_len(limit);
+        if policy.erase_dups {
+            let kept: Vec<String> = history
+                .iter()
+                .filter(|entry| *entry != line)
+                .cloned()
+                .collect();
+            if kept.len() != history.len() {
+                let _ = history.clear();
+                for entry in kept {
+                    let _ = history.add_owned(entry);
+                }
+            }
+            self.session_history.retain(|entry| entry != line);
+        }
+        let _ = history.add(line);
+        self.session_history.push(line.to_string());
+
+        let record = HistoryRecord {
+            line: line.to_string(),
+            time: utils::current_time(),
+            duration: Duration::ZERO,
+            status: None,
+            cwd: builtins::logical_working_directory(),
+            session: self.session_id.clone(),
+        };
+        self.history_records.push(record);
+        self.pending_record = Some((self.history_records.len() - 1, Instant::now()));
+        self.trim_history_records();
+    }
+
+    // Drops the oldest records beyond HISTSIZE, keeping the pending record's index in step
+    fn trim_history_records(&mut self) {
+        let excess = self.history_records.len().saturating_sub(self.history_size());
+        self.history_records.drain(..excess);
+        self.pending_record = self
+            .pending_record
+            .and_then(|(index, started)| Some((index.checked_sub(excess)?, started)));
+    }
+
+    fn remove_history_record(&mut self, index: usize) {
+        self.history_records.remove(index);
+        self.pending_record = match self.pending_record {
+            Some((pending, _)) if pending == index => None,
+            Some((pending, started)) if pending > index => Some((pending - 1, started)),
+            pending => pending,
+        };
+    }
+
+    // Every record but the one of the line still running, which is saved once it finishes
+    fn finished_history_records(&self) -> Vec<HistoryRecord> {
+        let pending = self.pending_record.map(|(index, _)| index);
+        self.history_records
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| Some(index) != pending)
+            .map(|(_, record)| record.clone())
+            .collect()
+    }
+
+    // Completes the record of the line just run; `status` is None for lines that were not run
+    fn finish_history_record(&mut self, status: Option<i32>) {
+        let Some((index, started)) = self.pending_record.take() else {
+            return;
+        };
+        let record = &mut self.history_records[index];
+        if status.is_some() {
+            record.duration = started.elapsed();
+        }
+        record.status = status;
+        self.session_records.push(record.clone());
+    }
+
+    fn save_history(&mut self) {
+        if !self.interactive {
+            return;
+        }
+        let Some(path) = self.history_file() else {
+            return;
+        };
+        let file_limit = history::parse_limit(self.variables.get("HISTFILESIZE"));
+        let entries = std::mem::take(&mut self.session_history);
+        if let Err(e) = history::append(&path, &entries, file_limit) {
+            eprintln!("shell-rs: {}: {}", path, utils::io_error_message(&e));
+        }
+        let records_path = history::records_file(&path);
+        let records = std::mem::take(&mut self.session_records);
+        if let Err(e) = history::append_records(&records_path, &records, file_limit) {
+            eprintln!("shell-rs: {}: {}", records_path, utils::io_error_message(&e));
+        }
+    }
+
+    fn exit(&mut self, args: &[String]) -> CommandOutput {
+        let status = match args.first() {
+            None => self.last_status,
+            Some(arg) => match arg.parse::<i64>() {
+                Ok(status) => (status & 0xff) as i32,
+                Err(_) => {
+                    eprintln!("exit: {}: numeric argument required", arg);
+                    2
+                }
+            },
+        };
+        self.finish_history_record(Some(status));
+        self.save_history();
+        exit(status)
+    }
+
+    pub fn load_startup_files(&mut self, options: &StartupOptions) {
+        let home = utils::home_directory();
+
+        if options.login {
+            self.source_if_exists("/etc/profile");
+            if let Some(home) = &home {
+                self.source_if_exists(&format!("{}/.profile", home));
+            }
+        }
+
+        if !options.interactive {
+            return;
+        }
+        self.key_bindings
+            .read_default_init_file(&mut self.editor, &mut self.options);
+        if !options.norc {
+            match &options.rcfile {
+                Some(rcfile) => {
+                    if let Err(e) = self.source_file(rcfile) {
+                        eprintln!("shell-rs: {}: {}", rcfile, utils::io_error_message(&e));
+                    }
+                }
+                None => {
+                    if let Some(home) = &home {
+                        self.source_if_exists(&format!("{}/.shellrsrc", home));
+                    }
+                }
+            }
+        }
+        if let Some(env_file) = self.variables.get("ENV").filter(|file| !file.is_empty()) {
+            let env_file = parse_arguments(&env_file, self).join(" ");
+            self.source_if_exists(&env_file);
+        }
+    }
+
+    fn source_if_exists(&mut self, path: &str) {
+        if Path::new(path).is_file() {
+            if let Err(e) = self.source_file(path) {
+                eprintln!("shell-rs: {}: {}", path, utils::io_error_message(&e));
+            }
+        }
+    }
+
+    // Runs a file line by line; errors are reported with the file name and line number. The
+    // file is left at the first command the shell can't run, such as an `if`, rather than running
+    // every branch of it
+    fn source_file(&mut self, path: &str) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        let previous_location = self.source_location.take();
+        let mut pending = String::new();
+        let mut start_line = 0;
+
+        for (index, line) in contents.lines().enumerate() {
+            if pending.is_empty() {
+                start_line = index + 1;
+            } else {
+                pending.push('\n');
+            }
+            pending.push_str(line);
+            if validation::is_incomplete(&pending) {
+                continue;
+            }
+            let input = validation::join_continuation_lines(&std::mem::take(&mut pending));
+            self.source_location = Some((path.to_string(), start_line));
+            self.process_input(&input);
+            if validation::unsupported_syntax(&input).is_some() {
+                break;
+            }
+        }
+        if !pending.is_empty() {
+            self.source_location = Some((path.to_string(), start_line));
+            self.process_input(&validation::join_continuation_lines(&pending));
+        }
+
+        self.source_location = previous_location;
+        Ok(())
+    }
+
+    fn source(&mut self, args: &[String]) -> CommandOutput {
+        let Some(file) = args.first() else {
+            return CommandOutput::stderr(
+                "source: filename argument required\nsource: usage: source filename [arguments]\n",
+            )
+            .with_status(2);
+        };
+
+        let path = if file.contains('/') {
+            file.clone()
+        } else {
+            self.variables
+                .get("PATH")
+                .unwrap_or_default()
+                .split(':')
+                .map(|dir| Path::new(dir).join(file))
+                .find(|candidate| candidate.is_file())
+                .map_or_else(|| file.clone(), |found| found.to_string_lossy().into_owned())
+        };
+
+        self.last_status = 0;
+        match self.source_file(&path) {
+            Ok(()) => CommandOutput::default().with_status(self.last_status),
+            Err(e) => {
+                CommandOutput::stderr(format!("source: {}: {}\n", file, utils::io_error_message(&e)))
+            }
         }
     }
 
     pub fn run(&mut self) {
         loop {
             match self.read_input() {
-                Ok(input) => self.process_input(&input),
+                Ok(input) => {
+                    self.process_input(&input);
+                    self.finish_history_record(Some(self.last_status));
+                }
                 Err(true) => break, // Control-C or EOF
                 Err(false) => continue,
             }
         }
+        self.save_history();
     }
 
     fn read_input(&mut self) -> Result<String, bool> {
-        match self.editor.readline("$ ") {
+        self.run_prompt_command();
+        self.update_helper();
+        let prompt = self.render_prompt("PS1");
+        let mut initial = (String::new(), String::new());
+        loop {
+            let line = self
+                .editor
+                .readline_with_initial(&prompt, (&initial.0, &initial.1));
+            // A `bind -x` key accepted the line only to run its command; the edited line comes
+            // back from READLINE_LINE and READLINE_POINT and is offered again
+            if let Some(key_command) = self.key_bindings.take_key_command() {
+                initial = self.run_key_command(key_command);
+                continue;
+            }
+            let search = self.history_search.lock().ok().and_then(|mut pending| pending.take());
+            let Some(current) = search else {
+                return self.accept_input(line);
+            };
+            match self.search_history(&prompt, &current) {
+                Selection::Accept(line) => return self.accept_input(Ok(line)),
+                Selection::Edit(line) => initial = (line, String::new()),
+                Selection::Cancel => initial = (current, String::new()),
+            }
+        }
+    }
+
+    // Ctrl-R: the picker opens on the line below the prompt, which it erases when it closes so
+    // the editor (or the accepted line) takes its place
+    fn search_history(&mut self, prompt: &str, current: &str) -> Selection {
+        let entries = self.history_entries();
+        let records = history::match_records(&entries, &self.history_records);
+        let mut seen = HashSet::new();
+        let candidates: Vec<Candidate> = entries
+            .iter()
+            .zip(records)
+            .rev()
+            .filter(|(line, _)| seen.insert(line.as_str()))
+            .map(|(line, record)| Candidate {
+                line: line.clone(),
+                time: record.map(|record| record.time),
+                cwd: record.map(|record| record.cwd.clone()),
+            })
+            .collect();
+
+        let selection = history_search::pick(&candidates, current);
+        // The prompt and line may have wrapped, so rows are counted from their display width
+        let (columns, _) = history_search::terminal_size();
+        let rows = history_search::rows(&format!("{}{}", prompt, current), columns);
+        print!("\x1b[{}A\r\x1b[J", rows);
+        if let Selection::Accept(line) = &selection {
+            println!("{}{}", prompt, line);
+        }
+        let _ = io::stdout().flush();
+        selection
+    }
+
+    fn accept_input(&mut self, line: rustyline::Result<String>) -> Result<String, bool> {
+        match line {
             Ok(line) => {
-                self.editor.add_history_entry(&line);
+                let line = self.read_continuation_lines(line).ok_or(false)?;
+                let line = self.expand_history(line).ok_or(false)?;
+                self.record_history(&line);
+                self.command_number += 1;
                 Ok(line)
             }
             Err(ReadlineError::Interrupted | ReadlineError::Eof) => Err(true),
@@ -64,45 +505,595 @@ impl Shell {
         }
     }
 
-    fn process_input(&self, input: &str) {
-        let parsed_args = parse_arguments(input);
+    // Runs a `bind -x` command with the line being edited, returning the line split at the point
+    fn run_key_command(&mut self, key_command: KeyCommand) -> (String, String) {
+        self.variables.set("READLINE_LINE", key_command.line);
+        self.variables.set("READLINE_POINT", key_command.point.to_string());
+        self.process_input(&key_command.command);
+        let line = self.variables.get("READLINE_LINE").unwrap_or_default();
+        let point = self
+            .variables
+            .get("READLINE_POINT")
+            .and_then(|point| point.parse::<usize>().ok())
+            .unwrap_or(line.chars().count());
+        self.variables.unset_variable("READLINE_LINE");
+        self.variables.unset_variable("READLINE_POINT");
+        let split = line
+            .char_indices()
+            .nth(point)
+            .map_or(line.len(), |(index, _)| index);
+        (line[..split].to_string(), line[split..].to_string())
+    }
+
+    // Passes the highlighting palette and variables to the editor helper; `NO_COLOR`
+    // (https://no-color.org) turns colours off whatever `HIGHLIGHT_COLORS` says
+    fn update_helper(&mut self) {
+        let no_color = self.variables.get("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let palette = (!no_color).then(|| {
+            Palette::from_spec(&self.variables.get("HIGHLIGHT_COLORS").unwrap_or_default())
+        });
+        let variables = self.variables.snapshot();
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.set_palette(palette);
+            helper.set_variables(variables);
+            helper.set_fuzzy(self.options.fuzzycomplete);
+        }
+        let edit_mode = if self.options.vi { EditMode::Vi } else { EditMode::Emacs };
+        self.editor.set_edit_mode(edit_mode);
+    }
+
+    // Incomplete input continues at the `PS2` prompt; interrupting it discards the whole command
+    fn read_continuation_lines(&mut self, mut input: String) -> Option<String> {
+        while validation::is_incomplete(&input) {
+            let prompt = self.render_prompt("PS2");
+            let next = self.editor.readline(&prompt).ok()?;
+            input.push('\n');
+            input.push_str(&next);
+        }
+        Some(validation::join_continuation_lines(&input))
+    }
+
+    // Prompts are only expanded for interactive shells; the editor doesn't show them otherwise
+    fn render_prompt(&mut self, name: &str) -> String {
+        if !self.interactive {
+            return String::new();
+        }
+        let template = self.variables.get(name).unwrap_or_default();
+        let info = PromptInfo {
+            history_number: self.editor.history().len() + 1,
+            command_number: self.command_number,
+            git: &self.git_prompt,
+        };
+        prompt::render(&template, &info, self)
+    }
+
+    fn run_prompt_command(&mut self) {
+        if !self.interactive {
+            return;
+        }
+        let Some(command) = self.variables.get("PROMPT_COMMAND").filter(|command| !command.is_empty())
+        else {
+            return;
+        };
+        let status = self.last_status;
+        self.process_input(&command);
+        self.last_status = status;
+    }
+
+    fn trace(&self, command: &str) {
+        let template = self.variables.get("PS4").unwrap_or_default();
+        let info = PromptInfo {
+            history_number: self.editor.history().len(),
+            command_number: self.command_number,
+            git: &self.git_prompt,
+        };
+        eprintln!("{}{}", prompt::render(&template, &info, self), command);
+    }
+
+    // Expanded lines are echoed before they run; `:p` only prints and records them
+    fn expand_history(&mut self, line: String) -> Option<String> {
+        if !self.interactive || !line.contains(['!', '^']) {
+            return Some(line);
+        }
+
+        let entries = self.history_entries();
+        match self.history_expander.expand(&line, &entries) {
+            Ok(expansion) if !expansion.changed => Some(line),
+            Ok(expansion) => {
+                println!("{}", expansion.line);
+                if expansion.print_only {
+                    self.record_history(&expansion.line);
+                    self.finish_history_record(None);
+                    return None;
+                }
+                Some(expansion.line)
+            }
+            Err(message) => {
+                eprintln!("shell-rs: {}", message);
+                None
+            }
+        }
+    }
+
+    fn history_entries(&self) -> Vec<String> {
+        self.editor.history().iter().cloned().collect()
+    }
+
+    fn replace_history(&mut self, entries: Vec<String>) {
+        let history = self.editor.history_mut();
+        let _ = history.clear();
+        for entry in entries {
+            let _ = history.add_owned(entry);
+        }
+    }
+
+    fn history(&mut self, args: &[String]) -> CommandOutput {
+        let mut entries = self.history_entries();
+        if args.iter().any(|arg| arg.starts_with("--") && arg.len() > 2) {
+            return self.filtered_history(args, &entries);
+        }
+
+        match args.first().map(String::as_str) {
+            None => {
+                let records = history::match_records(&entries, &self.history_records);
+                CommandOutput::stdout(self.history_listing(&entries, &records, 0..entries.len()))
+            }
+            Some("-c") => {
+                self.replace_history(Vec::new());
+                self.session_history.clear();
+                self.history_records.clear();
+                self.session_records.clear();
+                self.pending_record = None;
+                CommandOutput::default()
+            }
+            Some("-d") => {
+                let Some(offset) = args.get(1) else {
+                    return CommandOutput::stderr(format!(
+                        "history: -d: option requires an argument\n{}",
+                        HISTORY_USAGE
+                    ))
+                    .with_status(2);
+                };
+                let index = match offset.parse::<i64>() {
+                    Ok(position) if position > 0 => Some(position as usize - 1),
+                    Ok(position) if position < 0 => {
+                        entries.len().checked_sub(position.unsigned_abs() as usize)
+                    }
+                    _ => None,
+                };
+                match index.filter(|&index| index < entries.len()) {
+                    Some(index) => {
+                        let records = history::match_records(&entries, &self.history_records);
+                        let record_index = records[index].and_then(|record| {
+                            self.history_records
+                                .iter()
+                                .position(|candidate| std::ptr::eq(candidate, record))
+                        });
+                        if let Some(record_index) = record_index {
+                            self.remove_history_record(record_index);
+                        }
+                        let removed = entries.remove(index);
+                        if let Some(session_index) = self
+                            .session_history
+                            .iter()
+                            .rposition(|entry| *entry == removed)
+                        {
+                            self.session_history.remove(session_index);
+                        }
+                        if let Some(session_index) = self
+                            .session_records
+                            .iter()
+                            .rposition(|record| record.line == removed)
+                        {
+                            self.session_records.remove(session_index);
+                        }
+                        self.replace_history(entries);
+                        CommandOutput::default()
+                    }
+                    None => CommandOutput::stderr(format!(
+                        "history: {}: history position out of range\n",
+                        offset
+                    )),
+                }
+            }
+            Some(option @ ("-a" | "-r" | "-w")) => {
+                let Some(path) = args.get(1).cloned().or_else(|| self.history_file()) else {
+                    return CommandOutput::default();
+                };
+                // The records file changes alongside the history file
+                let records_path = history::records_file(&path);
+                let result = match option {
+                    "-a" => {
+                        history::append(&path, &std::mem::take(&mut self.session_history), None)
+                            .and_then(|()| {
+                                let records = std::mem::take(&mut self.session_records);
+                                history::append_records(&records_path, &records, None)
+                            })
+                    }
+                    "-w" => history::write(&path, &entries).and_then(|()| {
+                        // Everything is in the files now, so exiting only adds what follows
+                        self.session_history.clear();
+                        self.session_records.clear();
+                        history::write_records(&records_path, &self.finished_history_records())
+                    }),
+                    _ => history::load(&path, None).map(|loaded| {
+                        for entry in loaded {
+                            let _ = self.editor.add_history_entry(entry);
+                        }
+                        if let Ok(records) = history::load_records(&records_path, None) {
+                            self.history_records.extend(records);
+                            self.trim_history_records();
+                        }
+                    }),
+                };
+                match result {
+                    Ok(()) => CommandOutput::default(),
+                    Err(e) => CommandOutput::stderr(format!(
+                        "history: {}: {}\n",
+                        path,
+                        utils::io_error_message(&e)
+                    )),
+                }
+            }
+            Some(option) if option.starts_with('-') && option.len() > 1 => CommandOutput::stderr(
+                format!("history: {}: invalid option\n{}", option, HISTORY_USAGE),
+            )
+            .with_status(2),
+            Some(count) => match count.parse::<usize>() {
+                Ok(count) => {
+                    let skipped = entries.len().saturating_sub(count);
+                    let records = history::match_records(&entries, &self.history_records);
+                    CommandOutput::stdout(self.history_listing(
+                        &entries,
+                        &records,
+                        skipped..entries.len(),
+                    ))
+                }
+                Err(_) => CommandOutput::stderr(format!(
+                    "history: {}: numeric argument required\n",
+                    count
+                )),
+            },
+        }
+    }
+
+    // `--cwd[=dir]`, `--failed` and `--since age` list only the entries whose records match, and
+    // a count limits the listing to the last matches
+    fn filtered_history(&self, args: &[String], entries: &[String]) -> CommandOutput {
+        let mut cwd = None;
+        let mut failed = false;
+        let mut since = None;
+        let mut count = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value)),
+                _ => (arg.as_str(), None),
+            };
+            match name {
+                "--cwd" => {
+                    let current = builtins::logical_working_directory();
+                    cwd = Some(match value {
+                        Some(directory) => {
+                            let path = Path::new(&current).join(directory);
+                            utils::normalize_logical_path(&path).to_string_lossy().into_owned()
+                        }
+                        None => current,
+                    });
+                }
+                "--failed" if value.is_none() => failed = true,
+                "--since" => {
+                    let Some(age) = value.or_else(|| args.next().map(String::as_str)) else {
+                        return CommandOutput::stderr(format!(
+                            "history: --since: option requires an argument\n{}",
+                            HISTORY_USAGE
+                        ))
+                        .with_status(2);
+                    };
+                    let Some(age) = history::parse_age(age) else {
+                        return CommandOutput::stderr(format!("history: {}: invalid age\n", age));
+                    };
+                    since = Some(utils::current_time() - age);
+                }
+                _ if count.is_none() && arg.parse::<usize>().is_ok() => count = arg.parse().ok(),
+                _ => {
+                    return CommandOutput::stderr(format!(
+                        "history: {}: invalid option\n{}",
+                        arg, HISTORY_USAGE
+                    ))
+                    .with_status(2)
+                }
+            }
+        }
+
+        let records = history::match_records(entries, &self.history_records);
+        let listed: Vec<usize> = (0..entries.len())
+            .filter(|&index| {
+                records[index].is_some_and(|record| {
+                    cwd.as_ref().map_or(true, |cwd| record.cwd == *cwd)
+                        && (!failed || record.status.is_some_and(|status| status != 0))
+                        && since.map_or(true, |since| record.time >= since)
+                })
+            })
+            .collect();
+        let skipped = listed.len().saturating_sub(count.unwrap_or(listed.len()));
+        let listed = listed[skipped..].iter().copied();
+        CommandOutput::stdout(self.history_listing(entries, &records, listed))
+    }
+
+    // Numbered `history` lines, each with its start time when HISTTIMEFORMAT is set; as in bash,
+    // entries with no recorded time show `??`
+    fn history_listing(
+        &self,
+        entries: &[String],
+        records: &[Option<&HistoryRecord>],
+        listed: impl IntoIterator<Item = usize>,
+    ) -> String {
+        let time_format = self
+            .variables
+            .get("HISTTIMEFORMAT")
+            .filter(|format| !format.is_empty());
+        listed
+            .into_iter()
+            .map(|index| {
+                let time = match (&time_format, records[index]) {
+                    (None, _) => String::new(),
+                    (Some(format), Some(record)) => utils::format_time(format, record.time),
+                    (Some(_), None) => "??".to_string(),
+                };
+                format!("{:5}  {}{}\n", index + 1, time, entries[index])
+            })
+            .collect()
+    }
+
+    fn process_input(&mut self, input: &str) {
+        if let Some(construct) = validation::unsupported_syntax(input) {
+            let message = format!("syntax error: {} are not supported\n", construct);
+            self.finish_command(CommandOutput::stderr(message).with_status(2), &None, &None);
+            return;
+        }
+        self.run_command_list(input, &[]);
+    }
+
+    // Alias replacements are run as command lists of their own, without expanding the aliases
+    // whose replacement they came from
+    fn run_command_list(&mut self, input: &str, active_aliases: &[String]) {
+        for (connector, command) in split_command_list(input) {
+            let skip = match connector {
+                Connector::Always => false,
+                Connector::And => self.last_status != 0,
+                Connector::Or => self.last_status == 0,
+            };
+            if skip {
+                continue;
+            }
+
+            let mut active = active_aliases.to_vec();
+            let expanded = self.aliases.borrow().expand(&command, &mut active);
+            if expanded == command {
+                self.execute_simple_command(&command);
+            } else {
+                self.run_command_list(&expanded, &active);
+            }
+        }
+    }
+
+    fn execute_simple_command(&mut self, input: &str) {
+        if is_conditional_command(input) {
+            let words = parse_conditional_words(input, self);
+            if self.options.xtrace {
+                let traced: Vec<&str> = words.iter().map(|word| word.text.as_str()).collect();
+                self.trace(&traced.join(" "));
+            }
+            let output = conditional::conditional(&words[1..], &mut self.variables);
+            self.finish_command(output, &None, &None);
+            return;
+        }
+
+        let mut parsed_args = parse_arguments(input, self);
+        if self.options.xtrace {
+            let traced: Vec<String> = parsed_args
+                .iter()
+                .map(|arg| utils::shell_quote(arg, Quoting::Needed))
+                .collect();
+            self.trace(&traced.join(" "));
+        }
+        let assignment_count = parsed_args
+            .iter()
+            .take_while(|arg| variables::split_assignment(arg).is_some())
+            .count();
+        let assignments: Vec<String> = parsed_args.drain(..assignment_count).collect();
+
         if parsed_args.is_empty() {
+            for assignment in &assignments {
+                if let Some((name, value)) = variables::split_assignment(assignment) {
+                    self.variables.set(name, value);
+                }
+            }
+            self.last_status = 0;
             return;
         }
 
+        // Assignments before a command only apply to that command's environment
+        let saved_environment: Vec<(String, Option<String>)> = assignments
+            .iter()
+            .filter_map(|assignment| variables::split_assignment(assignment))
+            .map(|(name, value)| {
+                let previous = env::var(name).ok();
+                env::set_var(name, value);
+                (name.to_string(), previous)
+            })
+            .collect();
+
+        self.execute_command_words(parsed_args, false);
+
+        for (name, previous) in saved_environment {
+            match previous {
+                Some(value) => env::set_var(name, value),
+                None => env::remove_var(name),
+            }
+        }
+    }
+
+    // `default_path` looks external commands up in the default path rather than PATH
+    fn execute_command_words(&mut self, parsed_args: Vec<String>, default_path: bool) {
+        // `command name args` runs name itself; aliases were only ever expanded for the first word
+        if parsed_args[0] == "command" {
+            let options = parsed_args[1..]
+                .iter()
+                .take_while(|arg| *arg == "-p" || *arg == "--")
+                .count();
+            let words = parsed_args[1 + options..].to_vec();
+            if words.first().is_some_and(|word| !word.starts_with('-')) {
+                let default_path = parsed_args[1..=options].iter().any(|arg| arg == "-p");
+                return self.execute_command_words(words, default_path);
+            }
+        }
         let command = &parsed_args[0];
         let args = parsed_args[1..].to_vec();
         
-        let (processed_args, stdout_redir, stderr_redir) = process_redirections(args);
+        let (processed_args, stdin_redir, stdout_redir, stderr_redir) =
+            process_redirections(args);
         
-        match command.as_str() {
-            "cd" => {
-                let output = builtins::change_directory(
-                    processed_args.first().map(String::as_str).unwrap_or(""),
-                );
-                output::apply_output_redirections(output, &stdout_redir, &stderr_redir);
-            }
-            "exit" => exit(0),
-            "echo" => {
-                let output = builtins::echo_input(&processed_args);
-                output::apply_output_redirections(output, &stdout_redir, &stderr_redir);
-            }
-            "pwd" => {
-                let output = builtins::print_working_directory();
-                output::apply_output_redirections(output, &stdout_redir, &stderr_redir);
+        let output = match command.as_str() {
+            "cd" => builtins::change_directory(&processed_args, &self.variables),
+            "exit" => self.exit(&processed_args),
+            "echo" => builtins::echo_input(&processed_args),
+            "pushd" => self.directory_stack.push(&processed_args),
+            "popd" => self.directory_stack.pop(&processed_args),
+            "dirs" => self.directory_stack.dirs(&processed_args),
+            "printf" => printf::printf(&processed_args, &mut self.variables),
+            "pwd" => builtins::print_working_directory(&processed_args),
+            "test" | "[" => conditional::test(command, &processed_args, &self.variables),
+            "read" => read::read(&processed_args, &mut self.variables, &stdin_redir),
+            "source" | "." => self.source(&processed_args),
+            "export" => self.variables.export(&processed_args),
+            "unset" => self.variables.unset(&processed_args),
+            "alias" => self.aliases.borrow_mut().alias(&processed_args),
+            "unalias" => self.aliases.borrow_mut().unalias(&processed_args),
+            "history" => self.history(&processed_args),
+            "set" => self.options.set(&processed_args, &self.variables),
+            "bind" => self.key_bindings.bind(&processed_args, &mut self.editor, &mut self.options),
+            "complete" => self.completion_specs.borrow_mut().complete(&processed_args),
+            "compgen" => {
+                let variables = self.variables.snapshot();
+                let sources = CompletionSources {
+                    variables: &variables,
+                    aliases: self.aliases.borrow().names().map(String::from).collect(),
+                    commands: &self.commands,
+                };
+                programmable_completion::compgen(&processed_args, &sources)
             }
-            "type" => {
-                let cmd = processed_args.first().map(String::as_str).unwrap_or("");
-                let output = builtins::handle_type_command(cmd, &self.paths);
-                output::apply_output_redirections(output, &stdout_redir, &stderr_redir);
-            }
-            _ => execute_command(
-                command,
-                &self.paths,
+            "hash" => self.commands.borrow_mut().hash(&processed_args),
+            "type" => command_lookup::type_command(
+                &processed_args,
+                &mut self.commands.borrow_mut(),
+                &self.aliases.borrow(),
+            ),
+            "command" => command_lookup::command(
                 &processed_args,
-                &stdout_redir,
-                &stderr_redir,
+                &mut self.commands.borrow_mut(),
+                &self.aliases.borrow(),
             ),
+            _ => {
+                let default_commands;
+                let commands = if default_path {
+                    default_commands = RefCell::new(command_lookup::default_path_index());
+                    &default_commands
+                } else {
+                    &self.commands
+                };
+                let output = execute_command(
+                    command,
+                    commands,
+                    &processed_args,
+                    &stdin_redir,
+                    &stdout_redir,
+                    &stderr_redir,
+                );
+                // The child already wrote to the redirection targets, so only diagnostics remain
+                let stderr_redir = if output.stderr.is_empty() { &None } else { &stderr_redir };
+                self.finish_command(output, &None, stderr_redir);
+                return;
+            }
+        };
+
+        self.finish_command(output, &stdout_redir, &stderr_redir);
+    }
+
+    fn finish_command(
+        &mut self,
+        mut output: CommandOutput,
+        stdout_redir: &Option<Redirection>,
+        stderr_redir: &Option<Redirection>,
+    ) {
+        if let Some((file, line)) = &self.source_location {
+            output.stderr = output
+                .stderr
+                .lines()
+                .map(|message| format!("{}: line {}: {}\n", file, line, message))
+                .collect();
+        }
+        self.last_status = output.status;
+        output::apply_output_redirections(output, stdout_redir, stderr_redir);
+    }
+}
+
+fn is_conditional_command(input: &str) -> bool {
+    input
+        .trim_start()
+        .strip_prefix("[[")
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+impl WordExpander for Shell {
+    fn expand_tilde(&self, prefix: &str) -> Option<String> {
+        match prefix {
+            "" => utils::home_directory(),
+            "+" => env::var("PWD").ok(),
+            "-" => env::var("OLDPWD").ok(),
+            _ => self.directory_stack.tilde_entry(prefix),
+        }
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<String> {
+        match name {
+            "$" => Some(std::process::id().to_string()),
+            "?" => Some(self.last_status.to_string()),
+            _ => self.variables.lookup(name),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sourcing_reports_lines_and_stops_at_unsupported_commands() {
+        let _lock = builtins::ENVIRONMENT_LOCK.lock().unwrap();
+        let directory = env::temp_dir().join(format!("shell-source-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let errors = directory.join("errors");
+        let rc = directory.join("rc");
+        let script = format!(
+            "SOURCED_FIRST=1\n\nno-such-command-xyz 2> {}\nif true; then\n  SOURCED_SECOND=2\nfi\nSOURCED_THIRD=3\n",
+            errors.display()
+        );
+        fs::write(&rc, script).unwrap();
+        let rc = rc.to_string_lossy().into_owned();
+
+        let mut shell = Shell::new(false);
+        shell.source_file(&rc).unwrap();
+        assert_eq!(shell.variables.get("SOURCED_FIRST").as_deref(), Some("1"));
+        assert_eq!(shell.variables.get("SOURCED_SECOND"), None);
+        assert_eq!(shell.variables.get("SOURCED_THIRD"), None);
+        assert_eq!(shell.last_status, 2);
+        assert_eq!(
+            fs::read_to_string(&errors).unwrap(),
+            format!("{}: line 3: no-such-command-xyz: command not found\n", rc)
+        );
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
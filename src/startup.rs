@@ -0,0 +1,38 @@
+use std::io::{self, IsTerminal};
+
+pub struct StartupOptions {
+    pub login: bool,
+    pub interactive: bool,
+    pub norc: bool,
+    pub rcfile: Option<String>,
+}
+
+impl StartupOptions {
+    // A login shell is requested with `-l`/`--login` or by a leading `-` in argv[0]
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let program = args.next().unwrap_or_default();
+        let mut options = StartupOptions {
+            login: program.starts_with('-'),
+            interactive: io::stdin().is_terminal(),
+            norc: false,
+            rcfile: None,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-l" | "--login" => options.login = true,
+                "-i" => options.interactive = true,
+                "--norc" => options.norc = true,
+                "--rcfile" | "--init-file" => {
+                    let file = args
+                        .next()
+                        .ok_or_else(|| format!("{}: option requires an argument", arg))?;
+                    options.rcfile = Some(file);
+                }
+                _ => return Err(format!("{}: invalid option", arg)),
+            }
+        }
+
+        Ok(options)
+    }
+}
@@ -0,0 +1,461 @@
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::autocompletion::ArgumentCompleter;
+use crate::git::Repository;
+use crate::path_completion::WordContext;
+
+pub fn default_completers() -> Vec<Box<dyn ArgumentCompleter>> {
+    vec![
+        Box::new(MakeCompleter),
+        Box::new(CargoCompleter),
+        Box::new(GitCompleter),
+    ]
+}
+
+// Targets of the makefile in the current directory, or the one given with `-f`
+pub struct MakeCompleter;
+
+impl ArgumentCompleter for MakeCompleter {
+    fn command(&self) -> &'static str {
+        "make"
+    }
+
+    fn candidates(&self, word: &WordContext) -> Vec<String> {
+        if word.text.starts_with('-')
+            || matches!(word.words.last().map(String::as_str), Some("-f" | "-C"))
+        {
+            return Vec::new();
+        }
+        let given = word
+            .words
+            .iter()
+            .position(|argument| argument == "-f")
+            .and_then(|index| word.words.get(index + 1))
+            .map(PathBuf::from);
+        let makefile = given.or_else(|| {
+            ["GNUmakefile", "makefile", "Makefile"]
+                .iter()
+                .map(PathBuf::from)
+                .find(|path| path.is_file())
+        });
+        match makefile.and_then(|path| fs::read_to_string(path).ok()) {
+            Some(contents) => make_targets(&contents),
+            None => Vec::new(),
+        }
+    }
+}
+
+// Rule lines look like `target other: prerequisites`; variable assignments, recipe lines,
+// pattern rules and special targets such as `.PHONY` are skipped
+fn make_targets(contents: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+    for line in contents.lines() {
+        if line.starts_with('\t') || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let Some((names, rest)) = line.split_once(':') else {
+            continue;
+        };
+        if rest.starts_with('=') || names.contains('=') || names.contains('$') {
+            continue;
+        }
+        targets.extend(
+            names
+                .split_whitespace()
+                .filter(|name| !name.starts_with('.') && !name.contains('%'))
+                .map(String::from),
+        );
+    }
+    targets.sort();
+    targets.dedup();
+    targets
+}
+
+// Subcommands, then `--bin`, `--example` and `-p` names from the manifest and workspace members
+pub struct CargoCompleter;
+
+const CARGO_SUBCOMMANDS: &[&str] = &[
+    "add",
+    "bench",
+    "build",
+    "check",
+    "clean",
+    "clippy",
+    "doc",
+    "fetch",
+    "fix",
+    "fmt",
+    "generate-lockfile",
+    "help",
+    "init",
+    "install",
+    "locate-project",
+    "login",
+    "metadata",
+    "new",
+    "owner",
+    "package",
+    "pkgid",
+    "publish",
+    "remove",
+    "report",
+    "run",
+    "rustc",
+    "rustdoc",
+    "search",
+    "test",
+    "tree",
+    "uninstall",
+    "update",
+    "vendor",
+    "verify-project",
+    "version",
+    "yank",
+];
+
+impl ArgumentCompleter for CargoCompleter {
+    fn command(&self) -> &'static str {
+        "cargo"
+    }
+
+    fn candidates(&self, word: &WordContext) -> Vec<String> {
+        let previous = word.words.last().map(String::as_str);
+        if word.words.len() == 1 && !word.text.starts_with('-') {
+            let mut subcommands: Vec<String> = CARGO_SUBCOMMANDS
+                .iter()
+                .map(|name| name.to_string())
+                .collect();
+            subcommands.extend(external_cargo_subcommands());
+            return subcommands;
+        }
+
+        let Some(manifest) = find_manifest() else {
+            return Vec::new();
+        };
+        let root = manifest.parent().unwrap_or(Path::new("."));
+        let members = workspace_members(root, &manifest);
+        match previous {
+            Some("--bin") => members
+                .iter()
+                .flat_map(|member| targets(member, "bin", "src/bin"))
+                .collect(),
+            Some("--example") => members
+                .iter()
+                .flat_map(|member| targets(member, "example", "examples"))
+                .collect(),
+            Some("-p" | "--package") => members
+                .iter()
+                .filter_map(|member| package_name(member))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+// `cargo-foo` executables on PATH are run as `cargo foo`
+fn external_cargo_subcommands() -> Vec<String> {
+    let paths = env::var("PATH").unwrap_or_default();
+    paths
+        .split(':')
+        .filter_map(|directory| fs::read_dir(directory).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter(|entry| {
+            entry
+                .metadata()
+                .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+        })
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_prefix("cargo-").map(String::from)
+        })
+        .collect()
+}
+
+fn find_manifest() -> Option<PathBuf> {
+    let directory = env::current_dir().ok()?;
+    directory
+        .ancestors()
+        .map(|directory| directory.join("Cargo.toml"))
+        .find(|manifest| manifest.is_file())
+}
+
+// The directories of every package in the workspace, including the root package if it has one
+fn workspace_members(root: &Path, manifest: &Path) -> Vec<PathBuf> {
+    let mut members = Vec::new();
+    let contents = fs::read_to_string(manifest).unwrap_or_default();
+    if toml_value(&contents, "package", "name").is_some() {
+        members.push(root.to_path_buf());
+    }
+    for member in toml_array(&contents, "workspace", "members") {
+        match member.strip_suffix("/*") {
+            Some(parent) => {
+                let Ok(entries) = fs::read_dir(root.join(parent)) else {
+                    continue;
+                };
+                members.extend(
+                    entries
+                        .filter_map(Result::ok)
+                        .map(|entry| entry.path())
+                        .filter(|path| path.join("Cargo.toml").is_file()),
+                );
+            }
+            None => members.push(root.join(member)),
+        }
+    }
+    members
+}
+
+fn package_name(member: &Path) -> Option<String> {
+    let contents = fs::read_to_string(member.join("Cargo.toml")).ok()?;
+    toml_value(&contents, "package", "name")
+}
+
+// `[[bin]]`/`[[example]]` names plus the files Cargo discovers on its own
+fn targets(member: &Path, table: &str, directory: &str) -> Vec<String> {
+    let contents = fs::read_to_string(member.join("Cargo.toml")).unwrap_or_default();
+    let mut names = toml_table_array_values(&contents, table, "name");
+    if table == "bin" && member.join("src/main.rs").is_file() {
+        names.extend(toml_value(&contents, "package", "name"));
+    }
+    if let Ok(entries) = fs::read_dir(member.join(directory)) {
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(stem) = name.strip_suffix(".rs") {
+                names.push(stem.to_string());
+            } else if path.join("main.rs").is_file() {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+// Just enough TOML for manifests: `key = "value"` and `key = [...]` inside `[table]` sections
+fn toml_value(contents: &str, table: &str, key: &str) -> Option<String> {
+    toml_entries(contents, &format!("[{}]", table), key)
+        .into_iter()
+        .next()
+}
+
+fn toml_table_array_values(contents: &str, table: &str, key: &str) -> Vec<String> {
+    toml_entries(contents, &format!("[[{}]]", table), key)
+}
+
+fn toml_array(contents: &str, table: &str, key: &str) -> Vec<String> {
+    let header = format!("[{}]", table);
+    let mut in_section = false;
+    let mut collecting = false;
+    let mut values = Vec::new();
+    for line in contents.lines() {
+        let mut line = line.split('#').next().unwrap_or_default().trim();
+        if !collecting {
+            if line.starts_with('[') {
+                in_section = line == header;
+                continue;
+            }
+            match line.split_once('=') {
+                Some((name, value)) if in_section && name.trim() == key => line = value,
+                _ => continue,
+            }
+        }
+        values.extend(quoted_strings(line));
+        collecting = !line.contains(']');
+    }
+    values
+}
+
+fn toml_entries(contents: &str, header: &str, key: &str) -> Vec<String> {
+    let mut in_section = false;
+    let mut values = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_section = line == header;
+            continue;
+        }
+        let Some((name, value)) = line.split_once('=') else {
+            continue;
+        };
+        if in_section && name.trim() == key {
+            values.extend(quoted_strings(value).into_iter().next());
+        }
+    }
+    values
+}
+
+fn quoted_strings(text: &str) -> Vec<String> {
+    text.split('"')
+        .skip(1)
+        .step_by(2)
+        .map(String::from)
+        .collect()
+}
+
+// Subcommands, then branches, tags and remote-tracking branches; remote names for the
+// commands that take one, and nothing (so filenames are completed) for the ones that take paths
+pub struct GitCompleter;
+
+const GIT_SUBCOMMANDS: &[&str] = &[
+    "add",
+    "am",
+    "bisect",
+    "blame",
+    "branch",
+    "checkout",
+    "cherry-pick",
+    "clean",
+    "clone",
+    "commit",
+    "config",
+    "describe",
+    "diff",
+    "fetch",
+    "format-patch",
+    "grep",
+    "init",
+    "log",
+    "merge",
+    "mv",
+    "pull",
+    "push",
+    "rebase",
+    "reflog",
+    "remote",
+    "reset",
+    "restore",
+    "revert",
+    "rm",
+    "show",
+    "stash",
+    "status",
+    "switch",
+    "tag",
+    "worktree",
+];
+
+const GIT_PATH_COMMANDS: &[&str] = &["add", "blame", "clean", "grep", "mv", "restore", "rm"];
+
+const GIT_REMOTE_COMMANDS: &[&str] = &["fetch", "pull", "push", "remote"];
+
+// Options before the subcommand whose value is the next word
+const GIT_OPTIONS_WITH_VALUE: &[&str] = &[
+    "-C",
+    "-c",
+    "--config-env",
+    "--git-dir",
+    "--namespace",
+    "--super-prefix",
+    "--work-tree",
+];
+
+// What the word being completed follows, once `git` and its global options are skipped
+#[derive(Debug, PartialEq)]
+enum GitPosition {
+    // The index of the subcommand in the words before it
+    Subcommand(usize),
+    NoSubcommand,
+    OptionValue,
+}
+
+// Also returns the directory the `-C` options lead to, relative to the current one
+fn git_position(words: &[String]) -> (GitPosition, PathBuf) {
+    let mut directory = PathBuf::new();
+    let mut index = 1;
+    while let Some(argument) = words.get(index) {
+        if !argument.starts_with('-') {
+            return (GitPosition::Subcommand(index), directory);
+        }
+        if GIT_OPTIONS_WITH_VALUE.contains(&argument.as_str()) {
+            let Some(value) = words.get(index + 1) else {
+                return (GitPosition::OptionValue, directory);
+            };
+            if argument == "-C" {
+                directory.push(value);
+            }
+            index += 1;
+        }
+        index += 1;
+    }
+    (GitPosition::NoSubcommand, directory)
+}
+
+impl ArgumentCompleter for GitCompleter {
+    fn command(&self) -> &'static str {
+        "git"
+    }
+
+    fn candidates(&self, word: &WordContext) -> Vec<String> {
+        if word.text.starts_with('-') {
+            return Vec::new();
+        }
+        let (position, directory) = git_position(&word.words);
+        let index = match position {
+            GitPosition::Subcommand(index) => index,
+            GitPosition::NoSubcommand => {
+                return GIT_SUBCOMMANDS
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect()
+            }
+            // An option's value such as `-C dir` gets filename completion
+            GitPosition::OptionValue => return Vec::new(),
+        };
+        let subcommand = word.words[index].as_str();
+        if GIT_PATH_COMMANDS.contains(&subcommand) {
+            return Vec::new();
+        }
+        let Some(repository) = env::current_dir()
+            .ok()
+            .and_then(|current| Repository::discover(&current.join(&directory)))
+        else {
+            return Vec::new();
+        };
+
+        // The first operand of `git push origin ...` is the remote
+        let operands = word.words[index + 1..]
+            .iter()
+            .filter(|argument| !argument.starts_with('-'))
+            .count();
+        if GIT_REMOTE_COMMANDS.contains(&subcommand) && operands == 0 {
+            return repository.remotes();
+        }
+        let mut names = repository.references("heads");
+        names.extend(repository.references("tags"));
+        names.extend(
+            repository
+                .references("remotes")
+                .into_iter()
+                .filter(|name| !name.ends_with("/HEAD")),
+        );
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: &str) -> (GitPosition, PathBuf) {
+        let words: Vec<String> = line.split_whitespace().map(String::from).collect();
+        git_position(&words)
+    }
+
+    #[test]
+    fn skips_git_global_options() {
+        assert_eq!(position("git").0, GitPosition::NoSubcommand);
+        assert_eq!(position("git checkout").0, GitPosition::Subcommand(1));
+        assert_eq!(position("git --no-pager log -p").0, GitPosition::Subcommand(2));
+        assert_eq!(position("git -c color.ui=never push").0, GitPosition::Subcommand(3));
+        assert_eq!(position("git --git-dir=x --work-tree w diff").0, GitPosition::Subcommand(4));
+        assert_eq!(position("git -C").0, GitPosition::OptionValue);
+        assert_eq!(position("git -c").0, GitPosition::OptionValue);
+        assert_eq!(
+            position("git -C repo -C sub status"),
+            (GitPosition::Subcommand(5), PathBuf::from("repo/sub"))
+        );
+    }
+}
@@ -1,10 +1,18 @@
-use std::path::Path;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::ptr;
 
-pub fn find_command(command: &str, paths: &[String]) -> Option<String> {
+use crate::command_index::CommandIndex;
+
+pub fn find_command(command: &str, commands: &mut CommandIndex) -> Option<String> {
     if command.contains('/') {
         return find_absolute_command(command);
     }
-    find_command_in_path(command, paths)
+    commands.lookup(command)
 }
 
 fn find_absolute_command(command: &str) -> Option<String> {
@@ -12,9 +20,180 @@ fn find_absolute_command(command: &str) -> Option<String> {
     path.exists().then(|| command.to_string())
 }
 
-fn find_command_in_path(command: &str, paths: &[String]) -> Option<String> {
-    paths.iter()
-        .map(|path_dir| Path::new(path_dir).join(command))
-        .find(|path| path.exists())
-        .map(|path| path.to_string_lossy().into_owned())
-}
\ No newline at end of file
+pub fn home_directory() -> Option<String> {
+    env::var("HOME").ok().filter(|home| !home.is_empty())
+}
+
+pub fn user_home_directory(user: &str) -> Option<String> {
+    let name = CString::new(user).ok()?;
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        return None;
+    }
+    let directory = unsafe { CStr::from_ptr((*entry).pw_dir) };
+    Some(directory.to_string_lossy().into_owned())
+}
+
+pub fn abbreviate_home(path: &str) -> String {
+    match home_directory() {
+        Some(home) if home != "/" => match path.strip_prefix(home.as_str()) {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+            _ => path.to_string(),
+        },
+        _ => path.to_string(),
+    }
+}
+
+// Resolves `.` and `..` textually, the way `cd -L` treats symlinked paths
+pub fn normalize_logical_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        normalized.push("/");
+    }
+    normalized
+}
+
+pub fn io_error_message(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.find(" (os error") {
+        Some(index) => message[..index].to_string(),
+        None => message,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Quoting {
+    // Always single quotes, as alias definitions and the `:q` history modifier use
+    Always,
+    // Single quotes only when some character would otherwise be interpreted
+    Needed,
+    // Special characters escaped with backslashes, like `printf %q`
+    Backslash,
+}
+
+// Quotes a string so the shell reads it back as a single word; apart from `Always`, control
+// characters are written with `$'...'` escapes
+pub fn shell_quote(text: &str, quoting: Quoting) -> String {
+    let is_safe = |c: char| c.is_alphanumeric() || "_/.,:=+@%^-".contains(c);
+    let single_quoted = || format!("'{}'", text.replace('\'', "'\\''"));
+    if quoting == Quoting::Always {
+        return single_quoted();
+    }
+    if text.is_empty() {
+        return "''".to_string();
+    }
+    if text.chars().all(is_safe) {
+        return text.to_string();
+    }
+
+    if text.chars().any(|c| c.is_control()) {
+        let mut quoted = String::from("$'");
+        for c in text.chars() {
+            match c {
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                '\r' => quoted.push_str("\\r"),
+                '\x1b' => quoted.push_str("\\E"),
+                '\'' | '\\' => {
+                    quoted.push('\\');
+                    quoted.push(c);
+                }
+                c if c.is_control() => quoted.push_str(&format!("\\{:03o}", c as u32)),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('\'');
+        return quoted;
+    }
+
+    if quoting == Quoting::Needed {
+        return single_quoted();
+    }
+    let mut quoted = String::new();
+    for c in text.chars() {
+        if !is_safe(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+// Runs the script in a child shell and returns its output without trailing newlines; its errors
+// are discarded so they don't garble the line being edited
+pub fn run_in_child_shell(script: &str, environment: &[(&str, String)]) -> String {
+    let Ok(shell) = env::current_exe() else {
+        return String::new();
+    };
+    let Ok(mut child) = Command::new(shell)
+        .envs(environment.iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return String::new();
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{}", script);
+    }
+    match child.wait_with_output() {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches('\n')
+            .to_string(),
+        Err(_) => String::new(),
+    }
+}
+
+pub fn current_time() -> i64 {
+    unsafe { libc::time(ptr::null_mut()) }
+}
+
+// Formats seconds since the epoch in local time with strftime(3)
+pub fn format_time(format: &str, time: i64) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+    let mut buffer = [0u8; 256];
+    let length = unsafe {
+        let mut local: libc::tm = mem::zeroed();
+        libc::localtime_r(&time, &mut local);
+        libc::strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            format.as_ptr(),
+            &local,
+        )
+    };
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_in_each_style() {
+        assert_eq!(shell_quote("plain", Quoting::Always), "'plain'");
+        assert_eq!(shell_quote("it's", Quoting::Always), r"'it'\''s'");
+        assert_eq!(shell_quote("plain", Quoting::Needed), "plain");
+        assert_eq!(shell_quote("a b", Quoting::Needed), "'a b'");
+        assert_eq!(shell_quote("", Quoting::Needed), "''");
+        assert_eq!(shell_quote("a\x01", Quoting::Needed), r"$'a\001'");
+        assert_eq!(shell_quote("plain", Quoting::Backslash), "plain");
+        assert_eq!(shell_quote("", Quoting::Backslash), "''");
+        assert_eq!(shell_quote("a b", Quoting::Backslash), r"a\ b");
+        assert_eq!(shell_quote("a\nb'", Quoting::Backslash), r"$'a\nb\''");
+        assert_eq!(shell_quote("a\x01", Quoting::Backslash), r"$'a\001'");
+    }
+}
@@ -0,0 +1,321 @@
+// Whether more lines are needed before `input` can run: an open quote, a trailing `\`, `|`, `&&`
+// or `||`, an unclosed `if`/`for`/`while`/`until`/`case`/`{`/`(`, or a here-doc without its end
+pub fn is_incomplete(input: &str) -> bool {
+    scan(input).0
+}
+
+// The first construct in `input` that the shell can't run yet, such as "pipelines"; running
+// such input one line at a time would run every branch and body, so it is rejected instead
+pub fn unsupported_syntax(input: &str) -> Option<&'static str> {
+    scan(input).1
+}
+
+fn scan(input: &str) -> (bool, Option<&'static str>) {
+    let chars: Vec<char> = input.chars().collect();
+    let mut blocks: Vec<&str> = Vec::new();
+    let mut here_docs: Vec<(String, bool)> = Vec::new();
+    let mut quote = None;
+    let mut command_start = true;
+    let mut pending_operator = false;
+    // Inside `[[ ]]`, `|` and parentheses belong to the expression
+    let mut conditional = false;
+    let mut unsupported = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some(q) = quote {
+            match c {
+                '\\' if q != '\'' => i += 1,
+                _ if c == q => quote = None,
+                _ => {}
+            }
+            i += 1;
+            continue;
+        }
+
+        let next = chars.get(i + 1).copied();
+        match c {
+            '\\' if next.is_none() => return (true, unsupported),
+            '\\' => {
+                i += 2;
+                command_start = false;
+                pending_operator = false;
+                continue;
+            }
+            '\'' | '"' | '`' => {
+                quote = Some(c);
+                command_start = false;
+                pending_operator = false;
+            }
+            '#' if i == 0 || chars[i - 1].is_whitespace() => {
+                while i + 1 < chars.len() && chars[i + 1] != '\n' {
+                    i += 1;
+                }
+            }
+            '\n' => {
+                i += 1;
+                for (delimiter, strip_tabs) in here_docs.drain(..) {
+                    loop {
+                        if i >= chars.len() {
+                            return (true, unsupported);
+                        }
+                        let end = chars[i..]
+                            .iter()
+                            .position(|&c| c == '\n')
+                            .map_or(chars.len(), |offset| i + offset);
+                        let line: String = chars[i..end].iter().collect();
+                        i = end + 1;
+                        let line = if strip_tabs {
+                            line.trim_start_matches('\t')
+                        } else {
+                            &line
+                        };
+                        if line == delimiter {
+                            break;
+                        }
+                    }
+                }
+                command_start = true;
+                continue;
+            }
+            ';' | '&' | '|' => {
+                let doubled = next == Some(c);
+                if c == '|' && !doubled && !conditional {
+                    unsupported.get_or_insert("pipelines");
+                }
+                pending_operator = c != ';' && (doubled || c == '|');
+                command_start = true;
+                if doubled {
+                    i += 1;
+                }
+            }
+            '(' | ')' if conditional => {}
+            '(' => {
+                unsupported.get_or_insert("subshells");
+                blocks.push(")");
+                command_start = true;
+            }
+            ')' => {
+                // A `)` ending a case pattern closes nothing
+                if blocks.last() == Some(&")") {
+                    blocks.pop();
+                }
+                command_start = true;
+            }
+            '$' if next == Some('(') => {
+                unsupported.get_or_insert("command substitutions");
+                blocks.push(")");
+                command_start = true;
+                pending_operator = false;
+                i += 1;
+            }
+            // A `<<<` here-string needs no more lines
+            '<' if next == Some('<') && chars.get(i + 2) == Some(&'<') => {
+                unsupported.get_or_insert("here-strings");
+                i += 3;
+                continue;
+            }
+            '<' if next == Some('<') => {
+                unsupported.get_or_insert("here-documents");
+                i += 2;
+                let strip_tabs = chars.get(i) == Some(&'-');
+                if strip_tabs {
+                    i += 1;
+                }
+                while chars.get(i).is_some_and(|c| *c == ' ' || *c == '\t') {
+                    i += 1;
+                }
+                let end = word_end(&chars, i);
+                let delimiter: String = chars[i..end]
+                    .iter()
+                    .filter(|c| !"'\"\\".contains(**c))
+                    .collect();
+                here_docs.push((delimiter, strip_tabs));
+                i = end;
+                command_start = false;
+                continue;
+            }
+            '<' | '>' => {}
+            _ if c.is_whitespace() => {}
+            _ => {
+                let end = plain_word_end(&chars, i);
+                let word: String = chars[i..end].iter().collect();
+                let delimited = chars
+                    .get(end)
+                    .map_or(true, |c| c.is_whitespace() || ";&|)<>".contains(*c));
+                pending_operator = false;
+                if word == "]]" && delimited {
+                    conditional = false;
+                }
+                if command_start && delimited {
+                    match word.as_str() {
+                        "if" | "for" | "while" | "until" | "select" | "case" | "{" => {
+                            unsupported.get_or_insert("compound commands");
+                            blocks.push(match word.as_str() {
+                                "if" => "fi",
+                                "case" => "esac",
+                                "{" => "}",
+                                _ => "done",
+                            });
+                        }
+                        "[[" => conditional = true,
+                        "fi" | "done" | "esac" | "}" if blocks.last() == Some(&word.as_str()) => {
+                            blocks.pop();
+                        }
+                        _ => {}
+                    }
+                    command_start = matches!(
+                        word.as_str(),
+                        "if" | "then" | "elif" | "else" | "while" | "until" | "do" | "{" | "!"
+                    );
+                } else {
+                    command_start = false;
+                }
+                i = end;
+                continue;
+            }
+        }
+        i += 1;
+    }
+
+    let incomplete =
+        quote.is_some() || pending_operator || !blocks.is_empty() || !here_docs.is_empty();
+    (incomplete, unsupported)
+}
+
+// Removes backslash-newline pairs, which join lines everywhere except inside single quotes
+pub fn join_continuation_lines(input: &str) -> String {
+    let mut result = String::new();
+    let mut chars = input.chars();
+    let mut in_single = false;
+    let mut in_double = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !in_single => {
+                match chars.next() {
+                    Some('\n') => {}
+                    Some(escaped) => {
+                        result.push(c);
+                        result.push(escaped);
+                    }
+                    None => result.push(c),
+                }
+                continue;
+            }
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            _ => {}
+        }
+        result.push(c);
+    }
+
+    result
+}
+
+// Runs to the next unquoted blank or operator
+fn word_end(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    let mut quote = None;
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c.is_whitespace() || ";&|()<>".contains(c) => break,
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '\\' => i += 1,
+            None => {}
+        }
+        i += 1;
+    }
+    i.min(chars.len())
+}
+
+// Stops at quotes, escapes and `$(` too, so they are scanned by the caller
+fn plain_word_end(chars: &[char], start: usize) -> usize {
+    let mut i = start;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace()
+            || ";&|()<>'\"`\\".contains(c)
+            || (c == '$' && chars.get(i + 1) == Some(&'('))
+        {
+            break;
+        }
+        i += 1;
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_open_quotes_and_trailing_operators() {
+        assert!(!is_incomplete("echo hello"));
+        assert!(is_incomplete("echo 'hello"));
+        assert!(is_incomplete("echo \"hello"));
+        assert!(!is_incomplete("echo \"it's\""));
+        assert!(is_incomplete("echo hello \\"));
+        assert!(!is_incomplete("echo hello \\\\"));
+        assert!(is_incomplete("ls |"));
+        assert!(is_incomplete("true &&"));
+        assert!(is_incomplete("false ||"));
+        assert!(!is_incomplete("sleep 1 &"));
+        assert!(!is_incomplete("echo hi # it's a comment"));
+    }
+
+    #[test]
+    fn detects_unclosed_blocks() {
+        assert!(is_incomplete("if true; then"));
+        assert!(!is_incomplete("if true; then echo yes; fi"));
+        assert!(is_incomplete("for x in a b; do echo $x;"));
+        assert!(!is_incomplete("for x in a b; do echo $x; done"));
+        assert!(is_incomplete("while true; do"));
+        assert!(!is_incomplete("case $x in a) echo a;; esac"));
+        assert!(is_incomplete("case $x in a) echo a;;"));
+        assert!(is_incomplete("{ echo"));
+        assert!(!is_incomplete("{ echo; }"));
+        assert!(is_incomplete("(cd /tmp"));
+        assert!(is_incomplete("echo $(pwd"));
+        // Keywords only count in command position
+        assert!(!is_incomplete("echo if for"));
+    }
+
+    #[test]
+    fn names_constructs_that_cannot_run() {
+        assert_eq!(unsupported_syntax("echo a && echo b; echo c || true"), None);
+        assert_eq!(unsupported_syntax("echo 'a | b' \"(c)\" if"), None);
+        assert_eq!(unsupported_syntax("[[ $x =~ a|b && ( -n $y ) ]]"), None);
+        assert_eq!(unsupported_syntax("echo a |\ncat"), Some("pipelines"));
+        assert_eq!(
+            unsupported_syntax("if true; then\n echo yes\nelse\n echo no\nfi"),
+            Some("compound commands")
+        );
+        assert_eq!(unsupported_syntax("{ echo; }"), Some("compound commands"));
+        assert_eq!(unsupported_syntax("(cd /tmp)"), Some("subshells"));
+        assert_eq!(
+            unsupported_syntax("echo $(pwd)"),
+            Some("command substitutions")
+        );
+        assert_eq!(
+            unsupported_syntax("cat <<EOF\nhello\nEOF"),
+            Some("here-documents")
+        );
+        assert_eq!(unsupported_syntax("cat <<< word"), Some("here-strings"));
+    }
+
+    #[test]
+    fn waits_for_here_document_delimiters() {
+        assert!(is_incomplete("cat <<EOF"));
+        assert!(is_incomplete("cat <<EOF\nhello"));
+        assert!(!is_incomplete("cat <<EOF\nhello\nEOF"));
+        assert!(!is_incomplete("cat <<'EOF'\nhello\nEOF"));
+        assert!(is_incomplete("cat <<EOF\nhello\n\tEOF"));
+        assert!(!is_incomplete("cat <<-EOF\nhello\n\tEOF"));
+        assert!(!is_incomplete("cat <<< word"));
+    }
+}
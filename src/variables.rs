@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::env;
+
+use crate::output::CommandOutput;
+use crate::utils::{self, Quoting};
+
+enum Value {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+// Shell variables; exported ones live in the process environment so children see them
+#[derive(Default)]
+pub struct Variables {
+    values: HashMap<String, Value>,
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        match self.values.get(name) {
+            Some(Value::Scalar(value)) => Some(value.clone()),
+            Some(Value::Array(values)) => values.first().cloned(),
+            None => env::var(name).ok(),
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        let value = value.into();
+        if env::var_os(name).is_some() {
+            env::set_var(name, value);
+        } else {
+            self.values.insert(name.to_string(), Value::Scalar(value));
+        }
+    }
+
+    pub fn set_array(&mut self, name: &str, values: Vec<String>) {
+        env::remove_var(name);
+        self.values.insert(name.to_string(), Value::Array(values));
+    }
+
+    // Resolves the inside of a `${...}` expansion: `name`, `name[i]`, `name[@]` and `#name`
+    pub fn lookup(&self, expression: &str) -> Option<String> {
+        if let Some(name) = expression.strip_prefix('#').filter(|name| !name.is_empty()) {
+            return Some(self.length(name).to_string());
+        }
+
+        let Some((name, subscript)) = split_subscript(expression) else {
+            return self.get(expression);
+        };
+        match (self.values.get(name), subscript) {
+            (Some(Value::Array(values)), "@" | "*") => Some(values.join(" ")),
+            (Some(Value::Array(values)), index) => values.get(index.parse::<usize>().ok()?).cloned(),
+            (_, "@" | "*" | "0") => self.get(name),
+            _ => None,
+        }
+    }
+
+    // Every shell and environment variable as `name=value`, the way `set` prints them
+    pub fn listing(&self) -> String {
+        let mut lines: Vec<String> = env::vars()
+            .filter(|(name, _)| !self.values.contains_key(name))
+            .map(|(name, value)| {
+                format!("{}={}", name, utils::shell_quote(&value, Quoting::Backslash))
+            })
+            .collect();
+        for (name, value) in &self.values {
+            lines.push(match value {
+                Value::Scalar(value) => {
+                    format!("{}={}", name, utils::shell_quote(value, Quoting::Backslash))
+                }
+                Value::Array(values) => {
+                    let elements: Vec<String> = values
+                        .iter()
+                        .enumerate()
+                        .map(|(index, value)| {
+                            format!("[{}]={}", index, utils::shell_quote(value, Quoting::Backslash))
+                        })
+                        .collect();
+                    format!("{}=({})", name, elements.join(" "))
+                }
+            });
+        }
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    // Every variable's value the way `get` returns it
+    pub fn snapshot(&self) -> HashMap<String, String> {
+        let mut snapshot: HashMap<String, String> = env::vars().collect();
+        for name in self.values.keys() {
+            if let Some(value) = self.get(name) {
+                snapshot.insert(name.clone(), value);
+            }
+        }
+        snapshot
+    }
+
+    pub fn unset_variable(&mut self, name: &str) {
+        self.values.remove(name);
+        env::remove_var(name);
+    }
+
+    fn export_variable(&mut self, name: &str, value: Option<String>) {
+        if let Some(value) = value.or_else(|| self.get(name)) {
+            self.values.remove(name);
+            env::set_var(name, value);
+        }
+    }
+
+    pub fn export(&mut self, args: &[String]) -> CommandOutput {
+        let names: Vec<&String> = args.iter().filter(|arg| *arg != "-p").collect();
+        if names.is_empty() {
+            let mut exported: Vec<(String, String)> = env::vars().collect();
+            exported.sort();
+            let listing: String = exported
+                .iter()
+                .map(|(name, value)| {
+                    let value = utils::shell_quote(value, Quoting::Backslash);
+                    format!("declare -x {}={}\n", name, value)
+                })
+                .collect();
+            return CommandOutput::stdout(listing);
+        }
+
+        let mut output = CommandOutput::default();
+        for arg in names {
+            let (name, value) = match split_assignment(arg) {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if is_valid_name(name) {
+                self.export_variable(name, value);
+            } else {
+                output.stderr += &format!("export: `{}': not a valid identifier\n", arg);
+                output.status = 1;
+            }
+        }
+        output
+    }
+
+    pub fn unset(&mut self, args: &[String]) -> CommandOutput {
+        let mut output = CommandOutput::default();
+        for name in args.iter().filter(|arg| *arg != "-v") {
+            if is_valid_name(name) {
+                self.unset_variable(name);
+            } else {
+                output.stderr += &format!("unset: `{}': not a valid identifier\n", name);
+                output.status = 1;
+            }
+        }
+        output
+    }
+
+    fn length(&self, expression: &str) -> usize {
+        match split_subscript(expression) {
+            Some((name, "@" | "*")) => match self.values.get(name) {
+                Some(Value::Array(values)) => values.len(),
+                _ => usize::from(self.get(name).is_some()),
+            },
+            _ => self.lookup(expression).map_or(0, |value| value.chars().count()),
+        }
+    }
+}
+
+fn split_subscript(expression: &str) -> Option<(&str, &str)> {
+    let (name, rest) = expression.split_once('[')?;
+    Some((name, rest.strip_suffix(']')?))
+}
+
+// Splits a `NAME=value` assignment word
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    word.split_once('=').filter(|(name, _)| is_valid_name(name))
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}